@@ -1,5 +1,11 @@
 // admin.js
 
+// Escape text for interpolation into innerHTML; audit entries and usernames
+// carry user-controlled strings.
+const esc = (v) => String(v ?? '').replace(/[&<>"']/g, c => ({
+    '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;'
+}[c]));
+
 const AdminPanel = {
     async show() {
         const panel = document.getElementById('admin-panel');
@@ -33,6 +39,22 @@ const AdminPanel = {
                     <input type="number" id="inp-max-users" min="1" max="1000">
                     <button class="btn btn-primary" id="btn-save-settings">Save</button>
                 </div>
+                <div class="settings-row">
+                    <label>Announce</label>
+                    <input type="text" id="inp-announce" maxlength="400" placeholder="Maintenance in 10 minutes">
+                    <button class="btn btn-primary" id="btn-announce">Send</button>
+                </div>
+                <div class="settings-row">
+                    <label>Maintenance</label>
+                    <input type="text" id="inp-maint-message" maxlength="400" placeholder="Down for maintenance — back soon">
+                    <input type="text" id="inp-maint-drain" size="6" placeholder="drain in (10m)">
+                    <button class="btn btn-danger" id="btn-maintenance">Enable</button>
+                </div>
+                <div class="settings-row">
+                    <label>Backups</label>
+                    <span id="backup-latest" style="color:var(--text-tertiary)">—</span>
+                    <button class="btn btn-primary" id="btn-backup">Backup now</button>
+                </div>
             </div>
 
             <div class="admin-section">
@@ -45,7 +67,7 @@ const AdminPanel = {
                                 <th>First seen</th>
                                 <th>Last seen</th>
                                 <th>Session</th>
-                                <th>Admin</th>
+                                <th>Roles</th>
                                 <th>Actions</th>
                             </tr>
                         </thead>
@@ -55,14 +77,37 @@ const AdminPanel = {
                     </table>
                 </div>
             </div>
+
+            <div class="admin-section">
+                <h3>Audit log</h3>
+                <div class="table">
+                    <table>
+                        <thead>
+                            <tr>
+                                <th>Time</th>
+                                <th>Actor</th>
+                                <th>Action</th>
+                                <th>Target</th>
+                                <th>Outcome</th>
+                            </tr>
+                        </thead>
+                        <tbody id="audit-tbody">
+                            <tr><td colspan="5" style="text-align:center;color:var(--text-tertiary)">Loading...</td></tr>
+                        </tbody>
+                    </table>
+                </div>
+            </div>
         </div>`;
     },
 
     async _load() {
         try {
-            const [settings, usersData] = await Promise.all([
+            const [settings, maint, backups, usersData, auditData] = await Promise.all([
                 fetch('/api/admin/settings').then(r => r.json()),
-                fetch('/api/admin/users').then(r => r.json())
+                fetch('/api/admin/maintenance').then(r => r.json()),
+                fetch('/api/admin/backups').then(r => r.json()),
+                fetch('/api/admin/users').then(r => r.json()),
+                fetch('/api/admin/audit?limit=50').then(r => r.json())
             ]);
 
             document.getElementById('s-total').textContent  = settings.totalUsers;
@@ -80,12 +125,81 @@ const AdminPanel = {
                 await this._load();
             };
 
+            document.getElementById('btn-announce').onclick = async () => {
+                const input = document.getElementById('inp-announce');
+                const message = input.value.trim();
+                if (!message || !confirm(`Send to all users (web and IRC)?\n\n${message}`)) return;
+                const res = await fetch('/api/admin/announce', {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({ message })
+                });
+                if (res.ok) input.value = '';
+                await this._load();
+            };
+
+            const maintBtn = document.getElementById('btn-maintenance');
+            document.getElementById('inp-maint-message').value = maint.enabled ? maint.message : '';
+            maintBtn.textContent = maint.enabled ? 'Disable' : 'Enable';
+            maintBtn.className = maint.enabled ? 'btn btn-primary' : 'btn btn-danger';
+            maintBtn.onclick = async () => {
+                const enabled = !maint.enabled;
+                const message = document.getElementById('inp-maint-message').value.trim();
+                const drainIn = document.getElementById('inp-maint-drain').value.trim();
+                if (enabled && !confirm(drainIn
+                    ? `Refuse new sessions and detach everyone in ${drainIn}?`
+                    : 'Refuse new sessions? Connected users stay connected.')) return;
+                const res = await fetch('/api/admin/maintenance', {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({ enabled, message, drainIn })
+                });
+                if (!res.ok) alert((await res.json()).error || res.status);
+                await this._load();
+            };
+
+            const latest = (backups.backups || [])[0];
+            const latestEl = document.getElementById('backup-latest');
+            latestEl.innerHTML = '';
+            if (latest) {
+                const a = document.createElement('a');
+                a.href = `/api/admin/backups/${encodeURIComponent(latest.name)}`;
+                a.textContent = `${new Date(latest.createdAt).toLocaleString()} (${Math.ceil(latest.size / 1024)} KiB)`;
+                latestEl.appendChild(a);
+            } else {
+                latestEl.textContent = 'none yet';
+            }
+            document.getElementById('btn-backup').onclick = async () => {
+                const res = await fetch('/api/admin/backups', { method: 'POST' });
+                if (!res.ok) alert((await res.json()).error || res.status);
+                await this._load();
+            };
+
             this._renderUsers(usersData.users);
+            this._renderAudit(auditData.entries);
         } catch (e) {
             console.error('Admin load failed', e);
         }
     },
 
+    _renderAudit(entries) {
+        const tbody = document.getElementById('audit-tbody');
+        if (!entries || !entries.length) {
+            tbody.innerHTML = '<tr><td colspan="5" style="text-align:center;color:var(--text-tertiary)">No entries</td></tr>';
+            return;
+        }
+
+        tbody.innerHTML = entries.map(e => `
+            <tr>
+                <td>${new Date(e.ts).toLocaleString()}</td>
+                <td>${esc(e.actor)}</td>
+                <td>${esc(e.action)}</td>
+                <td>${e.target ? esc(e.target) : '—'}</td>
+                <td>${esc(e.outcome)}</td>
+            </tr>
+        `).join('');
+    },
+
     _renderUsers(users) {
         const tbody = document.getElementById('users-tbody');
         if (!users || !users.length) {
@@ -95,24 +209,32 @@ const AdminPanel = {
 
         tbody.innerHTML = users.map(u => `
             <tr>
-                <td>${u.username}</td>
+                <td>${esc(u.username)}</td>
                 <td>${new Date(u.first_seen).toLocaleDateString()}</td>
                 <td>${new Date(u.last_seen).toLocaleDateString()}</td>
-                <td>${u.active_session ? '<span style="color:var(--success)">● Active</span>' : '—'}</td>
-                <td>${u.is_admin ? '✓' : ''}</td>
+                <td>${u.suspended ? '<span style="color:var(--error)">Suspended</span>' : u.active_session ? '<span style="color:var(--success)">● Active</span>' : '—'}</td>
+                <td>${esc((u.roles || []).join(', '))}</td>
                 <td>
                     <div class="actions-cell">
-                        ${u.active_session ? `<button class="btn btn-kick" data-u="${u.username}">Kick</button>` : ''}
-                        <button class="btn btn-clear" data-u="${u.username}">Clear</button>
-                        <button class="btn btn-danger btn-del" data-u="${u.username}">Delete</button>
+                        ${u.active_session ? `<button class="btn btn-watch" data-u="${esc(u.username)}">Watch</button>` : ''}
+                        ${u.active_session ? `<button class="btn btn-kick" data-u="${esc(u.username)}">Kick</button>` : ''}
+                        <button class="btn btn-clear" data-u="${esc(u.username)}">Clear</button>
+                        ${u.suspended
+                            ? `<button class="btn btn-unsuspend" data-u="${esc(u.username)}">Unsuspend</button>`
+                            : `<button class="btn btn-suspend" data-u="${esc(u.username)}">Suspend</button>`}
+                        <button class="btn btn-danger btn-del" data-u="${esc(u.username)}">Delete</button>
                     </div>
                 </td>
             </tr>
         `).join('');
 
+        tbody.querySelectorAll('.btn-watch').forEach(btn => {
+            btn.onclick = () => window.open(`/?spectate=${encodeURIComponent(btn.dataset.u)}`, '_blank');
+        });
+
         tbody.querySelectorAll('.btn-kick').forEach(btn => {
             btn.onclick = async () => {
-                await fetch(`/api/admin/users/${btn.dataset.u}/kick`, { method: 'POST' });
+                await fetch(`/api/admin/users/${encodeURIComponent(btn.dataset.u)}/kick`, { method: 'POST' });
                 await this._load();
             };
         });
@@ -120,7 +242,28 @@ const AdminPanel = {
         tbody.querySelectorAll('.btn-clear').forEach(btn => {
             btn.onclick = async () => {
                 if (!confirm(`Clear IRC session and config for "${btn.dataset.u}"?`)) return;
-                await fetch(`/api/admin/users/${btn.dataset.u}/clear`, { method: 'POST' });
+                await fetch(`/api/admin/users/${encodeURIComponent(btn.dataset.u)}/clear`, { method: 'POST' });
+                await this._load();
+            };
+        });
+
+        tbody.querySelectorAll('.btn-suspend').forEach(btn => {
+            btn.onclick = async () => {
+                const reason = prompt(`Suspend "${btn.dataset.u}" — reason:`);
+                if (!reason) return;
+                const duration = prompt('Duration (e.g. 7d, 12h) — leave empty for indefinite:') || null;
+                await fetch(`/api/admin/users/${encodeURIComponent(btn.dataset.u)}/suspend`, {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({ reason, duration })
+                });
+                await this._load();
+            };
+        });
+
+        tbody.querySelectorAll('.btn-unsuspend').forEach(btn => {
+            btn.onclick = async () => {
+                await fetch(`/api/admin/users/${encodeURIComponent(btn.dataset.u)}/unsuspend`, { method: 'POST' });
                 await this._load();
             };
         });
@@ -128,7 +271,7 @@ const AdminPanel = {
         tbody.querySelectorAll('.btn-del').forEach(btn => {
             btn.onclick = async () => {
                 if (!confirm(`Remove "${btn.dataset.u}" from user list?`)) return;
-                await fetch(`/api/admin/users/${btn.dataset.u}`, { method: 'DELETE' });
+                await fetch(`/api/admin/users/${encodeURIComponent(btn.dataset.u)}`, { method: 'DELETE' });
                 await this._load();
             };
         });
@@ -10,10 +10,28 @@ const app = {
     _reconnectTimer: null,
     _lastHidden: 0,
     _expectingReconnect: false,
+    _kicked: false,
+    // Window title set by the terminal program, and bells/notifications
+    // that arrived while the tab was in the background
+    _title: 'IRC',
+    _unread: 0,
+    // Admin read-only view of another user's terminal (?spectate=<username>)
+    _spectate: new URLSearchParams(location.search).get('spectate'),
+    // Guest view of someone's terminal through a share link (?share=<token>)
+    _share: new URLSearchParams(location.search).get('share'),
 
     async init() {
         try {
             const res = await fetch('/api/me');
+            if (res.status === 403) {
+                const body = await res.json().catch(() => ({}));
+                const until = body.until ? ` until ${new Date(body.until).toLocaleString()}` : '';
+                const el = document.createElement('div');
+                el.style.cssText = 'color:#f00;padding:40px;font-family:monospace;background:#000;height:100vh';
+                el.textContent = `Account suspended${until}: ${body.reason || ''}`;
+                document.body.replaceChildren(el);
+                return;
+            }
             if (!res.ok) {
                 document.body.innerHTML = `
                     <div style="color:#f00;padding:40px;font-family:monospace;background:#000;height:100vh">
@@ -29,13 +47,24 @@ const app = {
 
         document.getElementById('user-info').textContent = `(${this.user.username})`;
 
-        if (this.user.isAdmin) {
+        if ((this.user.permissions || []).includes('view_users')) {
             const link = document.getElementById('admin-link');
             link.style.display = 'inline';
             link.addEventListener('click', () => AdminPanel.show());
         }
 
-        document.getElementById('btn-reset').addEventListener('click', () => this.resetSession());
+        const btnReset = document.getElementById('btn-reset');
+        const btnShare = document.getElementById('btn-share');
+        const btnAccount = document.getElementById('btn-account');
+        if (this._spectate || this._share) {
+            btnReset.style.display = 'none';
+            btnShare.style.display = 'none';
+            btnAccount.style.display = 'none';
+        }
+        btnReset.addEventListener('click', () => this.resetSession());
+        btnShare.addEventListener('click', () => this.shareSession());
+        btnAccount.addEventListener('click', () => this.accountMenu());
+        if (!this._spectate && !this._share) this._registerIrcHandler();
 
         // Mobile-only buttons
         const isTouchDevice = 'ontouchstart' in window || navigator.maxTouchPoints > 0;
@@ -52,6 +81,7 @@ const app = {
             }
         }
 
+        this._subscribeEvents();
         this._initTerm();
         this._setupReconnect();
         await this.loadTerminal();
@@ -108,6 +138,7 @@ const app = {
 
         // Send input to ttyd — protocol: '0' + data
         this._term.onData(data => {
+            if (this._spectate) return; // read-only; share scope is enforced server-side
             if (!this._ws || this._ws.readyState !== WebSocket.OPEN) return;
             this._ws.send('0' + data);
         });
@@ -203,6 +234,7 @@ const app = {
                 this._lastHidden = Date.now();
                 this._expectingReconnect = true;
             } else {
+                this._clearUnread();
                 if (!this._ws || this._ws.readyState !== WebSocket.OPEN) {
                     this._scheduleReconnect(300);
                 }
@@ -214,6 +246,7 @@ const app = {
         });
 
         window.addEventListener('focus', () => {
+            this._clearUnread();
             if (!this._ws || this._ws.readyState !== WebSocket.OPEN) {
                 this._scheduleReconnect(300);
             }
@@ -227,14 +260,27 @@ const app = {
     },
 
     _scheduleReconnect(ms) {
+        if (this._kicked) return;
         clearTimeout(this._reconnectTimer);
         this._reconnectTimer = setTimeout(() => this._connect(), ms);
     },
 
     async loadTerminal() {
+        if (this._spectate || this._share) {
+            this._connect();
+            return;
+        }
         this.updateStatus('connecting', 'Starting terminal...');
         try {
             const res = await fetch('/api/terminal');
+            if (res.status === 503) {
+                // Maintenance mode — keep retrying quietly until it is lifted
+                const body = await res.json().catch(() => ({}));
+                this.updateStatus('disconnected', body.error || 'Down for maintenance');
+                clearTimeout(this._reconnectTimer);
+                this._reconnectTimer = setTimeout(() => this.loadTerminal(), 30000);
+                return;
+            }
             if (!res.ok) throw new Error(`${res.status}`);
         } catch {
             this.updateStatus('disconnected', 'Failed to start terminal');
@@ -252,7 +298,12 @@ const app = {
         }
 
         const proto = location.protocol === 'https:' ? 'wss' : 'ws';
-        const ws = new WebSocket(`${proto}://${location.host}/terminal/ws`, ['tty']);
+        const path = this._spectate
+            ? `/api/admin/users/${encodeURIComponent(this._spectate)}/spectate/ws`
+            : this._share
+                ? `/share/ws?token=${encodeURIComponent(this._share)}`
+                : '/terminal/ws';
+        const ws = new WebSocket(`${proto}://${location.host}${path}`, ['tty']);
         ws.binaryType = 'arraybuffer';
         this._ws = ws;
         this._reconnecting = false;
@@ -261,11 +312,18 @@ const app = {
             log('ws open, sending auth');
             this._expectingReconnect = false;
             ws.send(JSON.stringify({ AuthToken: '' }));
-            this.updateStatus('connected', 'Connected');
+            this.updateStatus('connected',
+                this._spectate ? `Watching ${this._spectate} (read-only)`
+                    : this._share ? 'Shared terminal' : 'Connected');
             this._onResize();
         };
 
         ws.onmessage = (e) => {
+            // Text frames are structured side-channel messages from the server
+            if (typeof e.data === 'string') {
+                try { this._onSideMessage(JSON.parse(e.data)); } catch (err) { log('bad side message', err); }
+                return;
+            }
             if (!(e.data instanceof ArrayBuffer)) return;
             const buf = new Uint8Array(e.data);
             if (buf.length === 0) return;
@@ -280,7 +338,21 @@ const app = {
             // 49 = title, 50 = prefs — ignore
         };
 
-        ws.onclose = () => {
+        ws.onclose = (e) => {
+            // 4000 = this device was disconnected on purpose — stay down.
+            if (e.code === 4000) {
+                this._kicked = true;
+                this.updateStatus('disconnected', 'Disconnected from another device — reload to reconnect');
+                return;
+            }
+            // 4001 = session detached for maintenance — irssi keeps running,
+            // come back once the server accepts sessions again.
+            if (e.code === 4001) {
+                this.updateStatus('disconnected', 'Down for maintenance — will reconnect');
+                clearTimeout(this._reconnectTimer);
+                this._reconnectTimer = setTimeout(() => this.loadTerminal(), 30000);
+                return;
+            }
             if (!this._reconnecting && !this._expectingReconnect) {
                 this.updateStatus('disconnected', 'Disconnected');
             }
@@ -295,6 +367,281 @@ const app = {
         };
     },
 
+    _subscribeEvents() {
+        // EventSource reconnects on its own after network drops
+        const es = new EventSource('/api/events');
+        es.addEventListener('announcement', (e) => {
+            const a = JSON.parse(e.data);
+            this._showBanner('announce', `📢 ${a.message} — ${a.author}`, true);
+        });
+        es.addEventListener('maintenance', (e) => {
+            const m = JSON.parse(e.data);
+            let text = null;
+            if (m.enabled && m.drainAt) {
+                text = `🔧 ${m.message} — sessions will be detached at ${new Date(m.drainAt).toLocaleTimeString()}`;
+            } else if (m.enabled) {
+                text = `🔧 ${m.message}`;
+            }
+            this._showBanner('maintenance', text);
+        });
+    },
+
+    _onSideMessage(msg) {
+        log('side message:', msg);
+        if (msg.type === 'spectate') {
+            this._showBanner('spectate', msg.watching ? 'An administrator is viewing your terminal (read-only).' : null);
+        }
+        // Picked out of the terminal output by the server (OSC 0/2, 9/777, 52 and BEL)
+        if (msg.type === 'title') {
+            this._title = msg.title || 'IRC';
+            this._renderTitle();
+        }
+        if (msg.type === 'bell' || msg.type === 'notify') {
+            if (document.hidden || !document.hasFocus()) {
+                this._unread++;
+                this._renderTitle();
+            }
+        }
+        if (msg.type === 'notify' && document.hidden && 'Notification' in window) {
+            const show = () => new Notification(msg.title || this._title, { body: msg.body, tag: 'irssi-v5' });
+            if (Notification.permission === 'granted') show();
+            else if (Notification.permission === 'default') {
+                Notification.requestPermission().then((p) => p === 'granted' && show());
+            }
+        }
+        if (msg.type === 'clipboard') {
+            // Browsers may refuse without a user gesture; then offer a click to copy
+            navigator.clipboard.writeText(msg.text).catch(() => {
+                this._showBanner('clipboard', '📋 The terminal copied some text — click to copy it to your clipboard', true);
+                const el = document.getElementById('banner-clipboard');
+                el.onclick = () => {
+                    navigator.clipboard.writeText(msg.text).catch((e) => log('copy failed', e));
+                    el.remove();
+                };
+            });
+        }
+    },
+
+    _renderTitle() {
+        document.title = this._unread ? `(${this._unread}) ${this._title}` : this._title;
+    },
+
+    _clearUnread() {
+        if (!this._unread) return;
+        this._unread = 0;
+        this._renderTitle();
+    },
+
+    _showBanner(id, text, dismissable = false) {
+        let el = document.getElementById(`banner-${id}`);
+        if (!text) {
+            if (el) el.remove();
+            return;
+        }
+        if (!el) {
+            el = document.createElement('div');
+            el.id = `banner-${id}`;
+            el.className = 'banner';
+            document.body.appendChild(el);
+        }
+        el.textContent = text;
+        el.title = dismissable ? 'Click to dismiss' : '';
+        el.onclick = dismissable ? () => el.remove() : null;
+    },
+
+    async shareSession() {
+        const scope = confirm('Let the other person type too?\n\nOK = read-write, Cancel = read-only') ? 'write' : 'read';
+        const ttl = prompt('Link valid for (e.g. 30m, 2h):', '1h');
+        if (!ttl) return;
+        try {
+            const res = await fetch('/api/session/share', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ scope, ttl })
+            });
+            const body = await res.json();
+            if (!res.ok) throw new Error(body.error || res.status);
+            prompt(`Share link (${scope}, expires ${new Date(body.expiresAt).toLocaleTimeString()}):`, body.url);
+        } catch (e) {
+            alert(`Could not create share link: ${e.message}`);
+        }
+    },
+
+    // Offer to handle irc:// and ircs:// links (/open). Browsers ask the
+    // user, so only offer once per browser.
+    _registerIrcHandler() {
+        if (!navigator.registerProtocolHandler || localStorage.getItem('ircHandlerOffered')) return;
+        localStorage.setItem('ircHandlerOffered', '1');
+        for (const scheme of ['irc', 'ircs']) {
+            try {
+                navigator.registerProtocolHandler(scheme, `${location.origin}/open?url=%s`);
+            } catch (e) {
+                console.warn(`registerProtocolHandler(${scheme}):`, e);
+            }
+        }
+    },
+
+    async accountMenu() {
+        const choice = prompt(
+            'Your data:\n\n1 = download a copy\n2 = download a copy including passwords\n3 = delete my account\n4 = app passwords for IRC clients\n5 = SSH keys\n6 = terminal client (irssi, weechat, senpai)\n7 = irssi scripts and themes', '1');
+        if (choice === '1' || choice === '2') {
+            location.href = `/api/me/export${choice === '2' ? '?secrets=true' : ''}`;
+        } else if (choice === '3') {
+            await this.deleteAccount();
+        } else if (choice === '4') {
+            await this.appPasswords();
+        } else if (choice === '5') {
+            await this.sshKeys();
+        } else if (choice === '6') {
+            await this.chooseClient();
+        } else if (choice === '7') {
+            await this.catalog();
+        }
+    },
+
+    async catalog() {
+        try {
+            const res = await fetch('/api/catalog');
+            if (!res.ok) throw new Error(res.status);
+            const { scripts, themes } = await res.json();
+            const line = (e) => `${e.enabled ? '[x]' : '[ ]'} ${e.name}${e.description ? ` — ${e.description}` : ''}`;
+            const section = (title, list) => `${title}:\n${list.length ? list.map(line).join('\n') : '(none)'}`;
+            const answer = prompt(
+                `${section('Scripts', scripts)}\n\n${section('Themes', themes)}\n\n` +
+                'Type "enable <name>" or "disable <name>" (themes: pick with /set theme <name>):');
+            const m = answer && answer.trim().match(/^(enable|disable)\s+(\S+)$/i);
+            if (!m) return;
+            const name = m[2];
+            const kind = scripts.some(e => e.name === name) ? 'scripts'
+                : themes.some(e => e.name === name) ? 'themes' : null;
+            if (!kind) throw new Error(`${name} is not in the catalog`);
+            const change = await fetch(`/api/catalog/${kind}/${encodeURIComponent(name)}`,
+                { method: m[1].toLowerCase() === 'enable' ? 'PUT' : 'DELETE' });
+            if (!change.ok) throw new Error((await change.json().catch(() => ({}))).error || change.status);
+        } catch (e) {
+            alert(`Scripts and themes: ${e.message}`);
+        }
+    },
+
+    async chooseClient() {
+        try {
+            const res = await fetch('/api/me/preferences');
+            if (!res.ok) throw new Error(res.status);
+            const prefs = await res.json();
+            const offered = prefs.clients.filter(c => c.installed).map(c => c.name);
+            const answer = prompt(
+                `Terminal client (now ${prefs.client}). Switching restarts your session.\n\n` +
+                `Available: ${offered.join(', ')}`, prefs.client);
+            if (!answer || answer.trim() === prefs.client) return;
+            const put = await fetch('/api/me/preferences', {
+                method: 'PUT',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ client: answer.trim().toLowerCase() })
+            });
+            if (!put.ok) throw new Error((await put.json().catch(() => ({}))).error || put.status);
+            // The server ended the old session; connect to the new client
+            this.updateStatus('connecting', 'Restarting...');
+            if (this._ws) { this._ws.onclose = null; this._ws.close(); this._ws = null; }
+            this._term.clear();
+            setTimeout(() => this.loadTerminal(), 1500);
+        } catch (e) {
+            alert(`Terminal client: ${e.message}`);
+        }
+    },
+
+    async sshKeys() {
+        try {
+            const res = await fetch('/api/ssh-keys');
+            if (!res.ok) throw new Error(res.status);
+            const { sshKeys, ssh } = await res.json();
+            const when = (ms) => ms ? new Date(ms).toLocaleString() : 'never';
+            const list = sshKeys.map((k, i) =>
+                `${i + 1}. ${k.name} (${k.fingerprint}) — last used ${when(k.last_used_at)}`);
+            const answer = prompt(
+                (ssh ? 'SSH logins with these keys open your irssi session.' : 'The SSH gateway is not enabled on this server.') +
+                '\n\n' + (list.length ? list.join('\n') : '(none yet)') +
+                '\n\nPaste a public key (e.g. ~/.ssh/id_ed25519.pub) to add it, or "remove <number>":');
+            if (!answer) return;
+            const remove = answer.match(/^remove\s+(\d+)$/i);
+            if (remove) {
+                const k = sshKeys[Number(remove[1]) - 1];
+                if (!k || !confirm(`Remove "${k.name}"?`)) return;
+                const del = await fetch(`/api/ssh-keys/${k.id}`, { method: 'DELETE' });
+                if (!del.ok) throw new Error((await del.json().catch(() => ({}))).error || del.status);
+                return;
+            }
+            const added = await fetch('/api/ssh-keys', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ key: answer })
+            });
+            const body = await added.json().catch(() => ({}));
+            if (!added.ok) throw new Error(body.error || added.status);
+            alert(`Added "${body.name}" (${body.fingerprint}).`);
+        } catch (e) {
+            alert(`SSH keys: ${e.message}`);
+        }
+    },
+
+    async appPasswords() {
+        try {
+            const res = await fetch('/api/app-passwords');
+            if (!res.ok) throw new Error(res.status);
+            const { appPasswords } = await res.json();
+            const when = (ms) => ms ? new Date(ms).toLocaleString() : 'never';
+            const list = appPasswords.map((p, i) =>
+                `${i + 1}. ${p.name} — last used ${when(p.last_used_at)}${p.last_used_ip ? ` from ${p.last_used_ip}` : ''}`);
+            const answer = prompt(
+                `App passwords let IRC clients log in to your bouncer as ${this.user.username}.\n\n` +
+                (list.length ? list.join('\n') : '(none yet)') +
+                '\n\nType a name to create one, or "revoke <number>":');
+            if (!answer) return;
+            const revoke = answer.match(/^revoke\s+(\d+)$/i);
+            if (revoke) {
+                const p = appPasswords[Number(revoke[1]) - 1];
+                if (!p || !confirm(`Revoke "${p.name}"? Clients using it are disconnected.`)) return;
+                const del = await fetch(`/api/app-passwords/${p.id}`, { method: 'DELETE' });
+                if (!del.ok) throw new Error((await del.json().catch(() => ({}))).error || del.status);
+                return;
+            }
+            const made = await fetch('/api/app-passwords', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ name: answer })
+            });
+            const body = await made.json().catch(() => ({}));
+            if (!made.ok) throw new Error(body.error || made.status);
+            prompt(`Password for "${body.name}" (shown only now). Log in as ${body.username}:`, body.password);
+        } catch (e) {
+            alert(`App passwords: ${e.message}`);
+        }
+    },
+
+    async deleteAccount() {
+        try {
+            const res = await fetch('/api/me/delete', { method: 'POST' });
+            const { token } = await res.json();
+            if (!res.ok || !token) throw new Error(res.status);
+            const typed = prompt(
+                `This permanently deletes your IRC session, settings, logs and bouncer account.\n\nType your username (${this.user.username}) to confirm:`);
+            if (typed !== this.user.username) return;
+            const del = await fetch('/api/me', {
+                method: 'DELETE',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ token })
+            });
+            if (!del.ok) throw new Error((await del.json().catch(() => ({}))).error || del.status);
+            this._kicked = true;
+            if (this._ws) { this._ws.onclose = null; this._ws.close(); this._ws = null; }
+            document.body.innerHTML = `
+                <div style="color:#ccc;padding:40px;font-family:monospace;background:#000;height:100vh">
+                    Your account has been deleted.
+                </div>`;
+        } catch (e) {
+            alert(`Could not delete account: ${e.message}`);
+        }
+    },
+
     async resetSession() {
         if (!confirm('Reset your IRC session?')) return;
         try {
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -10,6 +10,8 @@ use serde::Deserialize;
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
+use crate::roles::{Permission, Role};
+
 static USERNAME_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[^a-z0-9-]").unwrap());
 
 /// Verified identity derived from a Cloudflare Access JWT.
@@ -17,16 +19,48 @@ static USERNAME_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[^a-z0-9-]").unwrap(
 pub struct User {
     pub username: String, // sanitized email prefix
     pub email: String,
-    pub is_admin: bool,
+    /// IdP groups from the token, used for group→role mapping
+    pub groups: Vec<String>,
+    /// Effective roles (stored grants ∪ group-mapped), filled in by AppState
+    pub roles: BTreeSet<Role>,
 }
 
-/// CF JWT claims we care about
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.roles.contains(&Role::Admin)
+    }
+
+    pub fn can(&self, perm: Permission) -> bool {
+        self.roles.iter().any(|r| r.grants(perm))
+    }
+
+    pub fn permissions(&self) -> Vec<Permission> {
+        let mut perms: Vec<Permission> = Vec::new();
+        for p in self.roles.iter().flat_map(|r| r.permissions()) {
+            if !perms.contains(p) {
+                perms.push(*p);
+            }
+        }
+        perms
+    }
+}
+
+/// CF JWT claims we care about. aud/iss/exp are checked by `Validation`
+/// during decode and are not kept here.
 #[derive(Debug, Deserialize)]
 struct CfClaims {
     email: String,
-    aud: Vec<String>,
-    iss: String,
-    exp: u64,
+    /// Present when the Access application forwards IdP groups
+    #[serde(default)]
+    groups: Vec<String>,
+    #[serde(default)]
+    custom: Option<CustomClaims>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CustomClaims {
+    #[serde(default)]
+    groups: Vec<String>,
 }
 
 /// A single JWK key from Cloudflare's JWKS endpoint
@@ -52,7 +86,6 @@ pub struct Validator {
     issuer: String,
     jwks_url: String,
     cache_ttl: Duration,
-    admin_users: HashSet<String>,
     cache: RwLock<Option<JwksCache>>,
 }
 
@@ -61,14 +94,12 @@ impl Validator {
         team_domain: &str,
         aud: &str,
         cache_ttl: Duration,
-        admin_users: HashSet<String>,
     ) -> Arc<Self> {
         Arc::new(Self {
             aud: aud.to_string(),
             issuer: format!("https://{}", team_domain),
             jwks_url: format!("https://{}/cdn-cgi/access/certs", team_domain),
             cache_ttl,
-            admin_users,
             cache: RwLock::new(None),
         })
     }
@@ -94,11 +125,14 @@ impl Validator {
         let token_data = decode::<CfClaims>(token, &decoding_key, &validation)
             .context("JWT validation failed")?;
 
-        let email = token_data.claims.email;
-        let username = email_to_username(&email);
-        let is_admin = self.admin_users.contains(&username);
+        let claims = token_data.claims;
+        let username = email_to_username(&claims.email);
+        let mut groups = claims.groups;
+        if let Some(custom) = claims.custom {
+            groups.extend(custom.groups);
+        }
 
-        Ok(User { username, email, is_admin })
+        Ok(User { username, email: claims.email, groups, roles: BTreeSet::new() })
     }
 
     async fn get_keys(&self) -> Result<Vec<Jwk>> {
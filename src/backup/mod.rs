@@ -0,0 +1,330 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::ConnectOptions;
+
+use crate::store::{now_ms, Store};
+
+/// Bumped when the archive layout changes; restore refuses newer formats.
+pub const FORMAT: u32 = 1;
+
+const PREFIX: &str = "irssi-v5-backup-";
+const SUFFIX: &str = ".tar.gz";
+
+/// `manifest.json`, the first entry of every archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub format: u32,
+    pub app_version: String,
+    pub sqlite_version: String,
+    pub created_at: i64,
+    /// Whether soju.db is included
+    pub soju_db: bool,
+    /// Users with a session directory in the archive
+    pub users: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupFile {
+    pub name: String,
+    pub size: u64,
+    pub created_at: i64,
+}
+
+/// What `restore` put in place and what it moved aside.
+#[derive(Debug)]
+pub struct Restored {
+    pub manifest: Manifest,
+    pub moved_aside: Vec<PathBuf>,
+    /// soju.db from the archive, when it was not restored over the live one
+    pub soju_db_left: Option<PathBuf>,
+}
+
+/// Write a backup archive into `out_dir`: a `VACUUM INTO` snapshot of the
+/// app DB (and of the soju DB when given), every session directory, and a
+/// manifest. The archive only appears under its final name once complete.
+pub async fn create(
+    store: &Store,
+    sessions_dir: &Path,
+    soju_db: Option<&Path>,
+    out_dir: &Path,
+) -> Result<(PathBuf, Manifest)> {
+    std::fs::create_dir_all(out_dir).with_context(|| format!("create {}", out_dir.display()))?;
+    let created_at = now_ms();
+    let staging = out_dir.join(format!(".staging-{}", created_at));
+    std::fs::create_dir_all(&staging)?;
+
+    let result = async {
+        store.snapshot(&staging.join("app.db")).await.context("snapshot app.db")?;
+        if let Some(src) = soju_db {
+            snapshot_file(src, &staging.join("soju.db"))
+                .await
+                .with_context(|| format!("snapshot {}", src.display()))?;
+        }
+
+        let manifest = Manifest {
+            format: FORMAT,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            sqlite_version: store.sqlite_version().await?,
+            created_at,
+            soju_db: soju_db.is_some(),
+            users: session_users(sessions_dir)?,
+        };
+        std::fs::write(staging.join("manifest.json"), serde_json::to_vec_pretty(&manifest)?)?;
+
+        let name = format!("{}{}{}", PREFIX, timestamp(created_at), SUFFIX);
+        let dest = out_dir.join(&name);
+        let partial = out_dir.join(format!("{}.partial", name));
+        let (staging, sessions_dir, archive) = (staging.clone(), sessions_dir.to_path_buf(), partial.clone());
+        tokio::task::spawn_blocking(move || write_archive(&archive, &staging, &sessions_dir)).await??;
+        std::fs::rename(&partial, &dest)?;
+        Ok((dest, manifest))
+    }
+    .await;
+
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+/// Consistent copy of another SQLite database, opened read-only.
+async fn snapshot_file(src: &Path, dest: &Path) -> Result<()> {
+    let mut conn = SqliteConnectOptions::new().filename(src).read_only(true).connect().await?;
+    let dest = dest.to_str().context("non-UTF-8 snapshot path")?;
+    sqlx::query("VACUUM INTO ?").bind(dest).execute(&mut conn).await?;
+    Ok(())
+}
+
+fn session_users(sessions_dir: &Path) -> Result<Vec<String>> {
+    let mut users = Vec::new();
+    if sessions_dir.exists() {
+        for entry in std::fs::read_dir(sessions_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                users.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+    users.sort();
+    Ok(users)
+}
+
+fn write_archive(dest: &Path, staging: &Path, sessions_dir: &Path) -> Result<()> {
+    let gz = GzEncoder::new(File::create(dest)?, Compression::default());
+    let mut tar = tar::Builder::new(gz);
+    for name in ["manifest.json", "app.db", "soju.db"] {
+        let path = staging.join(name);
+        if path.exists() {
+            tar.append_path_with_name(&path, name)?;
+        }
+    }
+    if sessions_dir.exists() {
+        tar.append_dir("sessions", sessions_dir)?;
+        append_tree(&mut tar, sessions_dir, Path::new("sessions"))?;
+    }
+    tar.into_inner()?.finish()?.sync_all()?;
+    Ok(())
+}
+
+/// Regular files and directories only: dtach and control sockets, FIFOs
+/// and symlinks are skipped.
+fn append_tree<W: std::io::Write>(tar: &mut tar::Builder<W>, dir: &Path, name: &Path) -> Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<std::io::Result<_>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let (path, ft) = (entry.path(), entry.file_type()?);
+        let name = name.join(entry.file_name());
+        if ft.is_dir() {
+            tar.append_dir(&name, &path)?;
+            append_tree(tar, &path, &name)?;
+        } else if ft.is_file() {
+            tar.append_path_with_name(&path, &name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace app.db and the sessions directory with the archive's contents.
+/// The current ones are renamed to `*.pre-restore-<ms>`, not deleted. The
+/// server must be stopped. soju.db is only written over `soju_db` when
+/// given; otherwise it is left next to app.db for a manual restore.
+pub fn restore(archive: &Path, data_dir: &Path, sessions_dir: &Path, soju_db: Option<&Path>) -> Result<Restored> {
+    let stamp = now_ms();
+    let staging = data_dir.join(format!(".restore-{}", stamp));
+    std::fs::create_dir_all(&staging)?;
+    let result = restore_from(archive, &staging, stamp, data_dir, sessions_dir, soju_db);
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+fn restore_from(
+    archive: &Path,
+    staging: &Path,
+    stamp: i64,
+    data_dir: &Path,
+    sessions_dir: &Path,
+    soju_db: Option<&Path>,
+) -> Result<Restored> {
+    let file = File::open(archive).with_context(|| format!("open {}", archive.display()))?;
+    // unpack() refuses entries that would land outside `staging`
+    tar::Archive::new(GzDecoder::new(file)).unpack(staging).context("unpack archive")?;
+
+    let manifest: Manifest = serde_json::from_slice(
+        &std::fs::read(staging.join("manifest.json")).context("archive has no manifest.json")?,
+    )
+    .context("invalid manifest.json")?;
+    if manifest.format > FORMAT {
+        bail!("backup format {} is newer than this build supports ({})", manifest.format, FORMAT);
+    }
+    if !staging.join("app.db").exists() {
+        bail!("archive has no app.db");
+    }
+
+    let mut moved_aside = Vec::new();
+    let mut set_aside = |path: &Path| -> Result<()> {
+        if path.exists() {
+            let aside = PathBuf::from(format!("{}.pre-restore-{}", path.display(), stamp));
+            std::fs::rename(path, &aside)?;
+            moved_aside.push(aside);
+        }
+        Ok(())
+    };
+
+    let app_db = data_dir.join("app.db");
+    set_aside(&app_db)?;
+    // Keep the WAL with its database so the moved-aside copy stays complete
+    for suffix in ["-wal", "-shm"] {
+        let side = PathBuf::from(format!("{}{}", app_db.display(), suffix));
+        if side.exists() {
+            std::fs::rename(&side, format!("{}.pre-restore-{}{}", app_db.display(), stamp, suffix))?;
+        }
+    }
+    set_aside(sessions_dir)?;
+
+    std::fs::rename(staging.join("app.db"), &app_db)?;
+    match staging.join("sessions") {
+        s if s.exists() => std::fs::rename(s, sessions_dir)?,
+        _ => std::fs::create_dir_all(sessions_dir)?,
+    }
+
+    let mut soju_db_left = None;
+    if manifest.soju_db {
+        match soju_db {
+            Some(dest) => {
+                set_aside(dest)?;
+                std::fs::copy(staging.join("soju.db"), dest)?;
+            }
+            None => {
+                let left = data_dir.join(format!("soju.db.restored-{}", stamp));
+                std::fs::rename(staging.join("soju.db"), &left)?;
+                soju_db_left = Some(left);
+            }
+        }
+    }
+
+    Ok(Restored { manifest, moved_aside, soju_db_left })
+}
+
+/// Backups in `dir`, newest first.
+pub fn list(dir: &Path) -> Result<Vec<BackupFile>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !is_backup_name(&name) {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        let created_at = meta
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        files.push(BackupFile { name, size: meta.len(), created_at });
+    }
+    files.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(files)
+}
+
+/// Delete all but the newest `keep` backups. Returns how many were removed.
+pub fn prune(dir: &Path, keep: usize) -> Result<usize> {
+    let old = list(dir)?.into_iter().skip(keep).collect::<Vec<_>>();
+    for f in &old {
+        std::fs::remove_file(dir.join(&f.name))?;
+    }
+    Ok(old.len())
+}
+
+/// Time since the newest backup in `dir`, if there is one.
+pub fn newest_age(dir: &Path) -> Option<Duration> {
+    let newest = list(dir).ok()?.into_iter().next()?;
+    Some(Duration::from_millis((now_ms() - newest.created_at).max(0) as u64))
+}
+
+/// Names produced by `create`, so user input can never reach other files.
+pub fn is_backup_name(name: &str) -> bool {
+    name.strip_prefix(PREFIX)
+        .and_then(|rest| rest.strip_suffix(SUFFIX))
+        .is_some_and(|ts| !ts.is_empty() && ts.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+}
+
+/// Unix ms as a sortable, filename-safe UTC timestamp: 20240131T235959123Z.
+fn timestamp(ms: i64) -> String {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_millis(ms as u64);
+    let s = humantime::format_rfc3339_millis(t).to_string();
+    s.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backup_restore_roundtrip() {
+        let root = std::env::temp_dir().join(format!("irssi-v5-backup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (data, sessions, out) = (root.join("data"), root.join("data/sessions"), root.join("out"));
+        std::fs::create_dir_all(sessions.join("alice/.irssi")).unwrap();
+        std::fs::write(sessions.join("alice/.irssi/config"), "servers = ( );").unwrap();
+        std::fs::write(sessions.join("alice/soju_password"), "hunter2").unwrap();
+        let _sock = std::os::unix::net::UnixListener::bind(sessions.join("alice/control.sock")).unwrap();
+
+        let store = Store::new(data.join("app.db").to_str().unwrap()).await.unwrap();
+        store.touch("alice").await.unwrap();
+        let (archive, manifest) = create(&store, &sessions, None, &out).await.unwrap();
+        assert_eq!(manifest.users, ["alice"]);
+        assert!(is_backup_name(archive.file_name().unwrap().to_str().unwrap()));
+        assert_eq!(list(&out).unwrap().len(), 1);
+
+        // Diverge, then restore
+        store.touch("bob").await.unwrap();
+        std::fs::write(sessions.join("alice/soju_password"), "changed").unwrap();
+        drop(store);
+
+        let restored = restore(&archive, &data, &sessions, None).unwrap();
+        assert_eq!(restored.moved_aside.len(), 2);
+        assert_eq!(std::fs::read_to_string(sessions.join("alice/soju_password")).unwrap(), "hunter2");
+        assert!(!sessions.join("alice/control.sock").exists());
+
+        let store = Store::new(data.join("app.db").to_str().unwrap()).await.unwrap();
+        let users: Vec<String> = store.list_users().await.unwrap().into_iter().map(|u| u.username).collect();
+        assert_eq!(users, ["alice"]);
+
+        assert!(!is_backup_name("../app.db"));
+        assert!(!is_backup_name("irssi-v5-backup-x/../y.tar.gz"));
+        assert_eq!(prune(&out, 0).unwrap(), 1);
+        drop(store);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
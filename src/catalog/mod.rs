@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Name of the script irssi-v5 installs itself (see `session`); the
+/// catalog cannot shadow it.
+const RESERVED: &str = "irssi-v5-control";
+
+/// File in an irssi home listing what the catalog installed there, as
+/// path (relative to the home) → SHA-256 of the contents written, so a
+/// file the user wrote or changed is never replaced or removed.
+const MANIFEST: &str = ".catalog-installed.json";
+
+/// What the catalog holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Script,
+    Theme,
+}
+
+impl Kind {
+    /// Subdirectory of the catalog, also used in URLs.
+    pub fn dir(self) -> &'static str {
+        match self {
+            Kind::Script => "scripts",
+            Kind::Theme => "themes",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Kind::Script => "pl",
+            Kind::Theme => "theme",
+        }
+    }
+
+    /// Where an installed entry lives in an irssi home: scripts in
+    /// `scripts/`, loaded at startup through a link in `scripts/autorun/`,
+    /// themes next to the config.
+    fn installed_path(self, home: &Path, name: &str) -> PathBuf {
+        home.join(self.relative_path(name))
+    }
+
+    fn relative_path(self, name: &str) -> String {
+        match self {
+            Kind::Script => format!("scripts/{}.pl", name),
+            Kind::Theme => format!("{}.theme", name),
+        }
+    }
+}
+
+impl std::str::FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "scripts" => Ok(Kind::Script),
+            "themes" => Ok(Kind::Theme),
+            _ => bail!("unknown catalog section {:?} (expected scripts or themes)", s),
+        }
+    }
+}
+
+/// One catalog entry and whether the user has it.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub description: Option<String>,
+    pub enabled: bool,
+}
+
+/// Vetted irssi scripts and themes an admin put in `<dir>/scripts/*.pl`
+/// and `<dir>/themes/*.theme`, for users to install without fetching
+/// anything from the internet.
+pub struct Catalog {
+    dir: PathBuf,
+}
+
+impl Catalog {
+    pub fn new(dir: PathBuf) -> Self {
+        Catalog { dir }
+    }
+
+    fn source(&self, kind: Kind, name: &str) -> Result<PathBuf> {
+        if name.is_empty()
+            || name == RESERVED
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("no {} {:?} in the catalog", kind.dir(), name);
+        }
+        let path = self.dir.join(kind.dir()).join(format!("{}.{}", name, kind.extension()));
+        if !path.is_file() {
+            bail!("no {} {:?} in the catalog", kind.dir(), name);
+        }
+        Ok(path)
+    }
+
+    pub fn contains(&self, kind: Kind, name: &str) -> bool {
+        self.source(kind, name).is_ok()
+    }
+
+    /// Entries of `kind`, sorted by name, marked enabled when installed in
+    /// the irssi home `home`. A missing catalog directory is empty.
+    pub fn list(&self, kind: Kind, home: &Path) -> Result<Vec<Entry>> {
+        let dir = self.dir.join(kind.dir());
+        let read = match std::fs::read_dir(&dir) {
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+        };
+        let mut entries = Vec::new();
+        for file in read.flatten() {
+            let path = file.path();
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+            if path.extension().and_then(|e| e.to_str()) != Some(kind.extension()) || self.source(kind, name).is_err() {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path).unwrap_or_default();
+            entries.push(Entry {
+                name: name.to_string(),
+                description: describe(kind, &text),
+                enabled: self.enabled(kind, name, home),
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn enabled(&self, kind: Kind, name: &str, home: &Path) -> bool {
+        let installed = read_manifest(home).is_ok_and(|m| m.contains_key(&kind.relative_path(name)));
+        match kind {
+            Kind::Script => installed && home.join("scripts/autorun").join(format!("{}.pl", name)).exists(),
+            Kind::Theme => installed && kind.installed_path(home, name).exists(),
+        }
+    }
+
+    /// The file an entry would be installed as, if one is there that the
+    /// catalog did not write or that was changed since. `install` and
+    /// `remove` leave such a file alone and fail.
+    pub fn conflict(&self, kind: Kind, name: &str, home: &Path) -> Result<Option<PathBuf>> {
+        let target = kind.installed_path(home, name);
+        let data = match std::fs::read(&target) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read {}", target.display())),
+        };
+        let manifest = read_manifest(home)?;
+        let ours = manifest.get(&kind.relative_path(name)) == Some(&digest(&data));
+        Ok((!ours).then_some(target))
+    }
+
+    /// Copy an entry into the irssi home, replacing an older copy the
+    /// catalog installed, and for scripts link it into `autorun`.
+    pub fn install(&self, kind: Kind, name: &str, home: &Path) -> Result<()> {
+        let source = self.source(kind, name)?;
+        if let Some(path) = self.conflict(kind, name, home)? {
+            bail!("{} was not installed from the catalog", path.display());
+        }
+        let data = std::fs::read(&source).with_context(|| format!("read {}", source.display()))?;
+        let target = kind.installed_path(home, name);
+        let parent = target.parent().expect("installed path has a parent");
+        std::fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        let tmp = target.with_extension("tmp");
+        std::fs::write(&tmp, &data).with_context(|| format!("write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &target).with_context(|| format!("install {}", target.display()))?;
+        let mut manifest = read_manifest(home)?;
+        manifest.insert(kind.relative_path(name), digest(&data));
+        write_manifest(home, &manifest)?;
+        if kind == Kind::Script {
+            let autorun = home.join("scripts/autorun");
+            std::fs::create_dir_all(&autorun)?;
+            let link = autorun.join(format!("{}.pl", name));
+            if std::fs::symlink_metadata(&link).is_err() {
+                std::os::unix::fs::symlink(format!("../{}.pl", name), &link)
+                    .with_context(|| format!("link {}", link.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove an entry the catalog installed, and for scripts its
+    /// `autorun` link. Returns false if the catalog did not install it.
+    pub fn remove(&self, kind: Kind, name: &str, home: &Path) -> Result<bool> {
+        self.source(kind, name)?;
+        let mut manifest = read_manifest(home)?;
+        if !manifest.contains_key(&kind.relative_path(name)) {
+            return Ok(false);
+        }
+        if let Some(path) = self.conflict(kind, name, home)? {
+            bail!("{} was changed since it was installed", path.display());
+        }
+        let mut paths = vec![kind.installed_path(home, name)];
+        if kind == Kind::Script {
+            // Only the link `install` made, not a script the user put there
+            let link = home.join("scripts/autorun").join(format!("{}.pl", name));
+            if std::fs::read_link(&link).is_ok_and(|t| t == Path::new(&format!("../{}.pl", name))) {
+                paths.push(link);
+            }
+        }
+        for path in paths {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("remove {}", path.display())),
+            }
+        }
+        manifest.remove(&kind.relative_path(name));
+        write_manifest(home, &manifest)?;
+        Ok(true)
+    }
+}
+
+fn digest(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn read_manifest(home: &Path) -> Result<BTreeMap<String, String>> {
+    let path = home.join(MANIFEST);
+    match std::fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).with_context(|| format!("parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+fn write_manifest(home: &Path, manifest: &BTreeMap<String, String>) -> Result<()> {
+    let path = home.join(MANIFEST);
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(manifest)?).with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("write {}", path.display()))
+}
+
+/// A one-line description: a script's `description` in its `%IRSSI`
+/// header, or a theme's first comment line.
+fn describe(kind: Kind, text: &str) -> Option<String> {
+    match kind {
+        Kind::Script => {
+            let re = regex::Regex::new(r#"description\s*=>\s*(?:'([^']*)'|"([^"]*)")"#).expect("valid regex");
+            let caps = re.captures(text)?;
+            caps.get(1).or_else(|| caps.get(2)).map(|m| m.as_str().trim().to_string())
+        }
+        Kind::Theme => text
+            .lines()
+            .find_map(|l| l.trim().strip_prefix('#'))
+            .map(|c| c.trim().to_string()),
+    }
+    .filter(|d| !d.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_and_remove() {
+        let root = std::env::temp_dir().join(format!("irssi-v5-catalog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (dir, home) = (root.join("catalog"), root.join("home"));
+        std::fs::create_dir_all(dir.join("scripts")).unwrap();
+        std::fs::create_dir_all(dir.join("themes")).unwrap();
+        std::fs::write(
+            dir.join("scripts/nickcolor.pl"),
+            "our %IRSSI = (\n  authors => 'x',\n  description => 'Colour nicks by hash',\n);\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("scripts/irssi-v5-control.pl"), "").unwrap();
+        std::fs::write(dir.join("scripts/notes.txt"), "").unwrap();
+        std::fs::write(dir.join("themes/solarized.theme"), "# Solarized dark\ndefault_color = -1;\n").unwrap();
+        let catalog = Catalog::new(dir);
+
+        let scripts = catalog.list(Kind::Script, &home).unwrap();
+        assert_eq!(
+            scripts,
+            [Entry { name: "nickcolor".into(), description: Some("Colour nicks by hash".into()), enabled: false }]
+        );
+        assert_eq!(catalog.list(Kind::Theme, &home).unwrap()[0].description.as_deref(), Some("Solarized dark"));
+        assert!(catalog.list(Kind::Theme, &root.join("nowhere")).unwrap()[0].name == "solarized");
+
+        catalog.install(Kind::Script, "nickcolor", &home).unwrap();
+        catalog.install(Kind::Script, "nickcolor", &home).unwrap();
+        assert!(home.join("scripts/autorun/nickcolor.pl").exists());
+        assert!(catalog.list(Kind::Script, &home).unwrap()[0].enabled);
+        catalog.install(Kind::Theme, "solarized", &home).unwrap();
+        assert!(home.join("solarized.theme").exists());
+
+        assert!(catalog.install(Kind::Script, "irssi-v5-control", &home).is_err());
+        assert!(catalog.install(Kind::Script, "../x", &home).is_err());
+        assert!(catalog.install(Kind::Theme, "missing", &home).is_err());
+
+        assert!(catalog.remove(Kind::Script, "nickcolor", &home).unwrap());
+        assert!(!home.join("scripts/nickcolor.pl").exists());
+        assert!(std::fs::symlink_metadata(home.join("scripts/autorun/nickcolor.pl")).is_err());
+        assert!(!catalog.remove(Kind::Script, "nickcolor", &home).unwrap());
+
+        // Files the catalog did not write, or that were changed, are kept
+        std::fs::write(home.join("scripts/nickcolor.pl"), "# mine\n").unwrap();
+        assert!(catalog.conflict(Kind::Script, "nickcolor", &home).unwrap().is_some());
+        assert!(catalog.install(Kind::Script, "nickcolor", &home).is_err());
+        assert!(!catalog.remove(Kind::Script, "nickcolor", &home).unwrap());
+        assert_eq!(std::fs::read_to_string(home.join("scripts/nickcolor.pl")).unwrap(), "# mine\n");
+        std::fs::write(home.join("solarized.theme"), "# edited\n").unwrap();
+        assert!(catalog.remove(Kind::Theme, "solarized", &home).is_err());
+        assert!(home.join("solarized.theme").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::irssi::{self, Managed};
+
+/// Environment variable a session's client reads the soju password from
+/// (see `session::SojuLogin`), so no config file holds it.
+pub const PASSWORD_ENV: &str = "IRSSI_V5_SOJU_PASSWORD";
+
+/// The terminal IRC client a user's session runs. Each profile knows the
+/// command that starts it and how to point its config at soju.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Client {
+    #[default]
+    Irssi,
+    Weechat,
+    Senpai,
+}
+
+pub const ALL_CLIENTS: [Client; 3] = [Client::Irssi, Client::Weechat, Client::Senpai];
+
+impl Client {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Client::Irssi => "irssi",
+            Client::Weechat => "weechat",
+            Client::Senpai => "senpai",
+        }
+    }
+
+    /// Whether the client's binary is on PATH.
+    pub fn installed(self) -> bool {
+        let Some(path) = std::env::var_os("PATH") else { return false };
+        std::env::split_paths(&path).any(|dir| dir.join(self.as_str()).is_file())
+    }
+
+    /// Command line that runs the client with `home` (absolute) as its home.
+    pub fn command(self, home: &str) -> Vec<String> {
+        let args: Vec<String> = match self {
+            Client::Irssi => vec!["--home".into(), home.into(), "--config".into(), format!("{}/config", home)],
+            Client::Weechat => vec!["--dir".into(), format!("{}/weechat", home)],
+            Client::Senpai => vec!["-config".into(), format!("{}/senpai/senpai.scfg", home)],
+        };
+        std::iter::once(self.as_str().to_string()).chain(args).collect()
+    }
+
+    /// Bring the client's config in `home` up to date with soju: the
+    /// settings irssi-v5 manages are rewritten, everything else is left as
+    /// the user has it. `networks` are the user's soju networks, for
+    /// clients that need one server entry per network. Returns whether a
+    /// file was written.
+    pub async fn sync_config(self, home: &Path, m: &Managed<'_>, networks: &[String]) -> Result<bool> {
+        match self {
+            Client::Irssi => irssi::sync_config(&home.join("config"), m).await,
+            Client::Weechat => sync_file(&home.join("weechat/irc.conf"), |text| weechat_irc_conf(text, m, networks)).await,
+            Client::Senpai => sync_file(&home.join("senpai/senpai.scfg"), |text| senpai_scfg(text, m)).await,
+        }
+    }
+}
+
+impl FromStr for Client {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ALL_CLIENTS
+            .into_iter()
+            .find(|c| c.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown client {:?} (expected irssi, weechat or senpai)", s))
+    }
+}
+
+/// Rewrite `path` with `update` applied to its text (empty if missing),
+/// only when that changes something.
+async fn sync_file(path: &Path, update: impl FnOnce(&str) -> String) -> Result<bool> {
+    let old = match tokio::fs::read_to_string(path).await {
+        Ok(text) => Some(text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+    let new = update(old.as_deref().unwrap_or(""));
+    if old.as_deref() == Some(new.as_str()) {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp).await.with_context(|| format!("create {}", tmp.display()))?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600)).await?;
+    file.write_all(new.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&tmp, path).await.with_context(|| format!("rename {}", tmp.display()))?;
+    Ok(true)
+}
+
+/// Set `key = value` lines in `section` of an ini-style file. `always`
+/// entries replace what is there; `initial` ones are only added when
+/// missing, so the user can change them.
+fn set_options(text: &str, section: &str, always: &[(String, String)], initial: &[(String, String)]) -> String {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let header = format!("[{}]", section);
+    let start = match lines.iter().position(|l| l.trim() == header) {
+        Some(i) => i,
+        None => {
+            if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(header);
+            lines.len() - 1
+        }
+    };
+    let key_of = |line: &str| line.split_once('=').map(|(k, _)| k.trim().to_string());
+    for (entries, replace) in [(always, true), (initial, false)] {
+        for (key, value) in entries {
+            let end = lines[start + 1..].iter().position(|l| l.starts_with('[')).map_or(lines.len(), |i| start + 1 + i);
+            let line = format!("{} = {}", key, value);
+            match lines[start + 1..end].iter().position(|l| key_of(l).as_deref() == Some(key)) {
+                Some(i) if replace => lines[start + 1 + i] = line,
+                Some(_) => {}
+                None => {
+                    // Before the blank lines that end the section
+                    let at = (start + 1..end).rev().find(|&i| !lines[i].trim().is_empty()).map_or(start + 1, |i| i + 1);
+                    lines.insert(at, line);
+                }
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// WeeChat's irc.conf with one server per soju network. The password is
+/// evaluated from the environment when WeeChat connects.
+fn weechat_irc_conf(text: &str, m: &Managed<'_>, networks: &[String]) -> String {
+    let mut always = Vec::new();
+    let mut initial = Vec::new();
+    for net in networks {
+        let login = format!("\"{}/{}\"", m.username, net);
+        let opt = |name: &str| format!("{}.{}", net, name);
+        always.extend([
+            (opt("addresses"), format!("\"{}/{}\"", m.soju_host, m.soju_port)),
+            (opt("tls"), "off".to_string()),
+            (opt("sasl_mechanism"), "plain".to_string()),
+            (opt("sasl_username"), login.clone()),
+            (opt("sasl_password"), format!("\"${{env:{}}}\"", PASSWORD_ENV)),
+            (opt("username"), login),
+        ]);
+        initial.extend([
+            (opt("nicks"), format!("\"{}\"", m.username)),
+            (opt("autoconnect"), "on".to_string()),
+        ]);
+    }
+    set_options(text, "server", &always, &initial)
+}
+
+/// senpai's config. senpai speaks soju's bouncer-networks extension, so a
+/// single login as the user covers every network; the password comes from
+/// `password-cmd`.
+fn senpai_scfg(text: &str, m: &Managed<'_>) -> String {
+    let always = [
+        ("address", format!("\"irc+insecure://{}:{}\"", m.soju_host, m.soju_port)),
+        ("username", format!("\"{}\"", m.username)),
+        ("password-cmd", format!("printenv {}", PASSWORD_ENV)),
+    ];
+    let initial = [("nickname", format!("\"{}\"", m.username))];
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    // Only top-level directives, not those inside blocks
+    let directive = |line: &str| {
+        (!line.starts_with(char::is_whitespace)).then(|| line.split_whitespace().next().unwrap_or("").to_string())
+    };
+    for (entries, replace) in [(&always[..], true), (&initial[..], false)] {
+        for (name, value) in entries {
+            let line = format!("{} {}", name, value);
+            match lines.iter().position(|l| directive(l).as_deref() == Some(*name)) {
+                Some(i) if replace => lines[i] = line,
+                Some(_) => {}
+                None => lines.push(line),
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANAGED: Managed = Managed {
+        username: "alice",
+        network: "libera",
+        soju_host: "soju",
+        soju_port: "6667",
+    };
+
+    #[test]
+    fn test_client_configs() {
+        assert_eq!("weechat".parse::<Client>().unwrap(), Client::Weechat);
+        assert!("hexchat".parse::<Client>().is_err());
+        assert_eq!(Client::Irssi.command("/s/alice"), ["irssi", "--home", "/s/alice", "--config", "/s/alice/config"]);
+        assert_eq!(Client::Senpai.command("/s/alice"), ["senpai", "-config", "/s/alice/senpai/senpai.scfg"]);
+
+        let nets = ["libera".to_string(), "oftc".to_string()];
+        let fresh = weechat_irc_conf("", &MANAGED, &nets);
+        assert!(fresh.starts_with("[server]\nlibera.addresses = \"soju/6667\"\n"));
+        assert!(fresh.contains("oftc.sasl_username = \"alice/oftc\"\n"));
+        assert!(fresh.contains("libera.sasl_password = \"${env:IRSSI_V5_SOJU_PASSWORD}\"\n"));
+        assert_eq!(weechat_irc_conf(&fresh, &MANAGED, &nets), fresh, "idempotent");
+
+        // The user's own servers, settings and nick stay; managed ones are fixed
+        let edited = fresh
+            .replace("libera.nicks = \"alice\"", "libera.nicks = \"al\"")
+            .replace("libera.addresses = \"soju/6667\"", "libera.addresses = \"old/1\"")
+            + "\n[look]\ncolor_nicks = on\n";
+        let edited = edited.replace("[server]\n", "[server]\nmine.addresses = \"irc.example.org/6697\"\n");
+        let synced = weechat_irc_conf(&edited, &MANAGED, &nets);
+        assert!(synced.contains("libera.nicks = \"al\"\n") && synced.contains("libera.addresses = \"soju/6667\"\n"));
+        assert!(synced.contains("mine.addresses = \"irc.example.org/6697\"\n"));
+        assert!(synced.ends_with("[look]\ncolor_nicks = on\n"));
+
+        let scfg = senpai_scfg("nickname \"al\"\ncolors {\n  username \"x\"\n}\n", &MANAGED);
+        assert_eq!(
+            scfg,
+            "nickname \"al\"\ncolors {\n  username \"x\"\n}\naddress \"irc+insecure://soju:6667\"\n\
+             username \"alice\"\npassword-cmd printenv IRSSI_V5_SOJU_PASSWORD\n"
+        );
+        assert_eq!(senpai_scfg(&scfg, &MANAGED), scfg);
+    }
+}
@@ -1,8 +1,14 @@
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::Duration;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
 
+use crate::roles::{self, Role};
+
+/// Settings fixed for the life of the process. Changing any of these in the
+/// config file needs a restart.
 #[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
@@ -17,12 +23,10 @@ pub struct Config {
     pub dev_mode: bool,
     pub dev_user: String,
 
-    // Admin users (email prefixes)
-    pub admin_users: HashSet<String>,
-
     // Soju
     pub soju_addr: String,
-    pub soju_socket: PathBuf,
+    // soju's SQLite DB, if mounted here — included in backups
+    pub soju_db: Option<PathBuf>,
 
     pub irc_addr: String,
     pub irc_network_name: String,
@@ -38,12 +42,98 @@ pub struct Config {
     pub data_dir: PathBuf,
     pub sessions_dir: PathBuf,
     pub public_dir: PathBuf,
+
+    // Local admin API socket for `irssi-v5 admin`
+    pub control_socket: PathBuf,
+
+    // IRC listener for native clients logging in with app passwords
+    // (e.g. 0.0.0.0:6697); None = off. TLS when both cert and key are set.
+    pub irc_proxy_listen: Option<String>,
+    pub irc_proxy_tls_cert: Option<PathBuf>,
+    pub irc_proxy_tls_key: Option<PathBuf>,
+
+    // SSH gateway: an sshd run by irssi-v5 on this address (e.g.
+    // 0.0.0.0:2222) that attaches key-authenticated users to their dtach
+    // session; None = off.
+    pub ssh_listen: Option<String>,
+    pub ssh_host_key: PathBuf,
+    pub sshd: PathBuf,
+
+    // Admin-vetted irssi scripts (scripts/*.pl) and themes (themes/*.theme)
+    // users can enable
+    pub catalog_dir: PathBuf,
+
+    // Key for secrets encrypted at rest: APP_KEY (hex) if set, else the
+    // file, generated if missing. Retired keys only decrypt, for rotation.
+    pub app_key: Option<String>,
+    pub app_key_file: PathBuf,
+    pub app_retired_keys: Vec<String>,
+
+    /// TOML file the config was read from, if any — re-read on SIGHUP
+    pub file: Option<PathBuf>,
 }
 
+/// Settings that are re-read on SIGHUP and swapped in without a restart.
+#[derive(Debug, Clone)]
+pub struct Runtime {
+    // Admin users (email prefixes) — bootstrapped into the admin role at
+    // startup and on every reload
+    pub admin_users: BTreeSet<String>,
+
+    // IdP group → role mapping, e.g. ROLE_GROUPS=irc-admins=admin,helpdesk=operator
+    pub role_groups: Vec<(String, Role)>,
+
+    // Show users a banner while an admin is spectating their terminal
+    pub spectate_notify: bool,
+
+    // On SIGTERM: warn connected users, wait this long, then detach sessions
+    pub shutdown_grace: Duration,
+
+    // Longest lifetime a terminal share link may be given
+    pub max_share_ttl: Duration,
+
+    // Scheduled backups into <data_dir>/backups; None = off
+    pub backup_interval: Option<Duration>,
+    pub backup_keep: usize,
+
+    // Rotate each user's soju password once it is this old; None = off
+    pub soju_password_rotation: Option<Duration>,
+
+    // IRC networks users may pick from; the primary network is always first
+    pub networks: Vec<NetworkPreset>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkPreset {
+    pub name: String,
+    /// soju address format: irc+insecure://host[:port] or ircs://host[:port]
+    pub addr: String,
+}
+
+impl NetworkPreset {
+    /// Host part of `addr`, lowercased.
+    pub fn host(&self) -> String {
+        let rest = self.addr.split_once("://").map_or(self.addr.as_str(), |(_, r)| r);
+        rest.split(':').next().unwrap_or(rest).to_ascii_lowercase()
+    }
+}
+
+/// Config file keys that are not plain settings.
+const TABLE_KEYS: [&str; 1] = ["networks"];
+
 impl Config {
-    pub fn from_env() -> Result<Self> {
-        let _ = dotenvy::dotenv();
+    /// Load config from the environment, merged over the optional
+    /// TOML file. Env wins over the file. Every value is validated and any
+    /// error names the offending key.
+    pub fn load(file: Option<&Path>) -> Result<(Config, Runtime)> {
+        let src = Source::new(file, |k| std::env::var(k).ok())?;
+        let loaded = Self::from_source(&src)?;
+        src.check_unknown()?;
+        Ok(loaded)
+    }
 
+    fn from_source(src: &Source) -> Result<(Config, Runtime)> {
         let data_dir = if PathBuf::from("/data").exists() {
             PathBuf::from("/data")
         } else {
@@ -53,40 +143,335 @@ impl Config {
             d
         };
 
-        let admin_users: HashSet<String> = std::env::var("ADMIN_USERS")
-            .unwrap_or_default()
-            .split(',')
-            .map(|s| s.trim().to_lowercase())
-            .filter(|s| !s.is_empty())
-            .collect();
+        let irc_addr = src.string("IRC_ADDR", "irc+insecure://irc.libera.chat")?;
+        let irc_network_name = src.string("IRC_NETWORK_NAME", "libera")?;
+        let primary = NetworkPreset { name: irc_network_name.clone(), addr: irc_addr.clone() };
+        validate_network(&primary).context("invalid IRC_ADDR / IRC_NETWORK_NAME")?;
+
+        let mut networks = vec![primary];
+        for preset in src.networks()? {
+            if networks.iter().any(|n| n.name == preset.name) {
+                bail!("networks: duplicate network name '{}'", preset.name);
+            }
+            networks.push(preset);
+        }
 
-        let cf_jwks_cache_ttl = std::env::var("CF_JWKS_CACHE_TTL")
-            .ok()
-            .and_then(|s| humantime::parse_duration(&s).ok())
-            .unwrap_or(Duration::from_secs(6 * 3600));
-
-        Ok(Config {
-            port: env_var("PORT", "3001").parse().context("invalid PORT")?,
-            base_url: env_var("BASE_URL", "http://localhost:3001"),
-            cf_aud: env_var("CF_AUD", ""),
-            cf_team_domain: env_var("CF_TEAM_DOMAIN", ""),
-            cf_jwks_cache_ttl,
-            dev_mode: env_var("DEV_MODE", "false") == "true",
-            dev_user: env_var("DEV_USER", "devuser"),
-            admin_users,
-            soju_addr: env_var("SOJU_ADDR", "soju:6667"),
-            soju_socket: PathBuf::from(env_var("SOJU_SOCKET", "/soju/soju.sock")),
-            irc_addr: env_var("IRC_ADDR", "irc+insecure://irc.libera.chat"),
-            irc_network_name: env_var("IRC_NETWORK_NAME", "libera"),
-            ttyd_base_port: env_var("TTYD_BASE_PORT", "7100").parse().context("invalid TTYD_BASE_PORT")?,
-            dtach_session: env_var("DTACH_SESSION", "false") == "true",
+        let role_groups = roles::parse_group_map(&src.string("ROLE_GROUPS", "")?)
+            .map_err(|e| anyhow!("{}: {}", src.origin("ROLE_GROUPS"), e))?;
+
+        let cfg = Config {
+            port: src.parse("PORT", 3001)?,
+            base_url: src.string("BASE_URL", "http://localhost:3001")?,
+            cf_aud: src.secret("CF_AUD")?,
+            cf_team_domain: src.string("CF_TEAM_DOMAIN", "")?,
+            cf_jwks_cache_ttl: src.duration("CF_JWKS_CACHE_TTL", "6h")?,
+            dev_mode: src.bool("DEV_MODE", false)?,
+            dev_user: src.string("DEV_USER", "devuser")?,
+            soju_addr: src.string("SOJU_ADDR", "soju:6667")?,
+            soju_db: src.raw("SOJU_DB")?.filter(|s| !s.is_empty()).map(PathBuf::from),
+            irc_addr,
+            irc_network_name,
+            ttyd_base_port: src.parse("TTYD_BASE_PORT", 7100)?,
+            dtach_session: src.bool("DTACH_SESSION", false)?,
             sessions_dir: data_dir.join("sessions"),
-            public_dir: PathBuf::from(env_var("PUBLIC_DIR", "./public")),
+            public_dir: PathBuf::from(src.string("PUBLIC_DIR", "./public")?),
+            control_socket: match src.raw("CONTROL_SOCKET")? {
+                Some(p) => PathBuf::from(p),
+                None => data_dir.join("control.sock"),
+            },
+            irc_proxy_listen: src.raw("IRC_PROXY_LISTEN")?.filter(|s| !s.is_empty()),
+            irc_proxy_tls_cert: src.raw("IRC_PROXY_TLS_CERT")?.filter(|s| !s.is_empty()).map(PathBuf::from),
+            irc_proxy_tls_key: src.raw("IRC_PROXY_TLS_KEY")?.filter(|s| !s.is_empty()).map(PathBuf::from),
+            ssh_listen: src.raw("SSH_LISTEN")?.filter(|s| !s.is_empty()),
+            ssh_host_key: match src.raw("SSH_HOST_KEY")? {
+                Some(p) => PathBuf::from(p),
+                None => data_dir.join("ssh_host_ed25519_key"),
+            },
+            sshd: PathBuf::from(src.string("SSHD", "/usr/sbin/sshd")?),
+            catalog_dir: match src.raw("CATALOG_DIR")? {
+                Some(p) => PathBuf::from(p),
+                None => data_dir.join("catalog"),
+            },
+            app_key: match (src.raw("APP_KEY")?.filter(|s| !s.is_empty()), src.raw("APP_KEY_FILE")?) {
+                (Some(_), Some(_)) => bail!("set only one of APP_KEY and APP_KEY_FILE"),
+                (key, _) => key,
+            },
+            app_key_file: match src.raw("APP_KEY_FILE")? {
+                Some(p) => PathBuf::from(p),
+                None => data_dir.join("app.key"),
+            },
+            // Comma separated, or one per line in APP_RETIRED_KEYS_FILE
+            app_retired_keys: src
+                .secret("APP_RETIRED_KEYS")?
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect(),
             data_dir,
+            file: src.path.clone(),
+        };
+        // Backups archive the sessions dir; the key must never end up there
+        if cfg.app_key_file.starts_with(&cfg.sessions_dir) {
+            bail!("{}: must not be inside the sessions directory, which backups include", src.origin("APP_KEY_FILE"));
+        }
+        let runtime = Runtime {
+            admin_users: src.list("ADMIN_USERS")?.into_iter().map(|s| s.to_lowercase()).collect(),
+            role_groups,
+            spectate_notify: src.bool("SPECTATE_NOTIFY", true)?,
+            shutdown_grace: src.duration("SHUTDOWN_GRACE", "5s")?,
+            max_share_ttl: src.duration("MAX_SHARE_TTL", "24h")?,
+            backup_interval: match src.string("BACKUP_INTERVAL", "off")?.as_str() {
+                "off" | "" => None,
+                _ => Some(src.duration("BACKUP_INTERVAL", "off")?),
+            },
+            backup_keep: match src.parse("BACKUP_KEEP", 7)? {
+                0 => bail!("{}: must be at least 1", src.origin("BACKUP_KEEP")),
+                n => n,
+            },
+            soju_password_rotation: match src.string("SOJU_PASSWORD_ROTATION", "off")?.as_str() {
+                "off" | "" => None,
+                _ => Some(src.duration("SOJU_PASSWORD_ROTATION", "off")?),
+            },
+            networks,
+        };
+        Ok((cfg, runtime))
+    }
+}
+
+fn validate_network(n: &NetworkPreset) -> Result<()> {
+    if n.name.is_empty() || !n.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("network name '{}' must be non-empty and only contain letters, digits, - and _", n.name);
+    }
+    let host = ["irc+insecure://", "ircs://", "irc://"]
+        .iter()
+        .find_map(|scheme| n.addr.strip_prefix(scheme))
+        .ok_or_else(|| anyhow!("network address '{}' must start with ircs://, irc:// or irc+insecure://", n.addr))?;
+    if host.is_empty() || host.contains('/') {
+        bail!("network address '{}' must be scheme://host[:port]", n.addr);
+    }
+    Ok(())
+}
+
+/// Env var lookup, swappable in tests.
+type EnvFn = Box<dyn Fn(&str) -> Option<String>>;
+
+/// Layered lookup of config values: env var `KEY`, then `KEY_FILE` for
+/// secrets, then `key` in the TOML file.
+struct Source {
+    path: Option<PathBuf>,
+    file: toml::Table,
+    env: EnvFn,
+    /// File keys that were looked up, to reject unknown (misspelt) ones
+    used: Mutex<HashSet<String>>,
+}
+
+impl Source {
+    fn new(path: Option<&Path>, env: impl Fn(&str) -> Option<String> + 'static) -> Result<Self> {
+        let file = match path {
+            Some(p) => std::fs::read_to_string(p)
+                .with_context(|| format!("failed to read config file {}", p.display()))?
+                .parse::<toml::Table>()
+                .with_context(|| format!("failed to parse config file {}", p.display()))?,
+            None => toml::Table::new(),
+        };
+        Ok(Self {
+            path: path.map(Path::to_path_buf),
+            file,
+            env: Box::new(env),
+            used: Mutex::new(HashSet::new()),
         })
     }
+
+    /// Human-readable location of a key, for error messages.
+    fn origin(&self, key: &str) -> String {
+        if (self.env)(key).is_some() {
+            return key.to_string();
+        }
+        match &self.path {
+            Some(p) => format!("{} (in {})", key.to_lowercase(), p.display()),
+            None => key.to_string(),
+        }
+    }
+
+    fn file_value(&self, key: &str) -> Option<&toml::Value> {
+        let key = key.to_lowercase();
+        let v = self.file.get(&key);
+        self.used.lock().unwrap().insert(key);
+        v
+    }
+
+    fn raw(&self, key: &str) -> Result<Option<String>> {
+        let file_value = self.file_value(key);
+        if let Some(v) = (self.env)(key) {
+            return Ok(Some(v));
+        }
+        let Some(v) = file_value else {
+            return Ok(None);
+        };
+        let s = match v {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|i| i.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow!("{}: expected a list of strings", self.origin(key)))?
+                .join(","),
+            other => bail!("{}: unsupported value type {}", self.origin(key), other.type_str()),
+        };
+        Ok(Some(s))
+    }
+
+    fn string(&self, key: &str, default: &str) -> Result<String> {
+        Ok(self.raw(key)?.unwrap_or_else(|| default.to_string()))
+    }
+
+    /// A secret may also be given as a path in `KEY_FILE` / `key_file`, so
+    /// it can come from a Docker or systemd secret instead of the env.
+    fn secret(&self, key: &str) -> Result<String> {
+        let file_key = format!("{}_FILE", key);
+        match (self.raw(key)?, self.raw(&file_key)?) {
+            (Some(_), Some(_)) => bail!("set only one of {} and {}", key, file_key),
+            (Some(v), None) => Ok(v),
+            (None, Some(path)) => Ok(std::fs::read_to_string(&path)
+                .with_context(|| format!("{}: failed to read {}", self.origin(&file_key), path))?
+                .trim()
+                .to_string()),
+            (None, None) => Ok(String::new()),
+        }
+    }
+
+    fn bool(&self, key: &str, default: bool) -> Result<bool> {
+        match self.raw(key)?.as_deref() {
+            None => Ok(default),
+            Some("true") | Some("1") => Ok(true),
+            Some("false") | Some("0") => Ok(false),
+            Some(other) => bail!("{}: expected true or false, got '{}'", self.origin(key), other),
+        }
+    }
+
+    fn parse<T>(&self, key: &str, default: T) -> Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        match self.raw(key)? {
+            None => Ok(default),
+            Some(s) => s
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("{}: invalid value '{}': {}", self.origin(key), s, e)),
+        }
+    }
+
+    fn duration(&self, key: &str, default: &str) -> Result<Duration> {
+        let s = self.string(key, default)?;
+        humantime::parse_duration(s.trim())
+            .map_err(|e| anyhow!("{}: invalid duration '{}' ({}), expected e.g. 30s, 10m, 6h", self.origin(key), s, e))
+    }
+
+    /// Comma-separated in the env, a string or list of strings in the file.
+    fn list(&self, key: &str) -> Result<Vec<String>> {
+        Ok(self
+            .string(key, "")?
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// `[[networks]]` tables from the file (there is no env form).
+    fn networks(&self) -> Result<Vec<NetworkPreset>> {
+        let Some(v) = self.file_value("networks") else {
+            return Ok(Vec::new());
+        };
+        let presets: Vec<NetworkPreset> = v
+            .clone()
+            .try_into()
+            .map_err(|e| anyhow!("{}: {}", self.origin("networks"), e))?;
+        for (i, n) in presets.iter().enumerate() {
+            validate_network(n).with_context(|| format!("networks[{}]", i))?;
+        }
+        Ok(presets)
+    }
+
+    fn check_unknown(&self) -> Result<()> {
+        let used = self.used.lock().unwrap();
+        let unknown: Vec<&str> = self
+            .file
+            .keys()
+            .map(String::as_str)
+            .filter(|k| !used.contains(*k) && !TABLE_KEYS.contains(k))
+            .collect();
+        if let (false, Some(p)) = (unknown.is_empty(), &self.path) {
+            bail!("unknown key(s) in {}: {}", p.display(), unknown.join(", "));
+        }
+        Ok(())
+    }
 }
 
-fn env_var(key: &str, default: &str) -> String {
-    std::env::var(key).unwrap_or_else(|_| default.to_string())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn source(toml: &str, env: &[(&str, &str)]) -> Source {
+        let dir = std::env::temp_dir().join(format!("irssi-v5-config-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml).unwrap();
+        let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        // The file is parsed here; nothing reads it again
+        let src = Source::new(Some(&path), move |k| env.get(k).cloned()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        src
+    }
+
+    #[test]
+    fn test_source_layering_and_validation() {
+        let src = source(
+            "dtach_session = true\nport = 4000\nadmin_users = [\"Alice\", \"bob\"]\n\
+             [[networks]]\nname = \"oftc\"\naddr = \"ircs://irc.oftc.net:6697\"\n",
+            &[("PORT", "5000")],
+        );
+        let (cfg, rt) = Config::from_source(&src).unwrap();
+        src.check_unknown().unwrap();
+        assert!(cfg.dtach_session);
+        assert_eq!(cfg.port, 5000, "env wins over the file");
+        assert_eq!(rt.admin_users.iter().collect::<Vec<_>>(), ["alice", "bob"]);
+        assert_eq!(rt.networks.len(), 2);
+        assert_eq!(rt.networks[1].name, "oftc");
+
+        let err = Config::from_source(&source("", &[("DTACH_SESSION", "ture")])).unwrap_err();
+        assert!(err.to_string().contains("DTACH_SESSION"), "{}", err);
+
+        let err = Config::from_source(&source("cf_jwks_cache_ttl = \"6 hours-ish\"", &[])).unwrap_err();
+        assert!(err.to_string().contains("cf_jwks_cache_ttl"), "{}", err);
+
+        let src = source("dtach_sesion = true", &[]);
+        Config::from_source(&src).unwrap();
+        assert!(src.check_unknown().unwrap_err().to_string().contains("dtach_sesion"));
+
+        let secret = std::env::temp_dir().join(format!("irssi-v5-secret-{}", rand::random::<u32>()));
+        std::fs::write(&secret, "aud-tag\n").unwrap();
+        let (cfg, _) = Config::from_source(&source("", &[("CF_AUD_FILE", secret.to_str().unwrap())])).unwrap();
+        assert_eq!(cfg.cf_aud, "aud-tag");
+        std::fs::write(&secret, "aa11\nbb22\n").unwrap();
+        let (cfg, _) =
+            Config::from_source(&source("", &[("APP_RETIRED_KEYS_FILE", secret.to_str().unwrap())])).unwrap();
+        assert_eq!(cfg.app_retired_keys, ["aa11", "bb22"]);
+        let (cfg, _) = Config::from_source(&source("app_retired_keys = [\"aa11\", \"bb22\"]", &[])).unwrap();
+        assert_eq!(cfg.app_retired_keys, ["aa11", "bb22"]);
+        std::fs::remove_file(&secret).unwrap();
+
+        let key_file = cfg.sessions_dir.join("app.key");
+        let err = Config::from_source(&source("", &[("APP_KEY_FILE", key_file.to_str().unwrap())])).unwrap_err();
+        assert!(err.to_string().contains("APP_KEY_FILE"), "{}", err);
+
+        let err = Config::from_source(&source("[[networks]]\nname = \"x\"\naddr = \"http://x\"", &[]))
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("networks[0]"), "{:#}", err);
+    }
+}
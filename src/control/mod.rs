@@ -0,0 +1,331 @@
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::Router;
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tower::Service;
+use tracing::{info, warn};
+
+/// Set on every request that arrived over the control socket, carrying the
+/// per-process token. Anything else presenting it is ignored.
+pub const TOKEN_HEADER: &str = "x-irssi-control";
+/// Unix uid of the process on the other end of the control socket.
+pub const UID_HEADER: &str = "x-irssi-control-uid";
+
+/// Sent to anyone but root before hanging up, so `irssi-v5 admin` can say why.
+const REFUSED: &[u8] =
+    b"HTTP/1.0 403 Forbidden\r\ncontent-type: application/json\r\n\r\n{\"error\":\"the control socket is root-only\"}";
+
+pub fn new_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    hex::encode(bytes)
+}
+
+/// Serve `app` on a Unix socket only root can use. Terminal sessions run as
+/// the server's own user, so that user is turned away too. Requests are
+/// stamped with `token` so `authenticate` can treat them as a local
+/// administrator without going through Cloudflare Access.
+pub async fn serve(path: PathBuf, app: Router, token: Arc<str>) -> Result<()> {
+    let listener = bind(&path).await?;
+    info!("control socket on {}", path.display());
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let uid = match stream.peer_cred() {
+            Ok(cred) if cred.uid() == 0 => cred.uid(),
+            Ok(cred) => {
+                warn!("control socket: refused uid {}", cred.uid());
+                let _ = stream.write_all(REFUSED).await;
+                continue;
+            }
+            Err(e) => {
+                warn!("control socket: peer credentials: {}", e);
+                continue;
+            }
+        };
+        let token = Arc::clone(&token);
+        serve_connection(stream, app.clone(), move |headers| {
+            headers.insert(TOKEN_HEADER, HeaderValue::from_str(&token).expect("hex token"));
+            headers.insert(UID_HEADER, HeaderValue::from(uid));
+        });
+    }
+}
+
+/// Serve `app` on a Unix socket to the processes `allow` accepts, by pid,
+/// among those of root and the server's own user. Terminal sessions run as
+/// that user too, so `allow` has to tell them apart.
+pub async fn serve_local(path: PathBuf, app: Router, allow: impl Fn(i32) -> bool) -> Result<()> {
+    let listener = bind(&path).await?;
+    let owner = std::fs::metadata(&path)?.uid();
+    loop {
+        let (stream, _) = listener.accept().await?;
+        match stream.peer_cred() {
+            Ok(cred) if (cred.uid() == 0 || cred.uid() == owner) && cred.pid().is_some_and(&allow) => {}
+            Ok(cred) => {
+                warn!("{}: refused uid {} pid {:?}", path.display(), cred.uid(), cred.pid());
+                continue;
+            }
+            Err(e) => {
+                warn!("{}: peer credentials: {}", path.display(), e);
+                continue;
+            }
+        }
+        serve_connection(stream, app.clone(), |headers| {
+            headers.remove(TOKEN_HEADER);
+            headers.remove(UID_HEADER);
+        });
+    }
+}
+
+async fn bind(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).await.is_ok() {
+        bail!("{} is in use — is another instance running?", path.display());
+    }
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path).with_context(|| format!("bind {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Serve HTTP/1 on one accepted connection, letting `stamp` adjust each
+/// request's headers before routing.
+fn serve_connection(stream: UnixStream, app: Router, stamp: impl Fn(&mut HeaderMap) + Clone + Send + 'static) {
+    tokio::spawn(async move {
+        let service = hyper::service::service_fn(move |mut req: Request<hyper::body::Incoming>| {
+            stamp(req.headers_mut());
+            // Router is always ready, so poll_ready can be skipped
+            app.clone().call(req)
+        });
+        if let Err(e) = hyper::server::conn::http1::Builder::new()
+            .serve_connection(TokioIo::new(stream), service)
+            .await
+        {
+            warn!("control connection: {}", e);
+        }
+    });
+}
+
+// ── Client side (`irssi-v5 admin ...`) ────────────────────────────────────────
+
+/// One request to the running server. Speaks HTTP/1.0 so the response is
+/// never chunked and ends when the server closes the connection.
+async fn request(socket: &Path, method: &str, uri: &str, body: Option<Value>) -> Result<(u16, Value)> {
+    let mut stream = UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("cannot connect to {} — is irssi-v5 running?", socket.display()))?;
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let head = format!(
+        "{} {} HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        method,
+        uri,
+        body.len()
+    );
+    // A refusing server answers and hangs up before reading the request
+    let sent = async {
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await
+    }
+    .await;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await?;
+    if raw.is_empty() {
+        sent?;
+    }
+    parse_response(&raw)
+}
+
+fn parse_response(raw: &[u8]) -> Result<(u16, Value)> {
+    let text = std::str::from_utf8(raw).context("response is not UTF-8")?;
+    let (head, body) = text.split_once("\r\n\r\n").ok_or_else(|| anyhow!("malformed response"))?;
+    let status: u16 = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("malformed status line"))?;
+    let body = if body.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string()))
+    };
+    Ok((status, body))
+}
+
+/// Percent-encode a single path segment.
+fn segment(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub const ADMIN_USAGE: &str = "usage: irssi-v5 admin <command>
+
+  users                   list known users
+  sessions                list live terminal connections
+  kick <user>             stop a user's session
+  clear <user>            stop the session and delete the soju user
+  delete <user>           delete a user entirely
+  settings                show settings
+  set <key> <value>       change a setting, e.g. set max_users 80
+  announce <message...>   announce to every user (web and IRC)
+  reencrypt               re-seal stored secrets with the current APP_KEY
+  rotate-password <user>  give a user a new soju password (--all: everyone)";
+
+/// Run one `irssi-v5 admin` command against the control socket.
+pub async fn run_admin(socket: &Path, args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (method, uri, body) = match args.as_slice() {
+        ["users"] => ("GET", "/api/admin/users".to_string(), None),
+        ["sessions"] => ("GET", "/api/admin/devices".to_string(), None),
+        ["kick", user] => ("POST", format!("/api/admin/users/{}/kick", segment(user)), None),
+        ["clear", user] => ("POST", format!("/api/admin/users/{}/clear", segment(user)), None),
+        ["delete", user] => ("DELETE", format!("/api/admin/users/{}", segment(user)), None),
+        ["settings"] => ("GET", "/api/admin/settings".to_string(), None),
+        ["set", key, value] => {
+            // Accept snake_case keys; the API speaks camelCase
+            let value = serde_json::from_str(value).unwrap_or_else(|_| json!(value));
+            ("POST", "/api/admin/settings".to_string(), Some(json!({ camel_case(key): value })))
+        }
+        ["announce", message @ ..] if !message.is_empty() => {
+            ("POST", "/api/admin/announce".to_string(), Some(json!({"message": message.join(" ")})))
+        }
+        ["rotate-password", "--all"] => ("POST", "/api/admin/rotate-soju-passwords".to_string(), None),
+        ["rotate-password", user] => {
+            ("POST", format!("/api/admin/users/{}/rotate-soju-password", segment(user)), None)
+        }
+        ["reencrypt"] => ("POST", "/api/admin/reencrypt".to_string(), None),
+        _ => bail!("{}", ADMIN_USAGE),
+    };
+
+    let (status, body) = request(socket, method, &uri, body).await?;
+    if !(200..300).contains(&status) {
+        let msg = body["error"].as_str().map(str::to_string).unwrap_or_else(|| body.to_string());
+        bail!("{} {}: {} ({})", method, uri, msg, status);
+    }
+
+    match args[0] {
+        "users" => print_users(&body),
+        "sessions" => print_sessions(&body),
+        _ => println!("{}", serde_json::to_string_pretty(&body)?),
+    }
+    Ok(())
+}
+
+// ── SSH gateway (commands run by sshd) ───────────────────────────────────────
+
+/// Owner and id of the SSH key with `fingerprint`, if any.
+pub async fn ssh_key_owner(socket: &Path, fingerprint: &str) -> Result<Option<(String, i64)>> {
+    let uri = format!("/api/ssh/keys/{}", segment(fingerprint));
+    match request(socket, "GET", &uri, None).await? {
+        (404, _) => Ok(None),
+        (200, body) => {
+            let username = body["username"].as_str().ok_or_else(|| anyhow!("no username in response"))?;
+            Ok(Some((username.to_string(), body["id"].as_i64().unwrap_or_default())))
+        }
+        (status, body) => bail!("GET {}: {} ({})", uri, body["error"].as_str().unwrap_or(""), status),
+    }
+}
+
+/// Start `username`'s irssi for an SSH login with key `key_id`; returns the
+/// dtach socket to attach to. Errors carry the server's message, for the
+/// user's terminal.
+pub async fn ssh_session(socket: &Path, username: &str, key_id: i64) -> Result<String> {
+    let body = json!({"username": username, "keyId": key_id});
+    let (status, body) = request(socket, "POST", "/api/ssh/sessions", Some(body)).await?;
+    if status != 200 {
+        bail!("{}", body["error"].as_str().unwrap_or("cannot start the session"));
+    }
+    body["socket"].as_str().map(str::to_string).ok_or_else(|| anyhow!("no socket in response"))
+}
+
+fn camel_case(key: &str) -> String {
+    let mut out = String::new();
+    let mut upper = false;
+    for c in key.chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                out.extend(c.to_uppercase());
+                upper = false;
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn print_users(body: &Value) {
+    println!("{:<24} {:<8} {:<20} {:<10} LAST SEEN", "USERNAME", "SESSION", "ROLES", "SUSPENDED");
+    for u in body["users"].as_array().into_iter().flatten() {
+        let roles: Vec<&str> = u["roles"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+        println!(
+            "{:<24} {:<8} {:<20} {:<10} {}",
+            u["username"].as_str().unwrap_or(""),
+            if u["active_session"].as_bool().unwrap_or(false) { "active" } else { "-" },
+            if roles.is_empty() { "-".to_string() } else { roles.join(",") },
+            if u["suspended"].is_null() { "-" } else { "yes" },
+            time(&u["last_seen"]),
+        );
+    }
+}
+
+fn print_sessions(body: &Value) {
+    println!("{:<6} {:<24} {:<24} {:<16} CONNECTED", "ID", "USERNAME", "KIND", "IP");
+    for c in body["connections"].as_array().into_iter().flatten() {
+        let kind = match c["kind"]["type"].as_str().unwrap_or("") {
+            "spectate" => format!("spectate {}", c["kind"]["target"].as_str().unwrap_or("")),
+            "share" => format!("share of {}", c["kind"]["owner"].as_str().unwrap_or("")),
+            other => other.to_string(),
+        };
+        println!(
+            "{:<6} {:<24} {:<24} {:<16} {}",
+            c["id"],
+            c["username"].as_str().unwrap_or(""),
+            kind,
+            c["ip"].as_str().unwrap_or("-"),
+            time(&c["connected_at"]),
+        );
+    }
+}
+
+/// Unix ms as RFC 3339, to the second.
+fn time(ms: &Value) -> String {
+    ms.as_i64()
+        .map(|ms| {
+            let t = std::time::UNIX_EPOCH + std::time::Duration::from_millis(ms as u64);
+            humantime::format_rfc3339_seconds(t).to_string()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_helpers() {
+        let (status, body) =
+            parse_response(b"HTTP/1.0 403 Forbidden\r\ncontent-type: application/json\r\n\r\n{\"error\":\"no\"}")
+                .unwrap();
+        assert_eq!(status, 403);
+        assert_eq!(body["error"], "no");
+        assert_eq!(parse_response(b"HTTP/1.0 200 OK\r\n\r\n").unwrap(), (200, Value::Null));
+        assert!(parse_response(b"garbage").is_err());
+        assert_eq!(parse_response(REFUSED).unwrap().1["error"], "the control socket is root-only");
+
+        assert_eq!(segment("first.last+irc"), "first.last%2Birc");
+        assert_eq!(segment("a/../b"), "a%2F..%2Fb");
+        assert_eq!(camel_case("max_users"), "maxUsers");
+        assert_eq!(camel_case("maxUsers"), "maxUsers");
+    }
+}
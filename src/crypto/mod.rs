@@ -0,0 +1,210 @@
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Sealed values written before keys had ids; opened by trying every key.
+const PREFIX_V1: &str = "v1:";
+/// `v2:<key id>:<hex nonce || ciphertext>`
+const PREFIX: &str = "v2:";
+const NONCE_LEN: usize = 12;
+
+struct AppKey {
+    /// First 8 hex characters of the key's SHA-256, stored with each value
+    /// so opening picks the right key without trial decryption.
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl AppKey {
+    fn new(key: [u8; 32]) -> Self {
+        AppKey {
+            id: hex::encode(&Sha256::digest(key)[..4]),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        }
+    }
+
+    fn decrypt(&self, context: &str, hex_body: &str) -> Result<String> {
+        let raw = hex::decode(hex_body).map_err(|_| anyhow!("not a sealed value"))?;
+        if raw.len() < NONCE_LEN {
+            bail!("sealed value is truncated");
+        }
+        let (nonce, ct) = raw.split_at(NONCE_LEN);
+        let plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ct, aad: context.as_bytes() })
+            .map_err(|_| anyhow!("cannot decrypt: wrong key or tampered value"))?;
+        String::from_utf8(plain).context("decrypted value is not UTF-8")
+    }
+}
+
+/// AES-256-GCM for secrets kept at rest (upstream SASL passwords in the
+/// Store, soju passwords in the session dirs). The key is kept out of the
+/// database and session files, so a backup archive or a leaked database
+/// alone does not reveal them. With the default `APP_KEY_FILE` it does sit
+/// in the data dir, so a copy of the whole data dir does.
+///
+/// The first key seals; retired keys stay around to open values sealed
+/// before a rotation until `reseal` has moved them to the current key.
+pub struct Keyring {
+    keys: Vec<AppKey>,
+}
+
+impl Keyring {
+    pub fn new(current: [u8; 32], retired: &[[u8; 32]]) -> Self {
+        let keys = std::iter::once(current).chain(retired.iter().copied()).map(AppKey::new).collect();
+        Keyring { keys }
+    }
+
+    /// The current key from `key` (APP_KEY) if set, else from the hex file
+    /// at `path`, generated (mode 0600) on first run. `retired` are hex keys
+    /// only used for opening.
+    pub fn load(key: Option<&str>, path: &Path, retired: &[String]) -> Result<Self> {
+        let current = match key {
+            Some(text) => parse_key(text).ok_or_else(|| anyhow!("APP_KEY must be 64 hex characters"))?,
+            None => load_or_create(path)?,
+        };
+        let retired = retired
+            .iter()
+            .map(|k| parse_key(k).ok_or_else(|| anyhow!("APP_RETIRED_KEYS: keys must be 64 hex characters")))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Keyring::new(current, &retired))
+    }
+
+    /// Id of the key new values are sealed with.
+    pub fn current_id(&self) -> &str {
+        &self.keys[0].id
+    }
+
+    /// Encrypt `plaintext`. `context` (e.g. `sasl:alice:libera`) is
+    /// authenticated but not stored: decrypting needs the same context, so
+    /// a sealed value copied to another row will not open.
+    pub fn seal(&self, context: &str, plaintext: &str) -> String {
+        let key = &self.keys[0];
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let payload = Payload { msg: plaintext.as_bytes(), aad: context.as_bytes() };
+        let ct = key
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .expect("AES-GCM encryption does not fail for in-memory input");
+        format!("{}{}:{}{}", PREFIX, key.id, hex::encode(nonce), hex::encode(ct))
+    }
+
+    pub fn open(&self, context: &str, sealed: &str) -> Result<String> {
+        if let Some(rest) = sealed.strip_prefix(PREFIX) {
+            let (id, body) = rest.split_once(':').ok_or_else(|| anyhow!("not a sealed value"))?;
+            let key = self
+                .keys
+                .iter()
+                .find(|k| k.id == id)
+                .ok_or_else(|| anyhow!("sealed with unknown key {}", id))?;
+            return key.decrypt(context, body);
+        }
+        let body = sealed.strip_prefix(PREFIX_V1).ok_or_else(|| anyhow!("not a sealed value"))?;
+        let mut last = anyhow!("no keys");
+        for key in &self.keys {
+            match key.decrypt(context, body) {
+                Ok(plain) => return Ok(plain),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    /// Re-seal a value with the current key. None when it already is.
+    pub fn reseal(&self, context: &str, sealed: &str) -> Result<Option<String>> {
+        if sealed.strip_prefix(PREFIX).and_then(|r| r.split_once(':')).map(|(id, _)| id)
+            == Some(self.current_id())
+        {
+            return Ok(None);
+        }
+        Ok(Some(self.seal(context, &self.open(context, sealed)?)))
+    }
+}
+
+/// Whether `value` looks like the output of `Keyring::seal`, as opposed to
+/// a secret written in plaintext by an older version.
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(PREFIX) || value.starts_with(PREFIX_V1)
+}
+
+/// A new random key, hex encoded, for APP_KEY or a key file.
+pub fn generate_key() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+fn parse_key(text: &str) -> Option<[u8; 32]> {
+    hex::decode(text.trim()).ok().and_then(|k| k.try_into().ok())
+}
+
+fn load_or_create(path: &Path) -> Result<[u8; 32]> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse_key(&text).ok_or_else(|| anyhow!("{} must hold 64 hex characters", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key = generate_key();
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)
+                .with_context(|| format!("create {}", path.display()))?;
+            writeln!(file, "{}", key)?;
+            file.sync_all()?;
+            tracing::info!("generated encryption key {}", path.display());
+            Ok(parse_key(&key).expect("generated key is valid"))
+        }
+        Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open() {
+        let path = std::env::temp_dir().join(format!("irssi-v5-key-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let keys = Keyring::load(None, &path, &[]).unwrap();
+        let sealed = keys.seal("sasl:alice:libera", "hunter2");
+        assert!(is_sealed(&sealed) && !sealed.contains("hunter2"));
+        assert!(sealed.starts_with(&format!("v2:{}:", keys.current_id())));
+        assert_ne!(sealed, keys.seal("sasl:alice:libera", "hunter2"), "fresh nonce each time");
+
+        // Same key from the file
+        let again = Keyring::load(None, &path, &[]).unwrap();
+        assert_eq!(again.open("sasl:alice:libera", &sealed).unwrap(), "hunter2");
+        assert!(again.open("sasl:bob:libera", &sealed).is_err());
+        assert!(Keyring::new([7; 32], &[]).open("sasl:alice:libera", &sealed).is_err());
+        let mut tampered = sealed.clone();
+        let flipped = if sealed.ends_with('0') { "1" } else { "0" };
+        tampered.replace_range(tampered.len() - 1.., flipped);
+        assert!(keys.open("sasl:alice:libera", &tampered).is_err());
+        assert!(!is_sealed("hunter2"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rotation() {
+        let old = Keyring::new([1; 32], &[]);
+        let sealed = old.seal("soju:alice", "pw");
+        // A v1 value (no key id) from before keys had ids
+        let v1 = format!("v1:{}", sealed.rsplit(':').next().unwrap());
+
+        let rotated = Keyring::new([2; 32], &[[1; 32]]);
+        assert_eq!(rotated.open("soju:alice", &sealed).unwrap(), "pw");
+        assert_eq!(rotated.open("soju:alice", &v1).unwrap(), "pw");
+        let resealed = rotated.reseal("soju:alice", &sealed).unwrap().unwrap();
+        assert!(rotated.reseal("soju:alice", &resealed).unwrap().is_none());
+        assert!(rotated.reseal("soju:alice", &v1).unwrap().is_some());
+
+        // Once the old key is dropped only the resealed value opens
+        let new_only = Keyring::new([2; 32], &[]);
+        assert_eq!(new_only.open("soju:alice", &resealed).unwrap(), "pw");
+        assert!(new_only.open("soju:alice", &sealed).unwrap_err().to_string().contains("unknown key"));
+    }
+}
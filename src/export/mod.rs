@@ -0,0 +1,150 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use zip::write::SimpleFileOptions;
+
+/// Files in a user's session directory (relative to it) that hold
+/// credentials. They are left out of an export unless secrets were asked for.
+const SECRET_FILES: [&str; 2] = ["soju_password", "weechat/sec.conf"];
+
+/// Client config files and the settings in them whose values are
+/// credentials. Only these values are redacted, so a client or setting
+/// that stores a secret has to be listed here.
+const SECRET_SETTINGS: [(&str, &[&str]); 3] = [
+    // irssi: `password = "…";` in servers, `sasl_password = "…";` in chatnets
+    ("config", &["password", "sasl_password"]),
+    // WeeChat: `<server>.password = "…"`, `<server>.sasl_password = "…"`
+    ("weechat/irc.conf", &["password", "sasl_password"]),
+    // senpai: `password "…"` or `password …`
+    ("senpai/senpai.scfg", &["password"]),
+];
+
+/// `<key> = "value"`, `<key> "value"` or `<key> value` for one of the
+/// secret keys, where the key may follow `{`, `;` or a WeeChat `server.`.
+static SECRET_SETTING: Lazy<Regex> = Lazy::new(|| {
+    let keys: Vec<&str> = SECRET_SETTINGS.iter().flat_map(|(_, keys)| keys.iter().copied()).collect();
+    Regex::new(&format!(
+        r#"(?m)((?:^|[\s{{;.])"?(?P<key>{})"?(?:[ \t]*=[ \t]*|[ \t]+))(?:"(?:[^"\\]|\\.)*"|[^\s;"]+)"#,
+        keys.join("|")
+    ))
+    .unwrap()
+});
+
+pub const REDACTED: &str = "[redacted]";
+
+/// Replace the secret values in a client config file, given its path
+/// relative to the session directory. Other files are returned as is.
+pub fn redact_config(path: &str, text: &str) -> String {
+    let Some((_, keys)) = SECRET_SETTINGS.iter().find(|(p, _)| *p == path) else {
+        return text.to_string();
+    };
+    SECRET_SETTING
+        .replace_all(text, |c: &regex::Captures| match keys.contains(&&c["key"]) {
+            true => format!(r#"{}"{}""#, &c[1], REDACTED),
+            false => c[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// Files from the user's session directory (irssi config, logs, scripts),
+/// as (archive path under `files/`, contents). Sockets and other special
+/// files are skipped.
+pub fn user_files(user_dir: &Path, include_secrets: bool) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut out = Vec::new();
+    if user_dir.exists() {
+        walk(user_dir, "", include_secrets, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// `rel` is `dir` relative to the session directory, "" at its top.
+fn walk(dir: &Path, rel: &str, include_secrets: bool, out: &mut Vec<(String, Vec<u8>)>) -> Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<std::io::Result<_>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = if rel.is_empty() { name } else { format!("{}/{}", rel, name) };
+        let ft = entry.file_type()?;
+        if ft.is_dir() {
+            walk(&entry.path(), &path, include_secrets, out)?;
+            continue;
+        }
+        if !ft.is_file() || (!include_secrets && SECRET_FILES.contains(&path.as_str())) {
+            continue;
+        }
+        let data = std::fs::read(entry.path())?;
+        let data = match include_secrets {
+            false if SECRET_SETTINGS.iter().any(|(p, _)| *p == path) => {
+                redact_config(&path, &String::from_utf8_lossy(&data)).into_bytes()
+            }
+            _ => data,
+        };
+        out.push((format!("files/{}", path), data));
+    }
+    Ok(())
+}
+
+/// Deflated zip of (path, contents) entries.
+pub fn zip(entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, data) in entries {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_config() {
+        let conf = r#"chatnets = {
+  libera = {
+    sasl_username = "alice/libera";
+    sasl_password = "s3cr\"et";
+  };
+};
+servers = ({ address = "soju"; password = "hunter2"; port = 6667; });
+"#;
+        let out = redact_config("config", conf);
+        assert!(out.contains(r#"sasl_password = "[redacted]";"#), "{}", out);
+        assert!(out.contains(r#"sasl_username = "alice/libera";"#));
+        assert!(!out.contains("s3cr"));
+        assert!(!out.contains("hunter2"), "{}", out);
+        assert!(out.contains(r#"address = "soju";"#));
+
+        let weechat = "[server]\nlibera.sasl_password = \"pw1\"\nlibera.password = \"pw2\"\nlibera.nicks = \"alice\"\n";
+        let out = redact_config("weechat/irc.conf", weechat);
+        assert!(!out.contains("pw1") && !out.contains("pw2"), "{}", out);
+        assert!(out.contains("libera.nicks = \"alice\""));
+
+        let senpai = "address soju\npassword pw3\npassword-cmd printenv IRSSI_V5_SOJU_PASSWORD\n";
+        let out = redact_config("senpai/senpai.scfg", senpai);
+        assert_eq!(out, "address soju\npassword \"[redacted]\"\npassword-cmd printenv IRSSI_V5_SOJU_PASSWORD\n");
+
+        // Only listed files are touched
+        assert_eq!(redact_config("notes/password.txt", "password = x"), "password = x");
+    }
+
+    #[test]
+    fn test_user_files_leave_out_secrets() {
+        let dir = std::env::temp_dir().join(format!("irssi-v5-export-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("weechat")).unwrap();
+        std::fs::write(dir.join("soju_password"), "pw").unwrap();
+        std::fs::write(dir.join("weechat/sec.conf"), "data").unwrap();
+        std::fs::write(dir.join("weechat/irc.conf"), "libera.password = \"pw\"\n").unwrap();
+
+        let files = user_files(&dir, false).unwrap();
+        let names: Vec<&str> = files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["files/weechat/irc.conf"]);
+        assert_eq!(files[0].1, b"libera.password = \"[redacted]\"\n");
+        assert_eq!(user_files(&dir, true).unwrap().len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, Notify};
+use tokio::time::timeout;
+use tokio_native_tls::native_tls;
+use tracing::{info, warn};
+
+use crate::soju::Manager as SojuManager;
+use crate::store::Store;
+
+/// Longest line accepted from a client before it has logged in.
+const MAX_LINE: usize = 8192;
+/// Time a client gets to log in.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(60);
+/// SASL payloads travel in chunks of this many base64 characters.
+const SASL_CHUNK: usize = 400;
+const SERVER_NAME: &str = "irssi-v5";
+
+/// App password as shown to the user once: 32 hex characters.
+pub fn new_password() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// What the Store keeps of an app password. They are random, so a plain
+/// digest is enough.
+pub fn password_hash(password: &str) -> String {
+    hex::encode(Sha256::digest(password.as_bytes()))
+}
+
+/// The account part of a soju login, e.g. `alice` for `alice/libera@phone`.
+fn base_user(login: &str) -> &str {
+    login.split(['/', '@']).next().unwrap_or(login)
+}
+
+/// Command and parameters of a client line, ignoring tags and prefix.
+fn parse_line(line: &str) -> (String, Vec<&str>) {
+    let mut rest = line.trim_end_matches(['\r', '\n']);
+    if rest.starts_with('@') {
+        rest = rest.split_once(' ').map_or("", |(_, r)| r);
+    }
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ').map_or("", |(_, r)| r);
+    }
+    let (head, trailing) = match rest.split_once(" :") {
+        Some((h, t)) => (h, Some(t)),
+        None => (rest, None),
+    };
+    let mut words = head.split(' ').filter(|w| !w.is_empty());
+    let command = words.next().unwrap_or("").to_ascii_uppercase();
+    let mut params: Vec<&str> = words.collect();
+    params.extend(trailing);
+    (command, params)
+}
+
+/// `AUTHENTICATE` lines carrying a SASL PLAIN payload.
+fn plain_lines(authzid: &str, authcid: &str, password: &str) -> Vec<String> {
+    let payload = BASE64.encode(format!("{}\0{}\0{}", authzid, authcid, password));
+    let mut lines: Vec<String> = payload
+        .as_bytes()
+        .chunks(SASL_CHUNK)
+        .map(|c| format!("AUTHENTICATE {}", String::from_utf8_lossy(c)))
+        .collect();
+    if payload.len() % SASL_CHUNK == 0 {
+        lines.push("AUTHENTICATE +".to_string());
+    }
+    lines
+}
+
+/// (authzid, authcid, password) from a decoded SASL PLAIN payload.
+fn decode_plain(payload: &str) -> Option<(String, String, String)> {
+    let raw = BASE64.decode(payload).ok()?;
+    let text = String::from_utf8(raw).ok()?;
+    let mut parts = text.splitn(3, '\0');
+    Some((parts.next()?.to_string(), parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// A client connection that logged in with an app password.
+struct Conn {
+    username: String,
+    app_password: i64,
+    close: Arc<Notify>,
+}
+
+/// IRC listener for native clients (weechat, mobile apps) in front of
+/// soju. soju knows one password per user, so clients log in with one of
+/// the user's app passwords instead — by PASS or SASL PLAIN, with any soju
+/// login (`alice/libera@phone`) — and the proxy swaps in the soju password
+/// before passing the line on. After login it only copies bytes.
+pub struct Proxy {
+    store: Store,
+    soju: Arc<SojuManager>,
+    soju_addr: String,
+    tls: Option<tokio_native_tls::TlsAcceptor>,
+    conns: DashMap<u64, Conn>,
+    next_id: AtomicU64,
+}
+
+impl Proxy {
+    /// `tls` is a PEM certificate chain and PKCS#8 key; without it the
+    /// listener speaks plain IRC, for use behind a TLS terminator.
+    pub fn new(
+        store: Store,
+        soju: Arc<SojuManager>,
+        soju_addr: String,
+        tls: Option<(&Path, &Path)>,
+    ) -> Result<Arc<Self>> {
+        let tls = match tls {
+            Some((cert, key)) => {
+                let cert = std::fs::read(cert).with_context(|| format!("read {}", cert.display()))?;
+                let key = std::fs::read(key).with_context(|| format!("read {}", key.display()))?;
+                let identity = native_tls::Identity::from_pkcs8(&cert, &key).context("IRC proxy TLS identity")?;
+                Some(native_tls::TlsAcceptor::new(identity)?.into())
+            }
+            None => None,
+        };
+        Ok(Arc::new(Proxy {
+            store,
+            soju,
+            soju_addr,
+            tls,
+            conns: DashMap::new(),
+            next_id: AtomicU64::new(1),
+        }))
+    }
+
+    /// Close live connections that logged in with app password `id`.
+    pub fn revoke(&self, id: i64) {
+        self.conns.iter().filter(|c| c.app_password == id).for_each(|c| c.close.notify_one());
+    }
+
+    /// Close every live connection of `username`.
+    pub fn disconnect_user(&self, username: &str) {
+        self.conns.iter().filter(|c| c.username == username).for_each(|c| c.close.notify_one());
+    }
+
+    pub async fn serve(self: Arc<Self>, listen: &str) -> Result<()> {
+        let listener = TcpListener::bind(listen).await.with_context(|| format!("bind {}", listen))?;
+        info!("IRC proxy on {}{}", listen, if self.tls.is_some() { " (TLS)" } else { "" });
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let proxy = Arc::clone(&self);
+            tokio::spawn(async move {
+                let result = match &proxy.tls {
+                    Some(tls) => match timeout(LOGIN_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => proxy.handle(stream, peer).await,
+                        Ok(Err(e)) => Err(e.into()),
+                        Err(_) => Err(anyhow!("TLS handshake timed out")),
+                    },
+                    None => proxy.handle(stream, peer).await,
+                };
+                if let Err(e) = result {
+                    info!("IRC proxy {}: {:#}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn handle(self: &Arc<Self>, client: impl Stream + 'static, peer: SocketAddr) -> Result<()> {
+        let upstream = TcpStream::connect(&self.soju_addr)
+            .await
+            .with_context(|| format!("connect to soju at {}", self.soju_addr))?;
+        let (client_read, client_write) = tokio::io::split(client);
+        let client_write = Arc::new(Mutex::new(client_write));
+        let (mut soju_read, mut soju_write) = upstream.into_split();
+
+        // soju → client, untouched
+        let to_client = {
+            let client_write = Arc::clone(&client_write);
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 16 * 1024];
+                loop {
+                    let n = soju_read.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    client_write.lock().await.write_all(&buf[..n]).await?;
+                }
+                Ok::<_, std::io::Error>(())
+            })
+        };
+
+        let mut reader = BufReader::new(client_read);
+        let login = timeout(LOGIN_TIMEOUT, self.login(&mut reader, &mut soju_write, peer)).await;
+        let (username, app_password) = match login {
+            Ok(Ok(ok)) => ok,
+            Ok(Err(LoginError::Rejected(numeric, message))) => {
+                to_client.abort();
+                let mut w = client_write.lock().await;
+                w.write_all(format!(":{} {} * :{}\r\nERROR :Closing link\r\n", SERVER_NAME, numeric, message).as_bytes())
+                    .await?;
+                return Err(anyhow!("login rejected: {}", message));
+            }
+            Ok(Err(LoginError::Io(e))) => {
+                to_client.abort();
+                return Err(e);
+            }
+            Err(_) => {
+                to_client.abort();
+                return Err(anyhow!("no login within {:?}", LOGIN_TIMEOUT));
+            }
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let close = Arc::new(Notify::new());
+        self.conns.insert(id, Conn { username: username.clone(), app_password, close: Arc::clone(&close) });
+        info!("IRC proxy: {} logged in from {} (app password {})", username, peer, app_password);
+
+        // client → soju: what login read ahead, then the rest as is
+        let to_client_abort = to_client.abort_handle();
+        let to_soju = async {
+            soju_write.write_all(reader.buffer()).await?;
+            tokio::io::copy(&mut reader.into_inner(), &mut soju_write).await?;
+            Ok::<_, std::io::Error>(())
+        };
+        tokio::select! {
+            _ = to_soju => {}
+            _ = to_client => {}
+            _ = close.notified() => {
+                let _ = client_write.lock().await.write_all(b"ERROR :Closing link (app password revoked)\r\n").await;
+            }
+        }
+        to_client_abort.abort();
+        self.conns.remove(&id);
+        Ok(())
+    }
+
+    /// Pass registration lines on to soju until the client has logged in
+    /// with an app password (and sent USER), rewriting the credentials.
+    /// Returns the user and app password id.
+    async fn login<R, W>(&self, reader: &mut R, soju: &mut W, peer: SocketAddr) -> Result<(String, i64), LoginError>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut pass: Option<String> = None;
+        let mut sasl = String::new();
+        let mut logged_in: Option<(String, i64)> = None;
+        let mut user_sent = false;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = (&mut *reader).take(MAX_LINE as u64).read_line(&mut line).await?;
+            if n == 0 {
+                return Err(anyhow!("client closed the connection").into());
+            }
+            if !line.ends_with('\n') {
+                return Err(LoginError::Rejected("417", "Line too long".into()));
+            }
+            let (command, params) = parse_line(&line);
+            let mut out = Vec::new();
+            match command.as_str() {
+                "PASS" => {
+                    let arg = params.first().copied().unwrap_or("");
+                    match arg.split_once(':') {
+                        Some((login, password)) => {
+                            let (user, id, soju_password) = self.verify(login, password, peer, "464").await?;
+                            out.push(format!("PASS {}:{}", login, soju_password));
+                            logged_in = Some((user, id));
+                        }
+                        None => pass = Some(arg.to_string()),
+                    }
+                }
+                "USER" => {
+                    if let Some(password) = pass.take() {
+                        let login = params.first().copied().unwrap_or("");
+                        let (user, id, soju_password) = self.verify(login, &password, peer, "464").await?;
+                        out.push(format!("PASS {}", soju_password));
+                        logged_in = Some((user, id));
+                    }
+                    out.push(line.trim_end().to_string());
+                    user_sent = true;
+                }
+                "AUTHENTICATE" => {
+                    let arg = params.first().copied().unwrap_or("");
+                    if arg.eq_ignore_ascii_case("PLAIN") || arg == "*" || logged_in.is_some() {
+                        sasl.clear();
+                        out.push(line.trim_end().to_string());
+                    } else if arg.eq_ignore_ascii_case("EXTERNAL") {
+                        return Err(LoginError::Rejected("908", "PLAIN".into()));
+                    } else {
+                        if arg != "+" {
+                            sasl.push_str(arg);
+                        }
+                        if sasl.len() > 4 * SASL_CHUNK {
+                            return Err(LoginError::Rejected("905", "SASL message too long".into()));
+                        }
+                        if arg.len() < SASL_CHUNK {
+                            let Some((authzid, authcid, password)) = decode_plain(&sasl) else {
+                                return Err(LoginError::Rejected("904", "SASL authentication failed".into()));
+                            };
+                            sasl.clear();
+                            let (user, id, soju_password) = self.verify(&authcid, &password, peer, "904").await?;
+                            out.extend(plain_lines(&authzid, &authcid, &soju_password));
+                            logged_in = Some((user, id));
+                        }
+                    }
+                }
+                _ => out.push(line.trim_end().to_string()),
+            }
+            for l in out {
+                soju.write_all(format!("{}\r\n", l).as_bytes()).await?;
+            }
+            if user_sent {
+                if let Some(ok) = logged_in.take() {
+                    return Ok(ok);
+                }
+            }
+        }
+    }
+
+    /// Check an app password for `login`. Returns the user, the app
+    /// password's id and the soju password to send instead.
+    async fn verify(
+        &self,
+        login: &str,
+        password: &str,
+        peer: SocketAddr,
+        numeric: &'static str,
+    ) -> Result<(String, i64, String), LoginError> {
+        let rejected = || LoginError::Rejected(numeric, "Invalid username or app password".into());
+        let user = base_user(login).to_string();
+        let id = self.store.find_app_password(&user, &password_hash(password)).await?.ok_or_else(rejected)?;
+        if self.store.suspension(&user).await?.is_some_and(|s| !s.is_expired()) {
+            return Err(LoginError::Rejected(numeric, "Account suspended".into()));
+        }
+        let soju_password = self.soju.read_password(&user).await?.ok_or_else(rejected)?;
+        if let Err(e) = self.store.touch_app_password(id, &peer.ip().to_string()).await {
+            warn!("app password {} last use not recorded: {:#}", id, e);
+        }
+        Ok((user, id, soju_password))
+    }
+}
+
+enum LoginError {
+    /// Numeric and message to send the client before closing
+    Rejected(&'static str, String),
+    Io(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for LoginError {
+    fn from(e: E) -> Self {
+        LoginError::Io(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_helpers() {
+        assert_eq!(base_user("alice/libera@phone"), "alice");
+        assert_eq!(base_user("alice@phone"), "alice");
+        assert_eq!(base_user("alice"), "alice");
+
+        let (cmd, params) = parse_line("@time=x :nick!u@h pass secret\r\n");
+        assert_eq!((cmd.as_str(), params), ("PASS", vec!["secret"]));
+        let (cmd, params) = parse_line("USER alice 0 * :Alice Liddell\r\n");
+        assert_eq!((cmd.as_str(), params), ("USER", vec!["alice", "0", "*", "Alice Liddell"]));
+
+        let lines = plain_lines("", "alice/libera", "pw");
+        assert_eq!(lines.len(), 1);
+        let payload = lines[0].strip_prefix("AUTHENTICATE ").unwrap();
+        assert_eq!(decode_plain(payload), Some(("".into(), "alice/libera".into(), "pw".into())));
+        assert_eq!(decode_plain("not base64!"), None);
+
+        // A payload of exactly one chunk is followed by "+"
+        let password = "x".repeat(300 - "alice".len() - 2);
+        let lines = plain_lines("", "alice", &password);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], "AUTHENTICATE +");
+        assert_eq!(lines[0].len(), "AUTHENTICATE ".len() + SASL_CHUNK);
+
+        assert_eq!(password_hash("a"), password_hash("a"));
+        assert_eq!(new_password().len(), 32);
+    }
+}
@@ -0,0 +1,661 @@
+use std::fmt::{self, Write as _};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::io::AsyncWriteExt;
+
+pub mod settings;
+
+/// A value in irssi's config format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// `"quoted string"`, stored unescaped
+    Str(String),
+    /// Unquoted word, e.g. `yes` or `6667`
+    Bare(String),
+    /// `{ key = value; ... }`
+    Block(Vec<Node>),
+    /// `( value, ... )`
+    List(Vec<Node>),
+}
+
+/// One entry inside a block or list. Comments are kept so a user's notes
+/// survive a managed update.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Pair(String, Value),
+    Item(Value),
+    Comment(String),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) | Value::Bare(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// A whole config file: the top level is an unbraced block.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub nodes: Vec<Node>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut p = Parser { src: text.as_bytes(), pos: 0 };
+        let nodes = p.block_body(None)?;
+        Ok(Config { nodes })
+    }
+
+    /// Look up a value by path, e.g. `["settings", "core", "nick"]`.
+    pub fn get(&self, path: &[&str]) -> Option<&Value> {
+        let (last, parents) = path.split_last()?;
+        let mut nodes = &self.nodes;
+        for key in parents {
+            match get(nodes, key)? {
+                Value::Block(inner) => nodes = inner,
+                _ => return None,
+            }
+        }
+        get(nodes, last)
+    }
+}
+
+// ── Parsing ───────────────────────────────────────────────────────────────────
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn line(&self) -> usize {
+        self.src[..self.pos].iter().filter(|&&b| b == b'\n').count() + 1
+    }
+
+    fn err(&self, msg: &str) -> anyhow::Error {
+        anyhow!("line {}: {}", self.line(), msg)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    /// Skip whitespace and stray separators, collecting comments.
+    fn skip(&mut self, separators: &[u8], comments: &mut Vec<Node>) {
+        while let Some(b) = self.peek() {
+            if b.is_ascii_whitespace() || separators.contains(&b) {
+                self.pos += 1;
+            } else if b == b'#' {
+                let start = self.pos + 1;
+                while self.peek().is_some_and(|b| b != b'\n') {
+                    self.pos += 1;
+                }
+                let text = String::from_utf8_lossy(&self.src[start..self.pos]).into_owned();
+                comments.push(Node::Comment(text));
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Pairs up to `close` (or end of input for the top level).
+    fn block_body(&mut self, close: Option<u8>) -> Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip(b";", &mut nodes);
+            match self.peek() {
+                None if close.is_none() => return Ok(nodes),
+                None => return Err(self.err("unexpected end of file, missing '}'")),
+                Some(b) if Some(b) == close => {
+                    self.pos += 1;
+                    return Ok(nodes);
+                }
+                Some(_) => {}
+            }
+            let key = self.scalar().map_err(|_| self.err("expected a key"))?;
+            let key = key.as_str().unwrap_or_default().to_string();
+            self.skip(b"", &mut nodes);
+            if self.peek() != Some(b'=') {
+                return Err(self.err(&format!("expected '=' after '{}'", key)));
+            }
+            self.pos += 1;
+            self.skip(b"", &mut nodes);
+            let value = self.value()?;
+            nodes.push(Node::Pair(key, value));
+        }
+    }
+
+    fn list_body(&mut self) -> Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip(b",", &mut nodes);
+            match self.peek() {
+                None => return Err(self.err("unexpected end of file, missing ')'")),
+                Some(b')') => {
+                    self.pos += 1;
+                    return Ok(nodes);
+                }
+                Some(_) => nodes.push(Node::Item(self.value()?)),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                Ok(Value::Block(self.block_body(Some(b'}'))?))
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                Ok(Value::List(self.list_body()?))
+            }
+            _ => self.scalar(),
+        }
+    }
+
+    fn scalar(&mut self) -> Result<Value> {
+        if self.peek() == Some(b'"') {
+            self.pos += 1;
+            let mut out = Vec::new();
+            loop {
+                match self.peek() {
+                    None => return Err(self.err("unterminated string")),
+                    Some(b'"') => break,
+                    Some(b'\\') => {
+                        self.pos += 1;
+                        match self.peek() {
+                            Some(b'n') => out.push(b'\n'),
+                            Some(b't') => out.push(b'\t'),
+                            Some(b) => out.push(b),
+                            None => return Err(self.err("unterminated string")),
+                        }
+                    }
+                    Some(b) => out.push(b),
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            return Ok(Value::Str(String::from_utf8_lossy(&out).into_owned()));
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(is_bare) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.err("expected a value"));
+        }
+        Ok(Value::Bare(String::from_utf8_lossy(&self.src[start..self.pos]).into_owned()))
+    }
+}
+
+fn is_bare(b: u8) -> bool {
+    !b.is_ascii_whitespace() && !b"=;,{}()#\"".contains(&b)
+}
+
+// ── Serializing ───────────────────────────────────────────────────────────────
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            // Blank line between top-level sections, comments stay attached
+            if i > 0 && matches!(node, Node::Pair(..)) && !matches!(self.nodes[i - 1], Node::Comment(_)) {
+                out.push('\n');
+            }
+            write_node(&mut out, node, 0, false);
+        }
+        f.write_str(&out)
+    }
+}
+
+fn write_node(out: &mut String, node: &Node, indent: usize, comma: bool) {
+    out.extend(std::iter::repeat_n(' ', indent));
+    match node {
+        Node::Comment(text) => {
+            let _ = writeln!(out, "#{}", text);
+            return;
+        }
+        Node::Pair(key, value) => {
+            write_key(out, key);
+            out.push_str(" = ");
+            write_value(out, value, indent);
+            out.push(';');
+        }
+        Node::Item(value) => {
+            write_value(out, value, indent);
+            if comma {
+                out.push(',');
+            }
+        }
+    }
+    out.push('\n');
+}
+
+fn write_key(out: &mut String, key: &str) {
+    if !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+        out.push_str(key);
+    } else {
+        write_string(out, key);
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_value(out: &mut String, value: &Value, indent: usize) {
+    let (open, close, nodes) = match value {
+        Value::Str(s) => return write_string(out, s),
+        Value::Bare(s) => return out.push_str(s),
+        Value::Block(nodes) => ('{', '}', nodes),
+        Value::List(nodes) => ('(', ')', nodes),
+    };
+    if nodes.is_empty() {
+        let _ = write!(out, "{} {}", open, close);
+        return;
+    }
+    out.push(open);
+    out.push('\n');
+    let last_item = nodes.iter().rposition(|n| matches!(n, Node::Item(_)));
+    for (i, node) in nodes.iter().enumerate() {
+        write_node(out, node, indent + 2, Some(i) != last_item);
+    }
+    out.extend(std::iter::repeat_n(' ', indent));
+    out.push(close);
+}
+
+// ── Editing helpers ───────────────────────────────────────────────────────────
+
+fn get<'a>(nodes: &'a [Node], key: &str) -> Option<&'a Value> {
+    nodes.iter().find_map(|n| match n {
+        Node::Pair(k, v) if k == key => Some(v),
+        _ => None,
+    })
+}
+
+fn get_mut<'a>(nodes: &'a mut [Node], key: &str) -> Option<&'a mut Value> {
+    nodes.iter_mut().find_map(|n| match n {
+        Node::Pair(k, v) if k == key => Some(v),
+        _ => None,
+    })
+}
+
+/// The block under `key`, created (or replacing a non-block) if needed.
+fn block_mut<'a>(nodes: &'a mut Vec<Node>, key: &str) -> &'a mut Vec<Node> {
+    container_mut(nodes, key, || Value::Block(Vec::new()))
+}
+
+fn list_mut<'a>(nodes: &'a mut Vec<Node>, key: &str) -> &'a mut Vec<Node> {
+    container_mut(nodes, key, || Value::List(Vec::new()))
+}
+
+fn container_mut<'a>(nodes: &'a mut Vec<Node>, key: &str, empty: fn() -> Value) -> &'a mut Vec<Node> {
+    let fits = |v: &Value| std::mem::discriminant(v) == std::mem::discriminant(&empty());
+    match nodes.iter().position(|n| matches!(n, Node::Pair(k, _) if k == key)) {
+        Some(i) => {
+            if let Node::Pair(_, v) = &mut nodes[i] {
+                if !fits(v) {
+                    *v = empty();
+                }
+            }
+        }
+        None => nodes.push(Node::Pair(key.to_string(), empty())),
+    }
+    match get_mut(nodes, key) {
+        Some(Value::Block(inner)) | Some(Value::List(inner)) => inner,
+        _ => unreachable!("container was just inserted"),
+    }
+}
+
+/// Set a string, leaving it alone if it already reads the same (quoted or not).
+fn set(nodes: &mut Vec<Node>, key: &str, value: &str) {
+    match get_mut(nodes, key) {
+        Some(v) if v.as_str() == Some(value) => {}
+        Some(v) => *v = Value::Str(value.to_string()),
+        None => nodes.push(Node::Pair(key.to_string(), Value::Str(value.to_string()))),
+    }
+}
+
+fn remove(nodes: &mut Vec<Node>, key: &str) {
+    nodes.retain(|n| !matches!(n, Node::Pair(k, _) if k == key));
+}
+
+fn set_default(nodes: &mut Vec<Node>, key: &str, value: &str) {
+    if get(nodes, key).is_none() {
+        nodes.push(Node::Pair(key.to_string(), Value::Str(value.to_string())));
+    }
+}
+
+// ── Commands ──────────────────────────────────────────────────────────────────
+
+/// irssi commands the web UI may run in a user's irssi, with a check for
+/// their arguments.
+type ArgCheck = fn(&str) -> bool;
+
+const COMMANDS: [(&str, ArgCheck); 6] = [
+    ("join", channel_args),
+    ("part", channel_args),
+    ("away", |a| a.chars().count() <= 300),
+    ("nick", |a| !a.is_empty() && a.len() <= 30 && !a.contains(' ')),
+    ("reload", str::is_empty),
+    ("save", str::is_empty),
+];
+
+/// `#a,#b [key,key]`
+fn channel_args(args: &str) -> bool {
+    let mut words = args.split(' ');
+    let chans = words.next().unwrap_or("");
+    !chans.is_empty()
+        && chans.split(',').all(|c| c.len() <= 50 && c.starts_with(['#', '&', '+', '!']))
+        && words.count() <= 1
+}
+
+/// Build the command line for an allowed command, e.g.
+/// `("join", "#rust")` → `/join #rust`.
+pub fn command_line(name: &str, args: &str) -> Result<String> {
+    let name = name.to_ascii_lowercase();
+    let args = args.trim();
+    let Some((_, check)) = COMMANDS.iter().find(|(n, _)| *n == name) else {
+        let allowed: Vec<&str> = COMMANDS.iter().map(|(n, _)| *n).collect();
+        bail!("command must be one of {}", allowed.join(", "));
+    };
+    if args.chars().any(char::is_control) || !check(args) {
+        bail!("invalid arguments for /{}", name);
+    }
+    Ok(if args.is_empty() { format!("/{}", name) } else { format!("/{} {}", name, args) })
+}
+
+// ── Managed settings ──────────────────────────────────────────────────────────
+
+/// What irssi-v5 owns in a user's config: the soju chatnet and server
+/// entries and the terminal charset. Everything else is the user's.
+///
+/// The soju password is not part of it: irssi gets it at startup (see
+/// `session::SojuLogin`) and the config must not hold it.
+pub struct Managed<'a> {
+    pub username: &'a str,
+    /// Network name in soju, also used as the chatnet name
+    pub network: &'a str,
+    pub soju_host: &'a str,
+    pub soju_port: &'a str,
+}
+
+/// Chatnets that log in to soju as `username` (`<user>/<network>`).
+pub fn soju_chatnets(conf: &Config, username: &str) -> Vec<String> {
+    let prefix = format!("{}/", username);
+    let Some(Value::Block(chatnets)) = get(&conf.nodes, "chatnets") else {
+        return Vec::new();
+    };
+    chatnets
+        .iter()
+        .filter_map(|n| match n {
+            Node::Pair(name, Value::Block(net))
+                if get(net, "sasl_username")
+                    .and_then(Value::as_str)
+                    .is_some_and(|u| u.starts_with(&prefix)) =>
+            {
+                Some(name.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Merge the managed blocks into `conf`. Returns whether anything changed.
+///
+/// The primary network's chatnet and server are created if missing; every
+/// chatnet that authenticates to soju as this user loses any stored
+/// password (older versions wrote it, irssi may have saved it back), and
+/// every server using one of them gets the current soju address. Nick and
+/// real name are only filled in when absent.
+pub fn apply_managed(conf: &mut Config, m: &Managed) -> bool {
+    let before = conf.clone();
+
+    soju_network(conf, m, m.network);
+    let soju = soju_chatnets(conf, m.username);
+    let chatnets = block_mut(&mut conf.nodes, "chatnets");
+    for name in &soju {
+        remove(block_mut(chatnets, name), "sasl_password");
+    }
+    for node in list_mut(&mut conf.nodes, "servers").iter_mut() {
+        let Node::Item(Value::Block(server)) = node else { continue };
+        if get(server, "chatnet").and_then(Value::as_str).is_some_and(|c| soju.iter().any(|s| s == c)) {
+            set(server, "address", m.soju_host);
+            set(server, "port", m.soju_port);
+            set(server, "use_ssl", "no");
+        }
+    }
+
+    let settings = block_mut(&mut conf.nodes, "settings");
+    let core = block_mut(settings, "core");
+    for key in ["real_name", "user_name", "nick"] {
+        set_default(core, key, m.username);
+    }
+    set(block_mut(settings, "fe-text"), "term_charset", "UTF-8");
+    set(block_mut(settings, "fe-common/core"), "term_charset", "UTF-8");
+
+    *conf != before
+}
+
+/// Add a chatnet and autoconnecting server for another of the user's soju
+/// networks. Returns whether anything changed.
+pub fn add_network(conf: &mut Config, m: &Managed, network: &str) -> bool {
+    let before = conf.clone();
+    soju_network(conf, m, network);
+    *conf != before
+}
+
+/// Chatnet `network` logging in to soju as `<user>/<network>`, and a server
+/// entry for it if there is none.
+fn soju_network(conf: &mut Config, m: &Managed, network: &str) {
+    let chatnets = block_mut(&mut conf.nodes, "chatnets");
+    let net = block_mut(chatnets, network);
+    set(net, "type", "IRC");
+    set(net, "sasl_mechanism", "PLAIN");
+    set(net, "sasl_username", &format!("{}/{}", m.username, network));
+
+    let servers = list_mut(&mut conf.nodes, "servers");
+    let exists = servers.iter().any(|n| {
+        matches!(n, Node::Item(Value::Block(s)) if get(s, "chatnet").and_then(Value::as_str) == Some(network))
+    });
+    if !exists {
+        servers.push(Node::Item(Value::Block(vec![
+            Node::Pair("address".into(), Value::Str(m.soju_host.into())),
+            Node::Pair("port".into(), Value::Str(m.soju_port.into())),
+            Node::Pair("use_ssl".into(), Value::Str("no".into())),
+            Node::Pair("chatnet".into(), Value::Str(network.into())),
+            Node::Pair("autoconnect".into(), Value::Str("yes".into())),
+        ])));
+    }
+}
+
+/// Check the managed entries are in place.
+pub fn validate(conf: &Config, m: &Managed) -> Result<()> {
+    let username = format!("{}/{}", m.username, m.network);
+    if conf.get(&["chatnets", m.network, "sasl_username"]).and_then(Value::as_str) != Some(username.as_str()) {
+        bail!("chatnet {} is missing its soju login", m.network);
+    }
+    if soju_chatnets(conf, m.username).iter().any(|c| conf.get(&["chatnets", c, "sasl_password"]).is_some()) {
+        bail!("a soju chatnet still holds a password");
+    }
+    let Some(Value::List(servers)) = conf.get(&["servers"]) else {
+        bail!("no servers list");
+    };
+    let has_server = servers.iter().any(|n| {
+        matches!(n, Node::Item(Value::Block(s)) if get(s, "chatnet").and_then(Value::as_str) == Some(m.network))
+    });
+    if !has_server {
+        bail!("no server for chatnet {}", m.network);
+    }
+    Ok(())
+}
+
+/// Read and parse a config; a missing file reads as empty.
+pub async fn read_config(path: &Path) -> Result<Config> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(text) => Config::parse(&text).with_context(|| format!("{} is not a valid irssi config", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+/// Replace the config at `path` atomically, after checking the text parses
+/// back to the same config.
+pub async fn write_config(path: &Path, conf: &Config) -> Result<()> {
+    let text = conf.to_string();
+    if Config::parse(&text).ok().as_ref() != Some(conf) {
+        bail!("serialized config does not round-trip");
+    }
+    let tmp = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp).await.with_context(|| format!("create {}", tmp.display()))?;
+    // It may hold passwords for the user's own servers
+    file.set_permissions(std::fs::Permissions::from_mode(0o600)).await?;
+    file.write_all(text.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&tmp, path).await.with_context(|| format!("rename {}", tmp.display()))
+}
+
+/// Bring the config at `path` up to date, creating it if missing. The file
+/// is replaced only when something changed. A config the user has broken
+/// is left as it is. Returns whether it was written.
+pub async fn sync_config(path: &Path, m: &Managed<'_>) -> Result<bool> {
+    let mut conf = read_config(path).await?;
+    if !apply_managed(&mut conf, m) && path.exists() {
+        return Ok(false);
+    }
+    validate(&conf, m)?;
+    write_config(path, &conf).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANAGED: Managed = Managed {
+        username: "alice",
+        network: "libera",
+        soju_host: "bouncer",
+        soju_port: "6697",
+    };
+
+    #[test]
+    fn test_parse_and_serialize() {
+        let text = r#"# my config
+servers = (
+  { address = "irc.example.org"; port = 6667; },
+  { address = "x"; }
+);
+settings = {
+  core = { nick = "al\"ice"; };
+  "fe-common/core" = { theme = "default"; }; # trailing
+};
+aliases = { J = "join"; EMPTY = ( ); };
+"#;
+        let conf = Config::parse(text).unwrap();
+        assert_eq!(conf.get(&["settings", "core", "nick"]), Some(&Value::Str("al\"ice".into())));
+        assert_eq!(conf.get(&["settings", "fe-common/core", "theme"]).and_then(Value::as_str), Some("default"));
+        let Some(Value::List(servers)) = conf.get(&["servers"]) else { panic!() };
+        assert_eq!(servers.len(), 2);
+
+        let out = conf.to_string();
+        assert!(out.starts_with("# my config\n"), "{}", out);
+        assert!(out.contains(r#""fe-common/core" = {"#), "{}", out);
+        assert!(out.contains("# trailing"));
+        assert_eq!(Config::parse(&out).unwrap(), conf);
+
+        assert!(Config::parse("settings = { core = {").unwrap_err().to_string().contains("missing '}'"));
+        assert!(Config::parse("a = \"x").is_err());
+        assert!(Config::parse("a b;").unwrap_err().to_string().starts_with("line 1"));
+    }
+
+    #[test]
+    fn test_command_line() {
+        assert_eq!(command_line("JOIN", " #rust,#irssi key ").unwrap(), "/join #rust,#irssi key");
+        assert_eq!(command_line("away", "").unwrap(), "/away");
+        assert_eq!(command_line("away", "lunch").unwrap(), "/away lunch");
+        assert_eq!(command_line("reload", "").unwrap(), "/reload");
+        assert!(command_line("reload", "x").is_err());
+        assert!(command_line("join", "rust").is_err());
+        assert!(command_line("join", "#a\n/quit").is_err());
+        assert!(command_line("exec", "rm -rf /").is_err());
+        assert!(command_line("quit", "").is_err());
+    }
+
+    #[test]
+    fn test_apply_managed() {
+        // Fresh config
+        let mut conf = Config::default();
+        assert!(apply_managed(&mut conf, &MANAGED));
+        validate(&conf, &MANAGED).unwrap();
+        assert_eq!(conf.get(&["settings", "core", "nick"]).and_then(Value::as_str), Some("alice"));
+        assert!(!apply_managed(&mut conf, &MANAGED), "second apply is a no-op");
+
+        // An existing config written by the old template and then edited
+        let old = r#"chatnets = {
+  libera = { type = "IRC"; sasl_mechanism = "PLAIN"; sasl_username = "alice/libera"; sasl_password = "oldpw"; };
+  oftc = { type = "IRC"; sasl_username = "alice/oftc"; sasl_password = "oldpw"; };
+  other = { type = "IRC"; };
+};
+servers = (
+  { address = "soju"; port = 6667; use_ssl = no; chatnet = "libera"; autoconnect = no; },
+  { address = "soju"; port = 6667; chatnet = "oftc"; },
+  { address = "irc.other.net"; port = 6697; chatnet = "other"; }
+);
+settings = {
+  core = { nick = "ally"; real_name = "Alice"; };
+  "fe-text" = { term_charset = "latin1"; actlist_sort = "refnum"; };
+};
+# keep me
+"#;
+        let mut conf = Config::parse(old).unwrap();
+        assert!(apply_managed(&mut conf, &MANAGED));
+        validate(&conf, &MANAGED).unwrap();
+        let s = |path: &[&str]| conf.get(path).and_then(Value::as_str).map(str::to_string);
+        assert_eq!(s(&["chatnets", "libera", "sasl_password"]), None);
+        assert_eq!(s(&["chatnets", "oftc", "sasl_password"]), None);
+        assert_eq!(soju_chatnets(&conf, "alice"), ["libera", "oftc"]);
+        assert_eq!(s(&["settings", "core", "nick"]).as_deref(), Some("ally"));
+        assert_eq!(s(&["settings", "core", "user_name"]).as_deref(), Some("alice"));
+        assert_eq!(s(&["settings", "fe-text", "term_charset"]).as_deref(), Some("UTF-8"));
+        assert_eq!(s(&["settings", "fe-text", "actlist_sort"]).as_deref(), Some("refnum"));
+        let Some(Value::List(servers)) = conf.get(&["servers"]) else { panic!() };
+        let servers: Vec<&Vec<Node>> = servers
+            .iter()
+            .filter_map(|n| match n {
+                Node::Item(Value::Block(b)) => Some(b),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(servers.len(), 3, "no duplicate server added");
+        assert_eq!(get(servers[0], "address").and_then(Value::as_str), Some("bouncer"));
+        assert_eq!(get(servers[0], "port").and_then(Value::as_str), Some("6697"));
+        assert_eq!(get(servers[0], "autoconnect").and_then(Value::as_str), Some("no"));
+        assert_eq!(get(servers[1], "address").and_then(Value::as_str), Some("bouncer"));
+        assert_eq!(get(servers[2], "address").and_then(Value::as_str), Some("irc.other.net"));
+        assert!(conf.to_string().contains("# keep me"));
+        assert!(!apply_managed(&mut conf, &MANAGED));
+
+        assert!(add_network(&mut conf, &MANAGED, "hackint"));
+        assert_eq!(conf.get(&["chatnets", "hackint", "sasl_username"]).and_then(Value::as_str), Some("alice/hackint"));
+        assert!(!add_network(&mut conf, &MANAGED, "hackint"));
+        assert!(!apply_managed(&mut conf, &MANAGED));
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{block_mut, get, list_mut, set, Config, Node, Value};
+
+/// The irssi settings offered in the web UI. Reading fills in irssi's
+/// defaults; in a PATCH every field is optional and only given ones change.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Settings {
+    pub nick: Option<String>,
+    pub real_name: Option<String>,
+    pub timestamps: Option<bool>,
+    /// strftime format, e.g. `%H:%M`
+    pub timestamp_format: Option<String>,
+    pub theme: Option<String>,
+    /// Words that highlight a line
+    pub hilights: Option<Vec<String>>,
+    /// Ignored `nick!user@host` masks (all message levels)
+    pub ignores: Option<Vec<String>>,
+    /// Channels joined on connect, by network
+    pub autojoin: Option<BTreeMap<String, Vec<String>>>,
+    /// Order of the activity list: refnum, activity, recent, level or level,activity
+    pub actlist_sort: Option<String>,
+    pub autoclose_windows: Option<bool>,
+    pub window_auto_change: Option<bool>,
+    pub autocreate_own_query: Option<bool>,
+}
+
+const CORE: &str = "core";
+const FE_CORE: &str = "fe-common/core";
+const FE_TEXT: &str = "fe-text";
+
+const ACTLIST_SORTS: [&str; 5] = ["refnum", "activity", "recent", "level", "level,activity"];
+const MAX_ITEMS: usize = 100;
+
+fn setting<'a>(conf: &'a Config, section: &str, key: &str) -> Option<&'a str> {
+    conf.get(&["settings", section, key]).and_then(Value::as_str)
+}
+
+fn flag(conf: &Config, section: &str, key: &str, default: bool) -> bool {
+    setting(conf, section, key).map_or(default, |v| {
+        matches!(v.to_ascii_lowercase().as_str(), "yes" | "on" | "true" | "1")
+    })
+}
+
+/// Blocks in a top-level list such as `hilights` or `channels`.
+fn entries<'a>(conf: &'a Config, list: &str) -> impl Iterator<Item = &'a [Node]> {
+    let nodes = match conf.get(&[list]) {
+        Some(Value::List(nodes)) => nodes.as_slice(),
+        _ => &[],
+    };
+    nodes.iter().filter_map(|n| match n {
+        Node::Item(Value::Block(b)) => Some(b.as_slice()),
+        _ => None,
+    })
+}
+
+fn field<'a>(entry: &'a [Node], key: &str) -> Option<&'a str> {
+    get(entry, key).and_then(Value::as_str)
+}
+
+pub fn read(conf: &Config) -> Settings {
+    let mut autojoin: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for ch in entries(conf, "channels") {
+        if let (Some(name), Some(chatnet), Some("yes")) = (field(ch, "name"), field(ch, "chatnet"), field(ch, "autojoin")) {
+            autojoin.entry(chatnet.to_string()).or_default().push(name.to_string());
+        }
+    }
+    Settings {
+        nick: setting(conf, CORE, "nick").map(str::to_string),
+        real_name: setting(conf, CORE, "real_name").map(str::to_string),
+        timestamps: Some(flag(conf, FE_CORE, "timestamps", true)),
+        timestamp_format: Some(setting(conf, FE_CORE, "timestamp_format").unwrap_or("%H:%M").to_string()),
+        theme: Some(setting(conf, FE_CORE, "theme").unwrap_or("default").to_string()),
+        hilights: Some(entries(conf, "hilights").filter_map(|h| field(h, "text")).map(str::to_string).collect()),
+        ignores: Some(entries(conf, "ignores").filter_map(|i| field(i, "mask")).map(str::to_string).collect()),
+        autojoin: Some(autojoin),
+        actlist_sort: Some(setting(conf, FE_TEXT, "actlist_sort").unwrap_or("refnum").to_string()),
+        autoclose_windows: Some(flag(conf, FE_CORE, "autoclose_windows", true)),
+        window_auto_change: Some(flag(conf, FE_CORE, "window_auto_change", false)),
+        autocreate_own_query: Some(flag(conf, FE_CORE, "autocreate_own_query", true)),
+    }
+}
+
+/// Check every given field, then write them into `conf`.
+pub fn apply(conf: &mut Config, patch: &Settings) -> Result<()> {
+    validate(patch)?;
+
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    let mut put = |section: &str, key: &str, value: &str| {
+        let settings = block_mut(&mut conf.nodes, "settings");
+        set(block_mut(settings, section), key, value);
+    };
+    if let Some(v) = &patch.nick {
+        put(CORE, "nick", v);
+    }
+    if let Some(v) = &patch.real_name {
+        put(CORE, "real_name", v);
+    }
+    if let Some(v) = patch.timestamps {
+        put(FE_CORE, "timestamps", yes_no(v));
+    }
+    if let Some(v) = &patch.timestamp_format {
+        put(FE_CORE, "timestamp_format", v);
+    }
+    if let Some(v) = &patch.theme {
+        put(FE_CORE, "theme", v);
+    }
+    if let Some(v) = patch.autoclose_windows {
+        put(FE_CORE, "autoclose_windows", yes_no(v));
+    }
+    if let Some(v) = patch.window_auto_change {
+        put(FE_CORE, "window_auto_change", yes_no(v));
+    }
+    if let Some(v) = patch.autocreate_own_query {
+        put(FE_CORE, "autocreate_own_query", yes_no(v));
+    }
+    if let Some(v) = &patch.actlist_sort {
+        put(FE_TEXT, "actlist_sort", v);
+    }
+
+    if let Some(words) = &patch.hilights {
+        replace_entries(conf, "hilights", "text", words, &[("nick", "yes"), ("word", "yes")]);
+    }
+    if let Some(masks) = &patch.ignores {
+        replace_entries(conf, "ignores", "mask", masks, &[("level", "ALL")]);
+    }
+    if let Some(autojoin) = &patch.autojoin {
+        for (chatnet, names) in autojoin {
+            set_autojoin(conf, chatnet, names);
+        }
+    }
+    Ok(())
+}
+
+/// Make the entries of a top-level list exactly `values` (keyed by `key`),
+/// keeping existing entries and their other fields where they match.
+fn replace_entries(conf: &mut Config, list: &str, key: &str, values: &[String], defaults: &[(&str, &str)]) {
+    let nodes = list_mut(&mut conf.nodes, list);
+    nodes.retain(|n| match n {
+        Node::Item(Value::Block(b)) => field(b, key).is_some_and(|v| values.iter().any(|x| x == v)),
+        _ => true,
+    });
+    for value in values {
+        if nodes.iter().any(|n| matches!(n, Node::Item(Value::Block(b)) if field(b, key) == Some(value))) {
+            continue;
+        }
+        let mut entry = vec![Node::Pair(key.to_string(), Value::Str(value.clone()))];
+        entry.extend(defaults.iter().map(|(k, v)| Node::Pair(k.to_string(), Value::Str(v.to_string()))));
+        nodes.push(Node::Item(Value::Block(entry)));
+    }
+}
+
+/// Autojoin exactly `names` on `chatnet`. Channels dropped from the list
+/// stay in the config (they may hold a key) with autojoin off.
+fn set_autojoin(conf: &mut Config, chatnet: &str, names: &[String]) {
+    let listed = |name: &str| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+    let nodes = list_mut(&mut conf.nodes, "channels");
+    let mut seen = Vec::new();
+    for node in nodes.iter_mut() {
+        let Node::Item(Value::Block(ch)) = node else { continue };
+        if field(ch, "chatnet") != Some(chatnet) {
+            continue;
+        }
+        let Some(name) = field(ch, "name").map(str::to_string) else { continue };
+        set(ch, "autojoin", if listed(&name) { "yes" } else { "no" });
+        seen.push(name);
+    }
+    for name in names {
+        if seen.iter().any(|s| s.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+        nodes.push(Node::Item(Value::Block(vec![
+            Node::Pair("name".into(), Value::Str(name.clone())),
+            Node::Pair("chatnet".into(), Value::Str(chatnet.to_string())),
+            Node::Pair("autojoin".into(), Value::Str("yes".into())),
+        ])));
+    }
+}
+
+fn validate(p: &Settings) -> Result<()> {
+    if let Some(nick) = &p.nick {
+        let special = |c: char| "[]\\`_^{|}".contains(c);
+        let mut chars = nick.chars();
+        let first_ok = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || special(c));
+        if !first_ok || nick.len() > 30 || !chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || special(c)) {
+            bail!("nick must be 1–30 characters: letters, digits, - and []\\`_^{{|}}, not starting with a digit or -");
+        }
+    }
+    if let Some(v) = &p.real_name {
+        text("realName", v, 0, 128)?;
+    }
+    if let Some(v) = &p.timestamp_format {
+        text("timestampFormat", v, 0, 64)?;
+    }
+    if let Some(theme) = &p.theme {
+        if theme.is_empty()
+            || theme.len() > 64
+            || theme.starts_with('.')
+            || !theme.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+        {
+            bail!("theme must be a theme name, e.g. default");
+        }
+    }
+    if let Some(v) = &p.actlist_sort {
+        if !ACTLIST_SORTS.contains(&v.as_str()) {
+            bail!("actlistSort must be one of {}", ACTLIST_SORTS.join(", "));
+        }
+    }
+    if let Some(words) = &p.hilights {
+        items("hilights", words.len())?;
+        for w in words {
+            text("hilights", w, 1, 200)?;
+        }
+    }
+    if let Some(masks) = &p.ignores {
+        items("ignores", masks.len())?;
+        for m in masks {
+            if m.is_empty() || m.len() > 200 || m.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                bail!("ignores: '{}' is not a valid mask", m);
+            }
+        }
+    }
+    if let Some(autojoin) = &p.autojoin {
+        for (chatnet, names) in autojoin {
+            if chatnet.is_empty() || chatnet.len() > 64 || !chatnet.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+                bail!("autojoin: '{}' is not a valid network name", chatnet);
+            }
+            items("autojoin", names.len())?;
+            for name in names {
+                let ok = name.len() >= 2
+                    && name.len() <= 50
+                    && name.starts_with(['#', '&', '+', '!'])
+                    && !name.chars().any(|c| c == ' ' || c == ',' || c.is_control());
+                if !ok {
+                    bail!("autojoin: '{}' is not a valid channel name", name);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn text(name: &str, v: &str, min: usize, max: usize) -> Result<()> {
+    if v.chars().count() < min || v.chars().count() > max || v.chars().any(char::is_control) {
+        bail!("{} must be {}–{} characters on one line", name, min, max);
+    }
+    Ok(())
+}
+
+fn items(name: &str, n: usize) -> Result<()> {
+    if n > MAX_ITEMS {
+        bail!("{} takes at most {} entries", name, MAX_ITEMS);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_and_apply() {
+        let mut conf = Config::parse(
+            r##"settings = { core = { nick = "alice"; }; "fe-common/core" = { timestamps = "no"; }; };
+hilights = ( { text = "alice"; nick = "yes"; word = "yes"; }, { text = "old"; } );
+channels = (
+  { name = "#keyed"; chatnet = "libera"; autojoin = "yes"; password = "k"; },
+  { name = "#other"; chatnet = "oftc"; autojoin = "yes"; }
+);
+"##,
+        )
+        .unwrap();
+        let s = read(&conf);
+        assert_eq!(s.nick.as_deref(), Some("alice"));
+        assert_eq!(s.timestamps, Some(false));
+        assert_eq!(s.theme.as_deref(), Some("default"));
+        assert_eq!(s.hilights, Some(vec!["alice".to_string(), "old".to_string()]));
+        assert_eq!(s.autojoin.as_ref().unwrap()["libera"], vec!["#keyed".to_string()]);
+
+        let patch: Settings = serde_json::from_value(serde_json::json!({
+            "nick": "ally",
+            "timestamps": true,
+            "hilights": ["alice", "rust"],
+            "ignores": ["*!*@spam.example"],
+            "autojoin": {"libera": ["#new"]},
+            "actlistSort": "activity",
+        }))
+        .unwrap();
+        apply(&mut conf, &patch).unwrap();
+        let s = read(&conf);
+        assert_eq!(s.nick.as_deref(), Some("ally"));
+        assert_eq!(s.timestamps, Some(true));
+        assert_eq!(s.hilights, Some(vec!["alice".to_string(), "rust".to_string()]));
+        assert_eq!(s.ignores, Some(vec!["*!*@spam.example".to_string()]));
+        assert_eq!(s.autojoin.as_ref().unwrap()["libera"], vec!["#new".to_string()]);
+        assert_eq!(s.autojoin.as_ref().unwrap()["oftc"], vec!["#other".to_string()]);
+        assert_eq!(s.actlist_sort.as_deref(), Some("activity"));
+        // The dropped channel keeps its key, just not autojoin
+        assert!(conf.to_string().contains(r#"password = "k";"#));
+
+        for bad in [
+            serde_json::json!({"nick": "9lives"}),
+            serde_json::json!({"realName": "a\nb"}),
+            serde_json::json!({"theme": "../etc"}),
+            serde_json::json!({"actlistSort": "random"}),
+            serde_json::json!({"autojoin": {"libera": ["nochan"]}}),
+            serde_json::json!({"ignores": ["two words"]}),
+        ] {
+            let patch: Settings = serde_json::from_value(bad.clone()).unwrap();
+            assert!(apply(&mut conf, &patch).is_err(), "{}", bad);
+        }
+        assert!(serde_json::from_value::<Settings>(serde_json::json!({"sasl_password": "x"})).is_err());
+    }
+}
@@ -1,22 +1,37 @@
 mod auth;
+mod backup;
+mod catalog;
+mod client;
 mod config;
+mod control;
+mod crypto;
+mod export;
+mod ircproxy;
+mod irssi;
+mod maintenance;
+mod proxy;
+mod roles;
 mod session;
+mod share;
 mod soju;
+mod ssh;
 mod store;
 
-use std::sync::Arc;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
-    extract::{FromRequest, Path, State},
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use futures_util::{SinkExt, StreamExt};
 use tokio_tungstenite::tungstenite::Message as TungMsg;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio::signal;
 use tower_http::services::ServeDir;
@@ -25,33 +40,166 @@ use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
 use auth::{User, Validator};
-use config::Config;
+use client::{Client, ALL_CLIENTS};
+use config::{Config, Runtime};
+use maintenance::Maintenance;
+use roles::{Permission, Role, ALL_ROLES};
 use session::Manager as SessionManager;
 use soju::Manager as SojuManager;
-use store::Store;
+use store::{AuditFilter, Store, Suspension};
 
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 
 // ── App state ─────────────────────────────────────────────────────────────────
 
+/// Outcome of rotating one user's soju password.
+enum SojuRotation {
+    /// The new password is in place; `reconnected` if a running irssi took it
+    Rotated { reconnected: bool },
+    /// Left alone: this client is running and would keep the old password
+    Deferred(Client),
+}
+
+/// Outcome of `AppState::rotate_soju_passwords`, by username.
+#[derive(Default)]
+struct SojuRotations {
+    rotated: Vec<String>,
+    deferred: Vec<String>,
+    failed: Vec<(String, String)>,
+}
+
 #[derive(Clone)]
 struct AppState {
     cfg: Arc<Config>,
+    /// Settings swapped in place on SIGHUP
+    runtime: Arc<RwLock<Arc<Runtime>>>,
     validator: Option<Arc<Validator>>,
     store: Store,
     sessions: Arc<SessionManager>,
     soju: Arc<SojuManager>,
+    conns: Arc<proxy::Registry>,
+    /// Native IRC clients logged in with app passwords
+    irc_proxy: Arc<ircproxy::Proxy>,
+    /// HMAC key for share tokens, persisted in the settings table
+    share_secret: Arc<str>,
+    /// Seals secrets kept at rest (APP_KEY / APP_KEY_FILE)
+    keys: Arc<crypto::Keyring>,
+    /// Scripts and themes users can enable (CATALOG_DIR)
+    catalog: Arc<catalog::Catalog>,
+    /// Fan-out to every browser subscribed to /api/events
+    events: tokio::sync::broadcast::Sender<ServerEvent>,
+    maintenance: Arc<Maintenance>,
+    /// Marks requests that came in over the local control socket
+    control_token: Arc<str>,
+    /// Flipped to true on shutdown so long-lived streams (SSE) end and
+    /// graceful shutdown is not held up by them
+    shutdown: tokio::sync::watch::Receiver<bool>,
+}
+
+/// A server-sent event pushed to all connected browsers.
+#[derive(Debug, Clone)]
+struct ServerEvent {
+    kind: &'static str,
+    data: Value,
 }
 
 impl AppState {
+    fn runtime(&self) -> Arc<Runtime> {
+        Arc::clone(&self.runtime.read().unwrap())
+    }
+
+    /// Re-read the config file and env, and swap in the settings that can
+    /// change at runtime. On any error the running config is kept.
+    async fn reload_config(&self) -> anyhow::Result<()> {
+        let (_, runtime) = Config::load(self.cfg.file.as_deref())?;
+        bootstrap_admins(&self.store, &runtime).await?;
+        info!(
+            "config reloaded: {} admin users, {} group mappings, {} networks",
+            runtime.admin_users.len(),
+            runtime.role_groups.len(),
+            runtime.networks.len()
+        );
+        *self.runtime.write().unwrap() = Arc::new(runtime);
+        Ok(())
+    }
+
     async fn authenticate(&self, headers: &HeaderMap) -> Result<User, AppError> {
+        if let Some(user) = self.control_user(headers) {
+            return Ok(user);
+        }
+        let mut user = self.identify(headers).await?;
+        self.check_suspension(&user.username).await?;
+        user.roles = self.effective_roles(&user).await;
+
+        let (ip, ua) = client_info(headers);
+        if let Err(e) = self
+            .store
+            .record_login(&user.username, "auth", ip.as_deref(), ua.as_deref())
+            .await
+        {
+            warn!("record_login({}): {:#}", user.username, e);
+        }
+        Ok(user)
+    }
+
+    /// Requests over the control socket act as a local administrator. The
+    /// socket is root-only, so this works even when Cloudflare Access is down.
+    fn control_user(&self, headers: &HeaderMap) -> Option<User> {
+        let token = headers.get(control::TOKEN_HEADER)?.to_str().ok()?;
+        if token != &*self.control_token {
+            return None;
+        }
+        let uid = headers.get(control::UID_HEADER).and_then(|v| v.to_str().ok()).unwrap_or("?");
+        Some(User {
+            username: format!("(control uid {})", uid),
+            email: String::new(),
+            groups: Vec::new(),
+            roles: BTreeSet::from([Role::Admin]),
+        })
+    }
+
+    /// Reject suspended users. Expired suspensions are lifted on the spot.
+    async fn check_suspension(&self, username: &str) -> Result<(), AppError> {
+        let Some(s) = self.store.suspension(username).await.map_err(AppError::from)? else {
+            return Ok(());
+        };
+        if !s.is_expired() {
+            return Err(AppError::Suspended { reason: s.reason, until: s.expires_at });
+        }
+        info!("suspension of {} expired, lifting", username);
+        let result = self.lift_suspension(username).await.map(|_| ()).map_err(AppError::from);
+        let outcome = if result.is_ok() { "ok".to_string() } else { "error".to_string() };
+        let _ = self
+            .store
+            .audit("system", "user.unsuspend", Some(username), &json!({"expired": true}), &outcome)
+            .await;
+        result
+    }
+
+    /// Remove a suspension and re-enable the soju networks it disabled.
+    async fn lift_suspension(&self, username: &str) -> anyhow::Result<Option<Suspension>> {
+        let Some(s) = self.store.unsuspend(username).await? else {
+            return Ok(None);
+        };
+        if !self.cfg.dev_mode {
+            let networks: Vec<String> = serde_json::from_str(&s.disabled_networks).unwrap_or_default();
+            for net in &networks {
+                if let Err(e) = self.soju.set_network_enabled(username, net, true).await {
+                    warn!("re-enable {} for {}: {:#}", net, username, e);
+                }
+            }
+        }
+        Ok(Some(s))
+    }
+
+    async fn identify(&self, headers: &HeaderMap) -> Result<User, AppError> {
         if self.cfg.dev_mode {
             let username = self.cfg.dev_user.clone();
-            let is_admin = self.cfg.admin_users.contains(&username);
             return Ok(User {
                 username: username.clone(),
                 email: format!("{}@dev", username),
-                is_admin,
+                groups: Vec::new(),
+                roles: BTreeSet::new(),
             });
         }
 
@@ -73,6 +221,218 @@ impl AppState {
             })?;
         Ok(user)
     }
+
+    /// Stored role grants plus any roles mapped from the user's IdP groups.
+    async fn effective_roles(&self, user: &User) -> BTreeSet<Role> {
+        let mut roles: BTreeSet<Role> = match self.store.roles_for(&user.username).await {
+            Ok(stored) => stored.iter().filter_map(|r| r.parse().ok()).collect(),
+            Err(e) => {
+                error!("roles_for({}): {:#}", user.username, e);
+                BTreeSet::new()
+            }
+        };
+        for (group, role) in &self.runtime().role_groups {
+            if user.groups.iter().any(|g| g == group) {
+                roles.insert(*role);
+            }
+        }
+        roles
+    }
+
+    /// Refuse to start sessions while maintenance mode is on.
+    fn check_maintenance(&self) -> Result<(), AppError> {
+        match self.maintenance.refusal() {
+            Some(msg) => Err(AppError::Unavailable(msg)),
+            None => Ok(()),
+        }
+    }
+
+    /// Detach every live session: browsers are disconnected with `code` and
+    /// ttyd is stopped, but with dtach the irssi processes keep running and
+    /// are reattached on the next connect.
+    fn drain_sessions(&self, code: u16) {
+        let dropped = self.conns.disconnect_all(code);
+        let users = self.sessions.active_usernames();
+        for username in &users {
+            self.sessions.detach(username);
+        }
+        info!("drained {} sessions ({} browser connections)", users.len(), dropped);
+    }
+
+    fn backup_dir(&self) -> PathBuf {
+        self.cfg.data_dir.join("backups")
+    }
+
+    async fn create_backup(&self) -> anyhow::Result<(PathBuf, backup::Manifest)> {
+        backup::create(&self.store, &self.cfg.sessions_dir, self.cfg.soju_db.as_deref(), &self.backup_dir()).await
+    }
+
+    /// Tell browsers about the current maintenance status.
+    fn broadcast_maintenance(&self) {
+        let _ = self.events.send(ServerEvent {
+            kind: "maintenance",
+            data: json!(self.maintenance.status()),
+        });
+    }
+
+    /// The terminal client the user picked, irssi by default.
+    async fn client_for(&self, username: &str) -> Client {
+        match self.store.client_preference(username).await {
+            Ok(Some(name)) => name.parse().unwrap_or_else(|e| {
+                warn!("client preference of {}: {:#}", username, e);
+                Client::default()
+            }),
+            Ok(None) => Client::default(),
+            Err(e) => {
+                error!("client_preference({}): {:#}", username, e);
+                Client::default()
+            }
+        }
+    }
+
+    /// Provision the user in soju. If soju had lost them (its database was
+    /// reset), push their saved upstream SASL credentials again.
+    async fn ensure_soju_user(&self, user: &User) -> anyhow::Result<()> {
+        let client = self.client_for(&user.username).await;
+        if !self.soju.ensure_user(&user.username, user.is_admin(), client).await? {
+            return Ok(());
+        }
+        for cred in self.store.list_network_sasl(&user.username).await? {
+            let context = store::sasl_context(&user.username, &cred.network);
+            let pushed = match self.keys.open(&context, &cred.sealed_password) {
+                Ok(password) => {
+                    self.soju.set_sasl_plain(&user.username, &cred.network, &cred.sasl_username, &password).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = pushed {
+                warn!("restore SASL for {} on {}: {:#}", user.username, cred.network, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The soju password and chatnets a new irssi needs, decrypted just for
+    /// its start. None in dev mode or before the user has a soju password.
+    async fn soju_login(&self, username: &str) -> Option<session::SojuLogin> {
+        if self.cfg.dev_mode {
+            return None;
+        }
+        let password = match self.soju.read_password(username).await {
+            Ok(password) => password?,
+            Err(e) => {
+                warn!("soju password for {}: {:#}", username, e);
+                return None;
+            }
+        };
+        let chatnets = match irssi::read_config(&self.soju.user_dir(username).join("config")).await {
+            Ok(conf) => irssi::soju_chatnets(&conf, username),
+            Err(e) => {
+                warn!("irssi config for {}: {:#}", username, e);
+                Vec::new()
+            }
+        };
+        Some(session::SojuLogin { password, chatnets })
+    }
+
+    /// Give the user a new soju password and hand it to their running irssi,
+    /// which reconnects with it. If irssi cannot take it, soju and the
+    /// stored password go back to the old one. WeeChat and senpai read the
+    /// password only when they start, so while one runs (or an irssi
+    /// without the control script) the rotation is deferred: it would
+    /// fail the client's next reconnect.
+    async fn rotate_soju_password(&self, username: &str) -> anyhow::Result<SojuRotation> {
+        let dir = self.soju.user_dir(username);
+        let running = self.sessions.running_client(&dir);
+        if let Some(client) = running.filter(|c| *c != Client::Irssi) {
+            return Ok(SojuRotation::Deferred(client));
+        }
+        let (old, new) = self.soju.rotate_password(username).await?;
+        match self.sessions.set_soju_password(&dir, &new).await {
+            Ok(false) if running.is_some() => {
+                self.soju.set_password(username, &old).await.context("rolling back soju password")?;
+                Ok(SojuRotation::Deferred(Client::Irssi))
+            }
+            Ok(reconnected) => {
+                self.store.record_soju_rotation(username, store::now_ms()).await?;
+                Ok(SojuRotation::Rotated { reconnected })
+            }
+            Err(e) => {
+                // It may have taken the new one before failing to answer
+                let _ = self.sessions.set_soju_password(&dir, &old).await;
+                if let Err(undo) = self.soju.set_password(username, &old).await {
+                    error!("rolling back soju password of {}: {:#}", username, undo);
+                }
+                Err(e.context("irssi did not take the new password; rolled back"))
+            }
+        }
+    }
+
+    /// Rotate the soju password of every provisioned user, or with `max_age`
+    /// only of those whose password is at least that old. A user seen for
+    /// the first time only starts the clock; a deferred one stays due.
+    async fn rotate_soju_passwords(&self, max_age: Option<std::time::Duration>) -> anyhow::Result<SojuRotations> {
+        let rotations = self.store.soju_rotations().await?;
+        let now = store::now_ms();
+        let mut out = SojuRotations::default();
+        for username in self.soju.password_users().await? {
+            if let Some(max_age) = max_age {
+                match rotations.get(&username) {
+                    None => {
+                        self.store.record_soju_rotation(&username, now).await?;
+                        continue;
+                    }
+                    Some(&at) if now - at < max_age.as_millis() as i64 => continue,
+                    Some(_) => {}
+                }
+            }
+            match self.rotate_soju_password(&username).await {
+                Ok(SojuRotation::Rotated { .. }) => out.rotated.push(username),
+                Ok(SojuRotation::Deferred(_)) => out.deferred.push(username),
+                Err(e) => {
+                    warn!("rotating soju password of {}: {:#}", username, e);
+                    out.failed.push((username, format!("{:#}", e)));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Mirror the admin role onto soju's own admin flag. Best-effort: a user
+    /// who has never logged in has no soju account yet, and ensure_user
+    /// sets the flag when they do.
+    async fn sync_soju_admin(&self, username: &str, admin: bool) {
+        if self.cfg.dev_mode {
+            return;
+        }
+        if let Err(e) = self.soju.set_admin(username, admin).await {
+            warn!("soju admin flag for {}: {:#}", username, e);
+        }
+    }
+
+    /// Append an entry to the audit log. Failures are logged, never surfaced —
+    /// an audit hiccup must not turn a successful action into an error.
+    async fn audit<T>(
+        &self,
+        actor: &User,
+        action: &str,
+        target: Option<&str>,
+        params: Value,
+        result: &Result<T, AppError>,
+    ) {
+        let outcome = match result {
+            Ok(_) => "ok".to_string(),
+            Err(AppError::Forbidden) => "denied".to_string(),
+            Err(e) => format!("error: {}", e.describe()),
+        };
+        if let Err(e) = self
+            .store
+            .audit(&actor.username, action, target, &params, &outcome)
+            .await
+        {
+            error!("audit({} {} by {}): {:#}", action, target.unwrap_or("-"), actor.username, e);
+        }
+    }
 }
 
 // ── Error type ────────────────────────────────────────────────────────────────
@@ -80,6 +440,12 @@ impl AppState {
 enum AppError {
     Unauthorized(String),
     Forbidden,
+    NotFound(String),
+    BadRequest(String),
+    /// The request is fine but the user's session can't take it right now
+    Conflict(String),
+    Unavailable(String),
+    Suspended { reason: String, until: Option<i64> },
     Internal(anyhow::Error),
 }
 
@@ -93,7 +459,32 @@ impl IntoResponse for AppError {
                 .into_response(),
             AppError::Forbidden => (
                 StatusCode::FORBIDDEN,
-                Json(json!({"error": "Admin access required"})),
+                Json(json!({"error": "Insufficient permissions"})),
+            )
+                .into_response(),
+            AppError::NotFound(msg) => (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": msg})),
+            )
+                .into_response(),
+            AppError::BadRequest(msg) => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": msg})),
+            )
+                .into_response(),
+            AppError::Conflict(msg) => (
+                StatusCode::CONFLICT,
+                Json(json!({"error": msg})),
+            )
+                .into_response(),
+            AppError::Unavailable(msg) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({"error": msg, "maintenance": true})),
+            )
+                .into_response(),
+            AppError::Suspended { reason, until } => (
+                StatusCode::FORBIDDEN,
+                Json(json!({"error": "Account suspended", "reason": reason, "until": until})),
             )
                 .into_response(),
             AppError::Internal(e) => {
@@ -108,12 +499,49 @@ impl IntoResponse for AppError {
     }
 }
 
+impl AppError {
+    fn describe(&self) -> String {
+        match self {
+            AppError::Unauthorized(msg) => msg.clone(),
+            AppError::Forbidden => "forbidden".into(),
+            AppError::NotFound(msg) => format!("not found: {}", msg),
+            AppError::BadRequest(msg) => format!("invalid: {}", msg),
+            AppError::Conflict(msg) => msg.clone(),
+            AppError::Unavailable(msg) => format!("unavailable: {}", msg),
+            AppError::Suspended { reason, .. } => format!("suspended: {}", reason),
+            AppError::Internal(e) => format!("{:#}", e),
+        }
+    }
+}
+
 impl From<anyhow::Error> for AppError {
     fn from(e: anyhow::Error) -> Self {
         AppError::Internal(e)
     }
 }
 
+fn require(user: &User, perm: Permission) -> Result<(), AppError> {
+    if user.can(perm) { Ok(()) } else { Err(AppError::Forbidden) }
+}
+
+/// Client IP and user agent. Behind Cloudflare the real address is in
+/// CF-Connecting-IP; X-Forwarded-For / X-Real-IP cover other proxies.
+fn client_info(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+    };
+    let ip = header("cf-connecting-ip")
+        .or_else(|| header("x-forwarded-for").and_then(|v| v.split(',').next()).map(str::trim))
+        .or_else(|| header("x-real-ip"))
+        .map(str::to_string);
+    let ua = header("user-agent").map(|s| s.chars().take(512).collect());
+    (ip, ua)
+}
+
 // ── Handlers ──────────────────────────────────────────────────────────────────
 
 async fn handle_me(
@@ -121,15 +549,245 @@ async fn handle_me(
     headers: HeaderMap,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    let _ = state.store.touch(&user.username, user.is_admin).await;
+    let _ = state.store.touch(&user.username).await;
+
+    Ok(Json(json!({
+        "username":    user.username,
+        "email":       user.email,
+        "isAdmin":     user.is_admin(),
+        "roles":       user.roles,
+        "permissions": user.permissions(),
+    })))
+}
+
+async fn preferences_json(state: &AppState, username: &str) -> Value {
+    let clients: Vec<Value> =
+        ALL_CLIENTS.iter().map(|c| json!({"name": c, "installed": c.installed()})).collect();
+    json!({
+        "client": state.client_for(username).await,
+        "clients": clients,
+    })
+}
+
+/// The caller's preferences, with the terminal clients this server has.
+/// Route: GET /api/me/preferences
+async fn handle_get_preferences(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    Ok(Json(preferences_json(&state, &user.username).await))
+}
+
+#[derive(Deserialize, Serialize)]
+struct PreferencesBody {
+    client: Option<String>,
+}
+
+/// Change preferences; fields left out stay as they are. Another client
+/// ends the running session so the next one starts it. The old client's
+/// config is kept for switching back.
+/// Route: PUT /api/me/preferences
+async fn handle_put_preferences(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<PreferencesBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        if let Some(name) = &body.client {
+            let client: Client = name.parse().map_err(|e: anyhow::Error| AppError::BadRequest(e.to_string()))?;
+            if !client.installed() {
+                return Err(AppError::BadRequest(format!("{} is not installed on this server", name)));
+            }
+            if client != state.client_for(&user.username).await {
+                state.store.set_client_preference(&user.username, client.as_str()).await?;
+                // Written on the next login: ensure_user syncs the config
+                state.soju.forget(&user.username);
+                state.sessions.kill(&user.username);
+            }
+        }
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "preferences.update", Some(&user.username), json!(body), &result).await;
+    result?;
+    Ok(Json(preferences_json(&state, &user.username).await))
+}
+
+/// IRC network presets from the config file, primary network first.
+/// Route: GET /api/networks
+async fn handle_networks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    state.authenticate(&headers).await?;
+    Ok(Json(json!({"networks": state.runtime().networks})))
+}
+
+#[derive(Deserialize)]
+struct SaslBody {
+    username: String,
+    password: String,
+}
+
+/// The network must be one of the caller's soju networks.
+async fn check_user_network(state: &AppState, username: &str, network: &str) -> Result<(), AppError> {
+    if state.cfg.dev_mode {
+        return Ok(());
+    }
+    let networks = state.soju.list_networks(username).await?;
+    if !networks.iter().any(|n| n.name == network) {
+        return Err(AppError::NotFound(format!("no network {}", network)));
+    }
+    Ok(())
+}
 
+/// Saved upstream SASL login for one of the caller's networks (never the
+/// password) and soju's view of whether it worked.
+/// Route: GET /api/networks/:name/sasl
+async fn handle_get_sasl(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(network): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    check_user_network(&state, &user.username, &network).await?;
+    let saved = state.store.get_network_sasl(&user.username, &network).await?;
+    let status = if state.cfg.dev_mode {
+        None
+    } else {
+        Some(state.soju.sasl_status(&user.username, &network).await?)
+    };
     Ok(Json(json!({
-        "username": user.username,
-        "email":    user.email,
-        "isAdmin":  user.is_admin,
+        "network": network,
+        "saved": saved,
+        "status": status,
     })))
 }
 
+/// Save upstream SASL PLAIN credentials (sealed at rest) and hand them to
+/// soju, which reconnects the network with them.
+/// Route: PUT /api/networks/:name/sasl
+async fn handle_put_sasl(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(network): Path<String>,
+    Json(body): Json<SaslBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        let sasl_user = body.username.trim();
+        if sasl_user.is_empty() || sasl_user.len() > 64 || sasl_user.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(AppError::BadRequest("username must be 1–64 characters without spaces".into()));
+        }
+        if body.password.is_empty() || body.password.len() > 300 || body.password.chars().any(char::is_control) {
+            return Err(AppError::BadRequest("password must be 1–300 characters on one line".into()));
+        }
+        check_user_network(&state, &user.username, &network).await?;
+        if !state.cfg.dev_mode {
+            state.soju.set_sasl_plain(&user.username, &network, sasl_user, &body.password).await?;
+        }
+        let sealed = state.keys.seal(&store::sasl_context(&user.username, &network), &body.password);
+        state.store.set_network_sasl(&user.username, &network, sasl_user, &sealed).await?;
+        Ok(())
+    }
+    .await;
+    state
+        .audit(&user, "network.sasl.set", Some(&user.username), json!({"network": network, "username": body.username}), &result)
+        .await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+/// Route: DELETE /api/networks/:name/sasl
+async fn handle_delete_sasl(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(network): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        check_user_network(&state, &user.username, &network).await?;
+        if !state.cfg.dev_mode {
+            state.soju.reset_sasl(&user.username, &network).await?;
+        }
+        state.store.delete_network_sasl(&user.username, &network).await?;
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "network.sasl.reset", Some(&user.username), json!({"network": network}), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct CertfpBody {
+    /// rsa, ecdsa or ed25519; soju's default when omitted
+    key_type: Option<String>,
+}
+
+/// How to register a fingerprint with NickServ. Without an argument
+/// `CERT ADD` takes the fingerprint of the current connection, which is the
+/// one soju now uses.
+fn certfp_instructions(fp: &soju::Fingerprints) -> Value {
+    json!({
+        "fingerprints": fp,
+        "nickserv": "/msg NickServ CERT ADD",
+        "nickservExplicit": format!("/msg NickServ CERT ADD {}", fp.sha256),
+    })
+}
+
+/// Route: GET /api/networks/:name/certfp — the current client certificate's
+/// fingerprints, or `"fingerprints": null` when there is none.
+async fn handle_get_certfp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(network): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    if state.cfg.dev_mode {
+        return Err(AppError::Conflict("no bouncer in dev mode".into()));
+    }
+    check_user_network(&state, &user.username, &network).await?;
+    Ok(Json(match state.soju.certfp_fingerprint(&user.username, &network).await? {
+        Some(fp) => certfp_instructions(&fp),
+        None => json!({"fingerprints": null}),
+    }))
+}
+
+/// Generate a client certificate for the network in soju and switch it to
+/// SASL EXTERNAL. The response carries the fingerprints and the NickServ
+/// command to register them. A saved SASL PLAIN login is kept and comes
+/// back into use only if soju loses the user.
+/// Route: POST /api/networks/:name/certfp
+async fn handle_generate_certfp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(network): Path<String>,
+    body: Option<Json<CertfpBody>>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+    let result: Result<soju::Fingerprints, AppError> = async {
+        if let Some(t) = &body.key_type {
+            if !soju::CERT_KEY_TYPES.contains(&t.as_str()) {
+                return Err(AppError::BadRequest(format!("keyType must be one of {}", soju::CERT_KEY_TYPES.join(", "))));
+            }
+        }
+        if state.cfg.dev_mode {
+            return Err(AppError::Conflict("no bouncer in dev mode".into()));
+        }
+        check_user_network(&state, &user.username, &network).await?;
+        Ok(state.soju.generate_certfp(&user.username, &network, body.key_type.as_deref()).await?)
+    }
+    .await;
+    let params = json!({"network": network, "keyType": body.key_type});
+    state.audit(&user, "network.certfp.generate", Some(&user.username), params, &result).await;
+    Ok(Json(certfp_instructions(&result?)))
+}
+
 /// Provision the user's session (soju + ttyd). Called by the frontend
 /// before loading the terminal iframe. Returns 200 when ready.
 /// Route: GET /api/terminal
@@ -138,16 +796,20 @@ async fn handle_provision(
     headers: HeaderMap,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    let _ = state.store.touch(&user.username, user.is_admin).await;
+    let _ = state.store.touch(&user.username).await;
+    state.check_maintenance()?;
+    provision_session(&state, &user).await?;
+    Ok(Json(json!({"ok": true})))
+}
 
+async fn provision_session(state: &AppState, user: &User) -> Result<(), AppError> {
     let user_dir = if state.cfg.dev_mode {
         let dir = state.cfg.sessions_dir.join(&user.username);
         tokio::fs::create_dir_all(&dir).await.ok();
         dir
     } else {
         state
-            .soju
-            .ensure_user(&user.username)
+            .ensure_soju_user(user)
             .await
             .map_err(|e| {
                 error!("soju.ensure_user({}): {:#}", user.username, e);
@@ -156,16 +818,99 @@ async fn handle_provision(
         state.soju.user_dir(&user.username)
     };
 
+    start_session(state, user, &user_dir).await?;
+    Ok(())
+}
+
+/// The user's ttyd port, starting ttyd (and their client) if needed.
+async fn start_session(state: &AppState, user: &User, user_dir: &std::path::Path) -> Result<u16, AppError> {
+    let login = match state.sessions.is_active(&user.username) {
+        true => None,
+        false => state.soju_login(&user.username).await,
+    };
+    let client = state.client_for(&user.username).await;
     state
         .sessions
-        .get_or_create(&user.username, &user_dir)
+        .get_or_create(&user.username, user_dir, client, login.as_ref())
         .await
         .map_err(|e| {
             error!("session.get_or_create({}): {:#}", user.username, e);
             AppError::Internal(e)
-        })?;
+        })
+}
 
-    Ok(Json(json!({"ok": true})))
+#[derive(Deserialize)]
+struct OpenQuery {
+    url: String,
+}
+
+/// How long to keep trying to join an opened channel while irssi starts
+/// and connects.
+const OPEN_JOIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Open an `irc://` or `ircs://` link in the caller's session: map the host
+/// to one of the configured networks, add it to their bouncer if needed,
+/// start the session and have irssi join (and so focus) the channel, then
+/// send the browser to the app. Registered as the browser's irc: handler
+/// with `/open?url=%s`.
+/// Route: GET /open?url=irc://host/#chan
+async fn handle_open(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<OpenQuery>,
+) -> Result<Response, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let _ = state.store.touch(&user.username).await;
+    state.check_maintenance()?;
+
+    let result: Result<(), AppError> = async {
+        let link = soju::parse_irc_url(&q.url).map_err(|e| AppError::BadRequest(e.to_string()))?;
+        let network = state
+            .runtime()
+            .networks
+            .iter()
+            .find(|n| n.host() == link.host || n.name.eq_ignore_ascii_case(&link.host))
+            .cloned()
+            .ok_or_else(|| AppError::BadRequest(format!("{} is not one of this server's networks", link.host)))?;
+        let dir = state.cfg.sessions_dir.join(&user.username);
+        let client = state.client_for(&user.username).await;
+
+        if !state.cfg.dev_mode {
+            state.ensure_soju_user(&user).await?;
+            let added = state.soju.add_network(&user.username, &network.name, &network.addr, client).await?;
+            // A running irssi only autoconnects at startup
+            if added && client == Client::Irssi && state.sessions.send_command(&dir, None, "/reload").await.is_ok() {
+                state.sessions.add_soju_chatnet(&dir, &network.name).await?;
+                state.sessions.send_command(&dir, None, &format!("/connect {}", network.name)).await?;
+            }
+        }
+        provision_session(&state, &user).await?;
+
+        // Only irssi takes commands from irssi-v5
+        if let Some(channel) = link.channel.filter(|_| client == Client::Irssi) {
+            let join = irssi::command_line("join", &channel).map_err(|e| AppError::BadRequest(e.to_string()))?;
+            let sessions = Arc::clone(&state.sessions);
+            let username = user.username.clone();
+            tokio::spawn(async move {
+                let deadline = tokio::time::Instant::now() + OPEN_JOIN_TIMEOUT;
+                loop {
+                    match sessions.send_command(&dir, Some(&network.name), &join).await {
+                        Ok(()) => break,
+                        Err(e) if tokio::time::Instant::now() >= deadline => {
+                            warn!("open {} for {}: {:#}", channel, username, e);
+                            break;
+                        }
+                        Err(_) => tokio::time::sleep(std::time::Duration::from_secs(1)).await,
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "irssi.open", Some(&user.username), json!({"url": q.url}), &result).await;
+    result?;
+    Ok(axum::response::Redirect::to("/").into_response())
 }
 
 async fn handle_terminal_ws(
@@ -174,18 +919,9 @@ async fn handle_terminal_ws(
     ws: axum::extract::ws::WebSocketUpgrade,
 ) -> Result<Response, AppError> {
     let user = state.authenticate(&headers).await?;
+    state.check_maintenance()?;
 
-    let port = state
-        .sessions
-        .get_or_create(
-            &user.username,
-            &state.cfg.sessions_dir.join(&user.username),
-        )
-        .await
-        .map_err(|e| {
-            error!("session.get_or_create({}): {:#}", user.username, e);
-            AppError::Internal(e)
-        })?;
+    let port = start_session(&state, &user, &state.cfg.sessions_dir.join(&user.username)).await?;
 
     // Build upstream WS request to ttyd
     let ws_url = format!("ws://127.0.0.1:{}/ws", port);
@@ -214,9 +950,19 @@ async fn handle_terminal_ws(
         .await
         .map_err(|e| AppError::Internal(anyhow::anyhow!("ws connect ttyd: {}", e)))?;
 
+    let (ip, ua) = client_info(&headers);
+    if let Err(e) = state
+        .store
+        .record_login(&user.username, "terminal", ip.as_deref(), ua.as_deref())
+        .await
+    {
+        warn!("record_login({}): {:#}", user.username, e);
+    }
+    let conn = state.conns.register(&user.username, proxy::ConnKind::Terminal, ip, ua);
+
     Ok(ws
         .protocols(["tty"])
-        .on_upgrade(move |client| splice_ws(client, upstream))
+        .on_upgrade(move |client| splice_ws(client, upstream, conn))
         .into_response())
 }
 
@@ -228,71 +974,1122 @@ async fn splice_ws(
     upstream: tokio_tungstenite::WebSocketStream<
         tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
     >,
+    mut conn: proxy::Handle,
 ) {
-    use axum::extract::ws::Message as AxMsg;
+    use axum::extract::ws::{CloseFrame, Message as AxMsg};
 
     let (mut ctx, mut crx) = client.split();
     let (mut utx, mut urx) = upstream.split();
+    let mut side = conn.take_side();
+    let mut injected = conn.take_input();
+    let mut scanner = proxy::osc::Scanner::default();
 
     let c2u = async {
-        while let Some(Ok(msg)) = crx.next().await {
-            let m = match msg {
-                AxMsg::Text(t)   => TungMsg::Text(t),
-                AxMsg::Binary(b) => TungMsg::Binary(b),
-                AxMsg::Ping(p)   => TungMsg::Ping(p),
-                AxMsg::Pong(p)   => TungMsg::Pong(p),
-                AxMsg::Close(_)  => break,
+        loop {
+            let m = tokio::select! {
+                msg = crx.next() => match msg {
+                    Some(Ok(AxMsg::Text(t)))   => TungMsg::Text(t),
+                    Some(Ok(AxMsg::Binary(b))) => TungMsg::Binary(b),
+                    Some(Ok(AxMsg::Ping(p)))   => TungMsg::Ping(p),
+                    Some(Ok(AxMsg::Pong(p)))   => TungMsg::Pong(p),
+                    _ => break,
+                },
+                // Keystrokes from share guests with write access
+                Some(text) = injected.recv() => TungMsg::Text(text),
             };
             if utx.send(m).await.is_err() { break; }
         }
     };
 
     let u2c = async {
-        while let Some(Ok(msg)) = urx.next().await {
+        'relay: loop {
+            let msg = tokio::select! {
+                m = urx.next() => m,
+                Some(text) = side.recv() => {
+                    if ctx.send(AxMsg::Text(text)).await.is_err() { break; }
+                    continue;
+                }
+                code = conn.disconnected() => {
+                    let reason = if code == proxy::CLOSE_MAINTENANCE { "maintenance" } else { "disconnected" };
+                    let _ = ctx
+                        .send(AxMsg::Close(Some(CloseFrame { code, reason: reason.into() })))
+                        .await;
+                    break;
+                }
+            };
+            let Some(Ok(msg)) = msg else { break };
+            // Notifications, bells, titles and clipboard writes in ttyd
+            // output ('0' frames) follow the frame as side-channel messages
+            let mut events = Vec::new();
             let m = match msg {
                 TungMsg::Text(t)   => AxMsg::Text(t),
-                TungMsg::Binary(b) => AxMsg::Binary(b),
+                TungMsg::Binary(b) => {
+                    conn.mirror(&b);
+                    if let Some(output) = b.strip_prefix(b"0") {
+                        events = scanner.feed(output);
+                    }
+                    AxMsg::Binary(b)
+                }
                 TungMsg::Ping(p)   => AxMsg::Ping(p),
                 TungMsg::Pong(p)   => AxMsg::Pong(p),
                 TungMsg::Close(_) | TungMsg::Frame(_) => break,
             };
             if ctx.send(m).await.is_err() { break; }
+            for event in events {
+                let text = serde_json::to_string(&event).expect("event serializes");
+                if ctx.send(AxMsg::Text(text)).await.is_err() { break 'relay; }
+            }
         }
     };
 
     tokio::select! { _ = c2u => {}, _ = u2c => {} }
 }
 
-async fn handle_clear_session(
+/// Read-only mirror of a user's live terminal for support. Output frames of
+/// the user's most recent browser connection are copied to the admin; every
+/// frame the admin sends (input, resize, auth) is dropped, so the user's
+/// terminal is never disturbed.
+/// Route: GET /api/admin/users/:username/spectate/ws
+async fn handle_admin_spectate_ws(
     State(state): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<Value>, AppError> {
+    Path(username): Path<String>,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> Result<Response, AppError> {
     let user = state.authenticate(&headers).await?;
-    state.sessions.kill(&user.username);
-    if !state.cfg.dev_mode {
-        let _ = state.soju.delete_user(&user.username).await;
-    }
-    Ok(Json(json!({"success": true})))
+    let result = require(&user, Permission::SpectateSessions).and_then(|_| {
+        state
+            .conns
+            .subscribe(&username)
+            .ok_or_else(|| AppError::NotFound(format!("{} has no live terminal", username)))
+    });
+    state.audit(&user, "session.spectate", Some(&username), json!({}), &result).await;
+    let mirror = result?;
+
+    let (ip, ua) = client_info(&headers);
+    let conn = state.conns.register(
+        &user.username,
+        proxy::ConnKind::Spectate { target: username.clone() },
+        ip,
+        ua,
+    );
+    let conns = Arc::clone(&state.conns);
+    let notify = state.runtime().spectate_notify;
+
+    Ok(ws
+        .protocols(["tty"])
+        .on_upgrade(move |client| async move {
+            if notify {
+                conns.notify(&username, &json!({"type": "spectate", "watching": true}));
+            }
+            mirror_ws(client, mirror, conn, None, None).await;
+            if notify && conns.spectators(&username).is_empty() {
+                conns.notify(&username, &json!({"type": "spectate", "watching": false}));
+            }
+        })
+        .into_response())
 }
 
-// ── Admin handlers ────────────────────────────────────────────────────────────
+/// Relay a mirrored terminal to a watcher (spectator or share guest),
+/// starting with the owner's recent output so the screen is not blank
+/// until the next repaint. With `input` set, ttyd input frames ('0' + data) from the watcher are
+/// injected into the owner's terminal; everything else the watcher sends —
+/// auth, resize, pause/resume — is dropped. The connection is closed at
+/// `deadline` (unix ms) if one is given.
+async fn mirror_ws(
+    client: axum::extract::ws::WebSocket,
+    mirror: proxy::Mirror,
+    conn: proxy::Handle,
+    input: Option<tokio::sync::mpsc::UnboundedSender<String>>,
+    deadline: Option<i64>,
+) {
+    use axum::extract::ws::Message as AxMsg;
+    use tokio::sync::broadcast::error::RecvError;
 
-async fn handle_admin_users(
-    State(state): State<AppState>,
+    let (mut ctx, mut crx) = client.split();
+    let proxy::Mirror { backlog, frames: mut mirror } = mirror;
+
+    let inbound = async {
+        while let Some(Ok(msg)) = crx.next().await {
+            match msg {
+                AxMsg::Text(t) if t.starts_with('0') => {
+                    if let Some(ref tx) = input {
+                        if tx.send(t).is_err() { break; }
+                    }
+                }
+                AxMsg::Close(_) => break,
+                _ => {}
+            }
+        }
+    };
+
+    let expired = async {
+        match deadline {
+            Some(t) => {
+                let ms = (t - store::now_ms()).max(0) as u64;
+                tokio::time::sleep(std::time::Duration::from_millis(ms)).await
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    let outbound = async {
+        tokio::pin!(expired);
+        // Draw the screen as it is now before the live output
+        if backlog.len() > 1 && ctx.send(AxMsg::Binary(backlog)).await.is_err() {
+            return;
+        }
+        loop {
+            let frame = tokio::select! {
+                f = mirror.recv() => f,
+                _ = conn.disconnected() => break,
+                _ = &mut expired => break,
+            };
+            match frame {
+                Ok(b) => {
+                    if ctx.send(AxMsg::Binary(b)).await.is_err() { break; }
+                }
+                Err(RecvError::Lagged(n)) => warn!("mirror {} skipped {} frames", conn.id, n),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    tokio::select! { _ = inbound => {}, _ = outbound => {} }
+}
+
+// ── Announcements ─────────────────────────────────────────────────────────────
+
+/// Route: GET /api/events — server-sent event stream (announcements, …).
+async fn handle_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use tokio::sync::broadcast::error::RecvError;
+
+    state.authenticate(&headers).await?;
+
+    // Send the current maintenance status first so late joiners see it.
+    let initial = ServerEvent { kind: "maintenance", data: json!(state.maintenance.status()) };
+    let init = (Some(initial), state.events.subscribe(), state.shutdown.clone());
+
+    let stream = futures_util::stream::unfold(init, |(pending, mut rx, mut shutdown)| async move {
+        if let Some(ev) = pending {
+            let event = Event::default().event(ev.kind).data(ev.data.to_string());
+            return Some((Ok::<_, std::convert::Infallible>(event), (None, rx, shutdown)));
+        }
+        loop {
+            let next = tokio::select! {
+                r = rx.recv() => r,
+                _ = shutdown.wait_for(|down| *down) => return None,
+            };
+            match next {
+                Ok(ev) => {
+                    let event = Event::default().event(ev.kind).data(ev.data.to_string());
+                    return Some((Ok(event), (None, rx, shutdown)));
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()).into_response())
+}
+
+/// Route: GET /api/announcements — announcement history, newest first.
+async fn handle_announcements(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    state.authenticate(&headers).await?;
+    let rows = state.store.list_announcements(100).await.map_err(AppError::from)?;
+    Ok(Json(json!({"announcements": rows})))
+}
+
+#[derive(Deserialize, Serialize)]
+struct AnnounceBody {
+    message: String,
+    /// Also deliver as an IRC server notice through soju (default true)
+    irc: Option<bool>,
+}
+
+/// Store an announcement, push it to connected browsers and, unless
+/// disabled, send it into everyone's IRC client as a soju server notice.
+/// Route: POST /api/admin/announce
+async fn handle_admin_announce(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<AnnounceBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<store::Announcement, AppError> = async {
+        require(&user, Permission::Announce)?;
+        let message = body.message.trim();
+        if message.is_empty() || message.len() > 400 {
+            return Err(AppError::BadRequest("message must be 1–400 bytes".into()));
+        }
+        if message.contains(['\r', '\n']) {
+            return Err(AppError::BadRequest("message must be a single line".into()));
+        }
+
+        let mut ann = state.store.create_announcement(&user.username, message).await?;
+        let _ = state.events.send(ServerEvent { kind: "announcement", data: json!(ann) });
+
+        if body.irc.unwrap_or(true) && !state.cfg.dev_mode {
+            let res = state.soju.server_notice(message).await;
+            let err = res.as_ref().err().map(|e| format!("{:#}", e));
+            if let Some(ref e) = err {
+                warn!("announcement {} IRC delivery failed: {}", ann.id, e);
+            }
+            state.store.set_announcement_irc(ann.id, res.is_ok(), err.as_deref()).await?;
+            ann.irc_delivered = Some(res.is_ok());
+            ann.irc_error = err;
+        }
+        Ok(ann)
+    }
+    .await;
+    state.audit(&user, "announce", None, json!(body), &result).await;
+    Ok(Json(json!({"announcement": result?})))
+}
+
+// ── Share links ───────────────────────────────────────────────────────────────
+
+#[derive(Deserialize, Serialize)]
+struct ShareBody {
+    /// "read" (default) or "write"
+    scope: Option<String>,
+    /// humantime duration, default "1h"
+    ttl: Option<String>,
+}
+
+/// Mint a signed, expiring link to the caller's live terminal.
+/// Route: POST /api/session/share
+async fn handle_create_share(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<ShareBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<Value, AppError> = async {
+        let scope: share::Scope = body
+            .scope
+            .as_deref()
+            .unwrap_or("read")
+            .parse()
+            .map_err(|e: anyhow::Error| AppError::BadRequest(e.to_string()))?;
+        let ttl = humantime::parse_duration(body.ttl.as_deref().unwrap_or("1h"))
+            .map_err(|e| AppError::BadRequest(format!("invalid ttl: {}", e)))?;
+        let max_ttl = state.runtime().max_share_ttl;
+        if ttl > max_ttl {
+            return Err(AppError::BadRequest(format!(
+                "ttl must be at most {}",
+                humantime::format_duration(max_ttl)
+            )));
+        }
+
+        let id = share::new_share_id();
+        let expires_at = store::now_ms() + ttl.as_millis() as i64;
+        state
+            .store
+            .create_share(&id, &user.username, scope.as_str(), expires_at)
+            .await?;
+        let token = share::sign(&state.share_secret, &id, expires_at);
+
+        Ok(json!({
+            "id":        id,
+            "scope":     scope,
+            "expiresAt": expires_at,
+            "token":     token,
+            "url":       format!("{}/?share={}", state.cfg.base_url.trim_end_matches('/'), token),
+        }))
+    }
+    .await;
+    state.audit(&user, "share.create", Some(&user.username), json!(body), &result).await;
+    Ok(Json(result?))
+}
+
+/// Route: GET /api/session/shares — the caller's active share links.
+async fn handle_list_shares(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let shares = state.store.list_active_shares(&user.username).await.map_err(AppError::from)?;
+    let rows: Vec<Value> = shares
+        .iter()
+        .map(|s| {
+            json!({
+                "id":        s.id,
+                "scope":     s.scope,
+                "createdAt": s.created_at,
+                "expiresAt": s.expires_at,
+                "guests":    state.conns.share_guests(&s.id),
+            })
+        })
+        .collect();
+    Ok(Json(json!({"shares": rows})))
+}
+
+/// Revoke a share link and drop any guest attached through it.
+/// Route: DELETE /api/session/shares/:id
+async fn handle_revoke_share(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        if !state.store.revoke_share(&id, &user.username).await? {
+            return Err(AppError::NotFound(format!("no active share {}", id)));
+        }
+        for guest in state.conns.share_guests(&id) {
+            state.conns.disconnect(guest.id);
+        }
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "share.revoke", Some(&user.username), json!({"id": id}), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+#[derive(Deserialize)]
+struct ShareWsQuery {
+    token: String,
+}
+
+/// Attach to another user's terminal through a share link. The guest must
+/// be authenticated themselves; the link only grants access to the owner's
+/// live terminal, read-only or read-write depending on its scope.
+/// Route: GET /share/ws?token=
+async fn handle_share_ws(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<ShareWsQuery>,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let invalid = || AppError::Unauthorized("Invalid or expired share link".into());
+
+    let (id, _) = share::verify(&state.share_secret, &q.token).ok_or_else(invalid)?;
+    let record = state
+        .store
+        .get_share(&id)
+        .await
+        .map_err(AppError::from)?
+        .filter(|s| s.is_active())
+        .ok_or_else(invalid)?;
+    let scope: share::Scope = record.scope.parse()?;
+
+    let result = state
+        .conns
+        .subscribe(&record.owner)
+        .ok_or_else(|| AppError::NotFound(format!("{} has no live terminal", record.owner)));
+    state
+        .audit(&user, "share.join", Some(&record.owner), json!({"id": id, "scope": scope}), &result)
+        .await;
+    let mirror = result?;
+    let input = match scope {
+        share::Scope::Write => state.conns.input(&record.owner),
+        share::Scope::Read => None,
+    };
+
+    let (ip, ua) = client_info(&headers);
+    let conn = state.conns.register(
+        &user.username,
+        proxy::ConnKind::Share { owner: record.owner.clone(), share_id: id },
+        ip,
+        ua,
+    );
+
+    Ok(ws
+        .protocols(["tty"])
+        .on_upgrade(move |client| mirror_ws(client, mirror, conn, input, Some(record.expires_at)))
+        .into_response())
+}
+
+async fn handle_clear_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    state.sessions.kill(&user.username);
+    if !state.cfg.dev_mode {
+        let _ = state.soju.delete_user(&user.username).await;
+    }
+    state.audit(&user, "session.clear", Some(&user.username), json!({}), &Ok(())).await;
+    Ok(Json(json!({"success": true})))
+}
+
+// ── Personal data ─────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    /// Include soju_password and unredacted client config passwords
+    secrets: Option<bool>,
+}
+
+/// Everything stored about the caller, as a zip download.
+/// Route: GET /api/me/export[?secrets=true]
+async fn handle_export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let include_secrets = q.secrets.unwrap_or(false);
+    let result: Result<Vec<u8>, AppError> = async {
+        let username = user.username.as_str();
+        let record = state.store.get_user(username).await?;
+        let logins = state.store.list_logins(username, 10_000).await?;
+        let shares = state.store.list_active_shares(username).await?;
+        let by = AuditFilter { actor: Some(username.into()), limit: 10_000, ..Default::default() };
+        let about = AuditFilter { target: Some(username.into()), limit: 10_000, ..Default::default() };
+        let (by, _) = state.store.list_audit(&by).await?;
+        let (about, _) = state.store.list_audit(&about).await?;
+        let ssh_keys = state.store.list_ssh_keys(username).await?;
+        // Names and last use only; the password digests stay in the store
+        let app_passwords = state.store.list_app_passwords(username).await?;
+        let preferences = json!({
+            "client": state.store.client_preference(username).await?,
+            "sojuPasswordRotatedAt": state.store.soju_rotations().await?.get(username),
+        });
+        let suspension = state.store.suspension(username).await?;
+        let networks = if state.cfg.dev_mode {
+            json!([])
+        } else {
+            match state.soju.list_networks(username).await {
+                Ok(n) => json!(n),
+                Err(e) => json!({"error": format!("{:#}", e)}),
+            }
+        };
+        let sasl: Vec<Value> = state
+            .store
+            .list_network_sasl(username)
+            .await?
+            .into_iter()
+            .map(|c| {
+                let password = include_secrets
+                    .then(|| state.keys.open(&store::sasl_context(username, &c.network), &c.sealed_password).ok())
+                    .flatten();
+                json!({
+                    "network": c.network,
+                    "username": c.sasl_username,
+                    "password": password,
+                    "updatedAt": c.updated_at,
+                })
+            })
+            .collect();
+
+        let account = json!({
+            "username":  username,
+            "email":     user.email,
+            "groups":    user.groups,
+            "roles":     user.roles,
+            "firstSeen": record.as_ref().map(|r| r.first_seen),
+            "lastSeen":  record.as_ref().map(|r| r.last_seen),
+        });
+        let readme = format!(
+            "Data export for {} ({}).\n\n\
+             account.json         your account and roles\n\
+             logins.json          login and terminal connection history\n\
+             shares.json          active terminal share links\n\
+             audit.json           actions you took, and actions taken on your account\n\
+                                  (including past suspensions)\n\
+             suspension.json      your current suspension, if any\n\
+             preferences.json     your terminal client and last soju password rotation\n\
+             ssh_keys.json        public keys registered for the SSH gateway\n\
+             app_passwords.json   app passwords you made (names and last use, not the passwords)\n\
+             soju_networks.json   IRC networks configured in the bouncer\n\
+             upstream_sasl.json   saved NickServ/SASL logins for those networks\n\
+             files/               your session directory (client config, logs, scripts)\n\n{}\n",
+            username,
+            humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
+            if include_secrets {
+                "Passwords are included. Keep this file safe."
+            } else {
+                "Passwords are redacted. Export with ?secrets=true to include them."
+            },
+        );
+        let documents = [
+            ("account.json", account),
+            ("logins.json", json!(logins)),
+            ("shares.json", json!(shares)),
+            ("audit.json", json!({"byYou": by, "aboutYou": about})),
+            ("suspension.json", json!(suspension)),
+            ("preferences.json", preferences),
+            ("ssh_keys.json", json!(ssh_keys)),
+            ("app_passwords.json", json!(app_passwords)),
+            ("soju_networks.json", networks),
+            ("upstream_sasl.json", json!(sasl)),
+        ];
+        let user_dir = state.cfg.sessions_dir.join(username);
+        // The file itself is sealed; export what it holds
+        let soju_password = match include_secrets && !state.cfg.dev_mode {
+            true => state.soju.read_password(username).await?,
+            false => None,
+        };
+        let zip = tokio::task::spawn_blocking(move || {
+            let mut entries = vec![("README.txt".to_string(), readme.into_bytes())];
+            for (name, doc) in &documents {
+                entries.push((name.to_string(), serde_json::to_vec_pretty(doc)?));
+            }
+            entries.extend(export::user_files(&user_dir, include_secrets)?);
+            if let Some(password) = soju_password {
+                if let Some(entry) = entries.iter_mut().find(|(name, _)| name == "files/soju_password") {
+                    entry.1 = password.into_bytes();
+                }
+            }
+            export::zip(&entries)
+        })
+        .await
+        .map_err(anyhow::Error::from)??;
+        Ok(zip)
+    }
+    .await;
+    state.audit(&user, "account.export", None, json!({"secrets": include_secrets}), &result).await;
+
+    let disposition = format!("attachment; filename=\"irssi-v5-{}-export.zip\"", user.username);
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "application/zip".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, disposition),
+        ],
+        result?,
+    )
+        .into_response())
+}
+
+/// How long a deletion confirmation token stays valid.
+const DELETE_CONFIRM_TTL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Deletion tokens are signed (in their own MAC domain, see
+/// `share::sign_deletion`) over a per-user id, so no pending state has to
+/// be kept.
+fn deletion_id(username: &str) -> String {
+    format!("delete-{}", hex::encode(username))
+}
+
+/// Step 1 of account deletion: issue a short-lived confirmation token.
+/// Route: POST /api/me/delete
+async fn handle_request_deletion(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let expires_at = store::now_ms() + DELETE_CONFIRM_TTL.as_millis() as i64;
+    let token = share::sign_deletion(&state.share_secret, &deletion_id(&user.username), expires_at);
+    Ok(Json(json!({"token": token, "expiresAt": expires_at})))
+}
+
+#[derive(Deserialize)]
+struct DeleteAccountBody {
+    token: String,
+}
+
+/// Step 2: with the token from step 1, delete the account — Store rows,
+/// session directory and soju user. The audit log keeps its entries.
+/// Route: DELETE /api/me
+async fn handle_delete_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<DeleteAccountBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let username = user.username.clone();
+    let result: Result<(), AppError> = async {
+        match share::verify_deletion(&state.share_secret, &body.token) {
+            Some((id, exp)) if id == deletion_id(&username) && exp > store::now_ms() => {}
+            _ => return Err(AppError::Forbidden),
+        }
+        state.conns.disconnect_user(&username);
+        state.irc_proxy.disconnect_user(&username);
+        state.sessions.kill(&username);
+        if state.cfg.dev_mode {
+            let dir = state.cfg.sessions_dir.join(&username);
+            if dir.exists() {
+                tokio::fs::remove_dir_all(&dir).await.map_err(anyhow::Error::from)?;
+            }
+        } else {
+            state.soju.delete_user(&username).await?;
+        }
+        state.store.delete_user(&username).await?;
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "account.delete", Some(&username), json!({}), &result).await;
+    result?;
+    info!("{} deleted their account", username);
+    Ok(Json(json!({"success": true})))
+}
+
+// ── irssi settings ────────────────────────────────────────────────────────────
+
+/// Route: GET /api/irssi/settings — the curated settings from the caller's
+/// irssi config, with irssi's defaults filled in.
+async fn handle_irssi_settings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let path = state.cfg.sessions_dir.join(&user.username).join("config");
+    let conf = irssi::read_config(&path).await?;
+    Ok(Json(json!({ "settings": irssi::settings::read(&conf) })))
+}
+
+/// Change some of the curated settings in the caller's irssi config, then
+/// have a running irssi reload it. `applied` is false when there is no
+/// running session; the change takes effect on the next start.
+/// Route: PATCH /api/irssi/settings
+async fn handle_update_irssi_settings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(patch): Json<irssi::settings::Settings>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let dir = state.cfg.sessions_dir.join(&user.username);
+    let path = dir.join("config");
+    let result: Result<(irssi::Config, bool), AppError> = async {
+        let mut conf = irssi::read_config(&path).await?;
+        irssi::settings::apply(&mut conf, &patch).map_err(|e| AppError::BadRequest(e.to_string()))?;
+        tokio::fs::create_dir_all(&dir).await.map_err(anyhow::Error::from)?;
+        irssi::write_config(&path, &conf).await?;
+        // Through the control script if it's running, else by signal
+        let applied = state.sessions.send_command(&dir, None, "/reload").await.is_ok()
+            || state.sessions.reload_config(&path);
+        Ok((conf, applied))
+    }
+    .await;
+    state.audit(&user, "irssi.settings", Some(&user.username), json!(patch), &result).await;
+    let (conf, applied) = result?;
+    Ok(Json(json!({ "settings": irssi::settings::read(&conf), "applied": applied })))
+}
+
+#[derive(Deserialize, Serialize)]
+struct IrssiCommandBody {
+    command: String,
+    #[serde(default)]
+    args: String,
+    /// Chatnet to run it on; the active server if omitted
+    network: Option<String>,
+}
+
+/// Run one allowlisted command (join, part, away, nick, reload, save) in
+/// the caller's running irssi.
+/// Route: POST /api/irssi/command
+async fn handle_irssi_command(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<IrssiCommandBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        let line = irssi::command_line(&body.command, &body.args).map_err(|e| AppError::BadRequest(e.to_string()))?;
+        let dir = state.cfg.sessions_dir.join(&user.username);
+        state
+            .sessions
+            .send_command(&dir, body.network.as_deref(), &line)
+            .await
+            .map_err(|e| AppError::Conflict(format!("{:#}", e)))
+    }
+    .await;
+    state.audit(&user, "irssi.command", Some(&user.username), json!(body), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+// ── Script and theme catalog ──────────────────────────────────────────────────
+
+/// The catalog of scripts and themes, each marked enabled if the caller has
+/// it installed.
+/// Route: GET /api/catalog
+async fn handle_catalog(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let home = state.cfg.sessions_dir.join(&user.username);
+    Ok(Json(json!({
+        "scripts": state.catalog.list(catalog::Kind::Script, &home)?,
+        "themes": state.catalog.list(catalog::Kind::Theme, &home)?,
+    })))
+}
+
+/// The catalog section of an existing entry. Entries go into an irssi
+/// home, so only irssi users get them.
+async fn catalog_target(state: &AppState, username: &str, kind: &str, name: &str) -> Result<catalog::Kind, AppError> {
+    let kind = kind.parse().map_err(|e: anyhow::Error| AppError::NotFound(e.to_string()))?;
+    if !state.catalog.contains(kind, name) {
+        return Err(AppError::NotFound(format!("no {} {:?} in the catalog", kind.dir(), name)));
+    }
+    let client = state.client_for(username).await;
+    if client != Client::Irssi {
+        return Err(AppError::Conflict(format!("scripts and themes are for irssi; your session runs {}", client.as_str())));
+    }
+    Ok(kind)
+}
+
+/// 409 when a file the catalog did not write (or that the user changed)
+/// is where the entry goes; the catalog never replaces or removes those.
+fn catalog_conflict(state: &AppState, kind: catalog::Kind, name: &str, home: &std::path::Path) -> Result<(), AppError> {
+    match state.catalog.conflict(kind, name, home)? {
+        Some(path) => Err(AppError::Conflict(format!(
+            "your own {} is in the way; it was not installed from the catalog or has been changed",
+            path.strip_prefix(home).unwrap_or(&path).display()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Install a catalog script (loaded by a running irssi at once and at every
+/// start) or theme (irssi reloads, so a theme in use is re-read; pick one
+/// with the `theme` setting).
+/// Route: PUT /api/catalog/:kind/:name
+async fn handle_enable_catalog(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((kind, name)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<bool, AppError> = async {
+        let kind = catalog_target(&state, &user.username, &kind, &name).await?;
+        let home = state.cfg.sessions_dir.join(&user.username);
+        tokio::fs::create_dir_all(&home).await.map_err(anyhow::Error::from)?;
+        catalog_conflict(&state, kind, &name, &home)?;
+        state.catalog.install(kind, &name, &home)?;
+        let command = match kind {
+            catalog::Kind::Script => format!("/script load {}", name),
+            catalog::Kind::Theme => "/reload".to_string(),
+        };
+        Ok(state.sessions.send_command(&home, None, &command).await.is_ok())
+    }
+    .await;
+    state.audit(&user, "catalog.enable", Some(&user.username), json!({"kind": kind, "name": name}), &result).await;
+    Ok(Json(json!({"enabled": true, "applied": result?})))
+}
+
+/// Remove a script (a running irssi unloads it) or theme.
+/// Route: DELETE /api/catalog/:kind/:name
+async fn handle_disable_catalog(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((kind, name)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<bool, AppError> = async {
+        let kind = catalog_target(&state, &user.username, &kind, &name).await?;
+        let home = state.cfg.sessions_dir.join(&user.username);
+        catalog_conflict(&state, kind, &name, &home)?;
+        if !state.catalog.remove(kind, &name, &home)? {
+            return Err(AppError::NotFound(format!("{} is not enabled", name)));
+        }
+        Ok(match kind {
+            catalog::Kind::Script => state.sessions.send_command(&home, None, &format!("/script unload {}", name)).await.is_ok(),
+            catalog::Kind::Theme => false,
+        })
+    }
+    .await;
+    state.audit(&user, "catalog.disable", Some(&user.username), json!({"kind": kind, "name": name}), &result).await;
+    Ok(Json(json!({"enabled": false, "applied": result?})))
+}
+
+// ── Devices ───────────────────────────────────────────────────────────────────
+
+async fn devices_json(state: &AppState, username: &str) -> Result<Value, AppError> {
+    let logins = state.store.list_logins(username, 50).await.map_err(AppError::from)?;
+    Ok(json!({
+        "active": state.conns.list(Some(username)),
+        "logins": logins,
+    }))
+}
+
+/// Route: GET /api/me/devices — live terminal connections and recent logins.
+async fn handle_my_devices(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    Ok(Json(devices_json(&state, &user.username).await?))
+}
+
+/// Route: DELETE /api/me/devices/:id
+async fn handle_my_device_disconnect(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result = match state.conns.get(id) {
+        Some(c) if c.username == user.username => {
+            state.conns.disconnect(id);
+            Ok(())
+        }
+        _ => Err(AppError::NotFound(format!("no live connection {}", id))),
+    };
+    state.audit(&user, "device.disconnect", Some(&user.username), json!({"id": id}), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+/// Most app passwords one user may have.
+const MAX_APP_PASSWORDS: usize = 20;
+
+#[derive(Deserialize)]
+struct AppPasswordBody {
+    name: String,
+}
+
+/// The caller's app passwords, with when and from where each was last used.
+/// Route: GET /api/app-passwords
+async fn handle_list_app_passwords(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let passwords = state.store.list_app_passwords(&user.username).await?;
+    Ok(Json(json!({
+        "appPasswords": passwords,
+        "proxy": state.cfg.irc_proxy_listen.is_some(),
+    })))
+}
+
+/// Make a named password for one IRC client. The password is in this
+/// response only.
+/// Route: POST /api/app-passwords
+async fn handle_create_app_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<AppPasswordBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let name = body.name.trim().to_string();
+    let result: Result<Value, AppError> = async {
+        if name.is_empty() || name.chars().count() > 64 || name.chars().any(char::is_control) {
+            return Err(AppError::BadRequest("name must be 1–64 characters".into()));
+        }
+        if state.store.list_app_passwords(&user.username).await?.len() >= MAX_APP_PASSWORDS {
+            return Err(AppError::Conflict(format!("at most {} app passwords", MAX_APP_PASSWORDS)));
+        }
+        let password = ircproxy::new_password();
+        let id = state
+            .store
+            .create_app_password(&user.username, &name, &ircproxy::password_hash(&password))
+            .await?
+            .ok_or_else(|| AppError::Conflict(format!("an app password named {} exists", name)))?;
+        Ok(json!({"id": id, "name": name, "username": user.username, "password": password}))
+    }
+    .await;
+    state.audit(&user, "app_password.create", Some(&user.username), json!({"name": name}), &result).await;
+    Ok(Json(result?))
+}
+
+/// Revoke an app password and drop the connections that use it.
+/// Route: DELETE /api/app-passwords/:id
+async fn handle_delete_app_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        if !state.store.delete_app_password(&user.username, id).await? {
+            return Err(AppError::NotFound(format!("app password {}", id)));
+        }
+        state.irc_proxy.revoke(id);
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "app_password.revoke", Some(&user.username), json!({"id": id}), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+/// Most SSH keys one user may have.
+const MAX_SSH_KEYS: usize = 20;
+
+#[derive(Deserialize)]
+struct SshKeyBody {
+    key: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// The caller's public keys for the SSH gateway.
+/// Route: GET /api/ssh-keys
+async fn handle_list_ssh_keys(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let keys = state.store.list_ssh_keys(&user.username).await?;
+    Ok(Json(json!({
+        "sshKeys": keys,
+        "ssh": state.cfg.ssh_listen.is_some(),
+    })))
+}
+
+/// Add a public key; SSH logins with it attach to the caller's irssi. The
+/// name defaults to the key's comment.
+/// Route: POST /api/ssh-keys
+async fn handle_add_ssh_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<SshKeyBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<Value, AppError> = async {
+        let key = ssh::parse_public_key(&body.key).map_err(|e| AppError::BadRequest(e.to_string()))?;
+        let name = match body.name.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+            Some(name) => name.to_string(),
+            None => key.comment.clone().unwrap_or_else(|| key.algorithm.clone()),
+        };
+        if name.chars().count() > 64 || name.chars().any(char::is_control) {
+            return Err(AppError::BadRequest("name must be 1–64 characters".into()));
+        }
+        if state.store.list_ssh_keys(&user.username).await?.len() >= MAX_SSH_KEYS {
+            return Err(AppError::Conflict(format!("at most {} SSH keys", MAX_SSH_KEYS)));
+        }
+        let id = state
+            .store
+            .add_ssh_key(&user.username, &name, &key.algorithm, &key.key, &key.fingerprint)
+            .await?
+            .ok_or_else(|| AppError::Conflict("this key is already registered".into()))?;
+        Ok(json!({"id": id, "name": name, "fingerprint": key.fingerprint}))
+    }
+    .await;
+    let detail = result.as_ref().cloned().unwrap_or_else(|_| json!({"name": body.name}));
+    state.audit(&user, "ssh_key.add", Some(&user.username), detail, &result).await;
+    Ok(Json(result?))
+}
+
+/// Remove a public key. Logins already attached stay until they detach.
+/// Route: DELETE /api/ssh-keys/:id
+async fn handle_delete_ssh_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        if !state.store.delete_ssh_key(&user.username, id).await? {
+            return Err(AppError::NotFound(format!("SSH key {}", id)));
+        }
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "ssh_key.remove", Some(&user.username), json!({"id": id}), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+// ── SSH gateway (gateway socket only) ─────────────────────────────────────────
+//
+// Served only to `irssi-v5 ssh-keys` and `ssh-attach` run by our sshd (see
+// `ssh::run_by_sshd`), so the caller is trusted with the username.
+
+/// Who owns an SSH key, for `irssi-v5 ssh-keys` (sshd's
+/// AuthorizedKeysCommand). Keys of suspended users are not found.
+/// Route: GET /api/ssh/keys/:fingerprint
+async fn handle_ssh_key_owner(
+    State(state): State<AppState>,
+    Path(fingerprint): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let not_found = || AppError::NotFound(format!("SSH key {}", fingerprint));
+    let (username, id) = state.store.find_ssh_key(&fingerprint).await?.ok_or_else(not_found)?;
+    if state.check_suspension(&username).await.is_err() {
+        return Err(not_found());
+    }
+    Ok(Json(json!({"username": username, "id": id})))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SshSessionBody {
+    username: String,
+    key_id: i64,
+}
+
+/// Start a user's irssi for an SSH login and return the dtach socket for
+/// `irssi-v5 ssh-attach` to attach to. sshd has already checked the key.
+/// Route: POST /api/ssh/sessions
+async fn handle_ssh_session(
+    State(state): State<AppState>,
+    Json(body): Json<SshSessionBody>,
+) -> Result<Json<Value>, AppError> {
+    let mut user = User {
+        username: body.username.clone(),
+        email: String::new(),
+        groups: Vec::new(),
+        roles: BTreeSet::new(),
+    };
+    user.roles = state.effective_roles(&user).await;
+    let result: Result<String, AppError> = async {
+        state.check_suspension(&user.username).await?;
+        state.check_maintenance()?;
+        // The key may have been removed since sshd looked it up
+        if !state.store.list_ssh_keys(&user.username).await?.iter().any(|k| k.id == body.key_id) {
+            return Err(AppError::Forbidden);
+        }
+        let _ = state.store.touch(&user.username).await;
+        state.store.touch_ssh_key(body.key_id).await?;
+
+        let user_dir = if state.cfg.dev_mode {
+            let dir = state.cfg.sessions_dir.join(&user.username);
+            tokio::fs::create_dir_all(&dir).await.ok();
+            dir
+        } else {
+            state.ensure_soju_user(&user).await?;
+            state.soju.user_dir(&user.username)
+        };
+        let login = state.soju_login(&user.username).await;
+        let client = state.client_for(&user.username).await;
+        state
+            .sessions
+            .start_detached(&user.username, &user_dir, client, login.as_ref())
+            .await
+            .map_err(|e| {
+                error!("session.start_detached({}): {:#}", user.username, e);
+                AppError::Internal(e)
+            })
+    }
+    .await;
+    state.audit(&user, "ssh.login", Some(&user.username), json!({"keyId": body.key_id}), &result).await;
+    Ok(Json(json!({"socket": result?})))
+}
+
+// ── Admin handlers ────────────────────────────────────────────────────────────
+
+async fn handle_admin_users(
+    State(state): State<AppState>,
     headers: HeaderMap,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    if !user.is_admin { return Err(AppError::Forbidden); }
+    require(&user, Permission::ViewUsers)?;
 
     let users = state.store.list_users().await.map_err(AppError::from)?;
+    let grants = state.store.list_role_grants().await.map_err(AppError::from)?;
+    let suspensions = state.store.list_suspensions().await.map_err(AppError::from)?;
     let rows: Vec<Value> = users
         .iter()
         .map(|u| {
+            let roles: Vec<&str> = grants
+                .iter()
+                .filter(|g| g.username == u.username)
+                .map(|g| g.role.as_str())
+                .collect();
+            let suspended = suspensions
+                .iter()
+                .find(|s| s.username == u.username && !s.is_expired())
+                .map(|s| json!({"reason": s.reason, "until": s.expires_at, "by": s.suspended_by}));
             json!({
                 "username":       u.username,
                 "first_seen":     u.first_seen,
                 "last_seen":      u.last_seen,
                 "is_admin":       u.is_admin != 0,
+                "roles":          roles,
+                "suspended":      suspended,
                 "active_session": state.sessions.is_active(&u.username),
             })
         })
@@ -307,20 +2104,76 @@ async fn handle_admin_kick(
     Path(username): Path<String>,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    if !user.is_admin { return Err(AppError::Forbidden); }
-    state.sessions.kill(&username);
+    let result = require(&user, Permission::KickSessions).map(|_| {
+        state.sessions.kill(&username);
+    });
+    state.audit(&user, "user.kick", Some(&username), json!({}), &result).await;
+    result?;
     Ok(Json(json!({"success": true})))
 }
 
+/// Give a user a new soju password; their running irssi reconnects with it.
+/// 409 while a client that cannot take it is running.
+/// Route: POST /api/admin/users/:username/rotate-soju-password
+async fn handle_admin_rotate_soju_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<bool, AppError> = async {
+        require(&user, Permission::ManageSettings)?;
+        if state.cfg.dev_mode {
+            return Err(AppError::Conflict("no bouncer in dev mode".into()));
+        }
+        match state.rotate_soju_password(&username).await? {
+            SojuRotation::Rotated { reconnected } => Ok(reconnected),
+            SojuRotation::Deferred(client) => Err(AppError::Conflict(format!(
+                "{} is running and only reads the password when it starts; rotate after it exits",
+                client.as_str()
+            ))),
+        }
+    }
+    .await;
+    state.audit(&user, "soju.password.rotate", Some(&username), json!({}), &result).await;
+    Ok(Json(json!({"username": username, "reconnected": result?})))
+}
+
+/// Rotate every provisioned user's soju password.
+/// Route: POST /api/admin/rotate-soju-passwords
+async fn handle_admin_rotate_soju_passwords(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<Value, AppError> = async {
+        require(&user, Permission::ManageSettings)?;
+        if state.cfg.dev_mode {
+            return Err(AppError::Conflict("no bouncer in dev mode".into()));
+        }
+        let out = state.rotate_soju_passwords(None).await?;
+        let failed: Vec<Value> = out.failed.into_iter().map(|(u, e)| json!({"username": u, "error": e})).collect();
+        Ok(json!({"rotated": out.rotated, "deferred": out.deferred, "failed": failed}))
+    }
+    .await;
+    state.audit(&user, "soju.password.rotate_all", None, json!({}), &result).await;
+    Ok(Json(result?))
+}
+
 async fn handle_admin_clear(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path(username): Path<String>,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    if !user.is_admin { return Err(AppError::Forbidden); }
-    state.sessions.kill(&username);
-    let _ = state.soju.delete_user(&username).await;
+    let result = require(&user, Permission::ClearUsers);
+    if result.is_ok() {
+        state.sessions.kill(&username);
+        state.irc_proxy.disconnect_user(&username);
+        let _ = state.soju.delete_user(&username).await;
+    }
+    state.audit(&user, "user.clear", Some(&username), json!({}), &result).await;
+    result?;
     Ok(Json(json!({"success": true})))
 }
 
@@ -330,17 +2183,306 @@ async fn handle_admin_delete_user(
     Path(username): Path<String>,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    if !user.is_admin { return Err(AppError::Forbidden); }
-    if username == user.username {
-        return Err(AppError::Internal(anyhow::anyhow!("cannot delete yourself")));
+    let result: Result<(), AppError> = async {
+        require(&user, Permission::DeleteUsers)?;
+        if username == user.username {
+            return Err(AppError::BadRequest("cannot delete yourself".into()));
+        }
+        state.sessions.kill(&username);
+        state.irc_proxy.disconnect_user(&username);
+        let _ = state.soju.delete_user(&username).await;
+        state.store.delete_user(&username).await.map_err(AppError::from)?;
+        Ok(())
     }
-    state.sessions.kill(&username);
-    let _ = state.soju.delete_user(&username).await;
-    state.store.delete_user(&username).await.map_err(AppError::from)?;
+    .await;
+    state.audit(&user, "user.delete", Some(&username), json!({}), &result).await;
+    result?;
     Ok(Json(json!({"success": true})))
 }
 
-#[derive(Deserialize)]
+/// Route: GET /api/admin/devices — every live connection.
+async fn handle_admin_devices(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    require(&user, Permission::ViewUsers)?;
+    Ok(Json(json!({"connections": state.conns.list(None)})))
+}
+
+/// Route: GET /api/admin/users/:username/devices
+async fn handle_admin_user_devices(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    require(&user, Permission::ViewUsers)?;
+    Ok(Json(devices_json(&state, &username).await?))
+}
+
+/// Route: DELETE /api/admin/devices/:id
+async fn handle_admin_device_disconnect(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let target = state.conns.get(id).map(|c| c.username);
+    let result = require(&user, Permission::KickSessions).and_then(|_| {
+        state
+            .conns
+            .disconnect(id)
+            .map(|_| ())
+            .ok_or_else(|| AppError::NotFound(format!("no live connection {}", id)))
+    });
+    state.audit(&user, "device.disconnect", target.as_deref(), json!({"id": id}), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+#[derive(Deserialize, Serialize)]
+struct SuspendBody {
+    reason: String,
+    /// Optional humantime duration, e.g. "7d" or "12h". Omit for indefinite.
+    duration: Option<String>,
+}
+
+/// Suspend a user: blocks authentication, kills their session and disables
+/// their soju networks so their upstream IRC presence goes away.
+/// Route: POST /api/admin/users/:username/suspend
+async fn handle_admin_suspend(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    Json(body): Json<SuspendBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        require(&user, Permission::SuspendUsers)?;
+        if username == user.username {
+            return Err(AppError::BadRequest("cannot suspend yourself".into()));
+        }
+        let expires_at = match body.duration.as_deref().filter(|d| !d.is_empty()) {
+            Some(d) => {
+                let dur = humantime::parse_duration(d)
+                    .map_err(|e| AppError::BadRequest(format!("invalid duration: {}", e)))?;
+                Some(store::now_ms() + dur.as_millis() as i64)
+            }
+            None => None,
+        };
+
+        state.sessions.kill(&username);
+        state.conns.disconnect_user(&username);
+        state.irc_proxy.disconnect_user(&username);
+
+        // Remember which networks we switched off so unsuspend restores
+        // exactly those, not ones the user had disabled themselves.
+        let mut disabled = Vec::new();
+        if !state.cfg.dev_mode {
+            let networks = state.soju.list_networks(&username).await.unwrap_or_else(|e| {
+                warn!("list networks for {}: {:#}", username, e);
+                Vec::new()
+            });
+            for net in networks.into_iter().filter(|n| n.status != "disabled") {
+                match state.soju.set_network_enabled(&username, &net.name, false).await {
+                    Ok(()) => disabled.push(net.name),
+                    Err(e) => warn!("disable {} for {}: {:#}", net.name, username, e),
+                }
+            }
+        }
+
+        state
+            .store
+            .suspend(&username, &body.reason, &user.username, expires_at, &disabled)
+            .await
+            .map_err(AppError::from)?;
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "user.suspend", Some(&username), json!(body), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+/// Route: POST /api/admin/users/:username/unsuspend
+async fn handle_admin_unsuspend(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        require(&user, Permission::SuspendUsers)?;
+        state.lift_suspension(&username).await.map_err(AppError::from)?;
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "user.unsuspend", Some(&username), json!({}), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
+
+// ── Maintenance mode ──────────────────────────────────────────────────────────
+
+/// Route: GET /api/admin/maintenance
+async fn handle_admin_get_maintenance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    require(&user, Permission::ViewSettings)?;
+    Ok(Json(json!(state.maintenance.status())))
+}
+
+#[derive(Deserialize, Serialize)]
+struct MaintenanceBody {
+    enabled: bool,
+    message: Option<String>,
+    /// humantime countdown after which live sessions are detached, e.g. "10m".
+    /// Omit to only refuse new sessions.
+    #[serde(rename = "drainIn")]
+    drain_in: Option<String>,
+}
+
+/// Turn maintenance mode on or off. While on, new sessions get a 503 with
+/// the message; with drainIn, connected users are warned and their
+/// sessions detached when the countdown ends.
+/// Route: POST /api/admin/maintenance
+async fn handle_admin_post_maintenance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<MaintenanceBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<maintenance::Status, AppError> = async {
+        require(&user, Permission::ManageSettings)?;
+        let message = body
+            .message
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .unwrap_or(maintenance::DEFAULT_MESSAGE)
+            .to_string();
+        let drain_in = match body.drain_in.as_deref().filter(|d| !d.is_empty()) {
+            Some(d) if body.enabled => Some(
+                humantime::parse_duration(d).map_err(|e| AppError::BadRequest(format!("invalid drainIn: {}", e)))?,
+            ),
+            _ => None,
+        };
+
+        state.store.set_setting("maintenance_enabled", &body.enabled.to_string()).await?;
+        state.store.set_setting("maintenance_message", &message).await?;
+
+        let drain_at = drain_in.map(|d| store::now_ms() + d.as_millis() as i64);
+        let task = drain_in.map(|d| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(d).await;
+                state.drain_sessions(proxy::CLOSE_MAINTENANCE);
+                state.maintenance.drain_done();
+                state.broadcast_maintenance();
+            })
+        });
+        let status = maintenance::Status { enabled: body.enabled, message, drain_at };
+        state.maintenance.set(status.clone(), task);
+        state.broadcast_maintenance();
+        Ok(status)
+    }
+    .await;
+    state.audit(&user, "maintenance.update", None, json!(body), &result).await;
+    Ok(Json(json!(result?)))
+}
+
+// ── Backups ───────────────────────────────────────────────────────────────────
+
+/// Route: GET /api/admin/backups
+async fn handle_admin_backups(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    require(&user, Permission::ViewSettings)?;
+    Ok(Json(json!({"backups": backup::list(&state.backup_dir())?})))
+}
+
+/// Take a backup now, into <data_dir>/backups.
+/// Route: POST /api/admin/backups
+async fn handle_admin_create_backup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<Value, AppError> = async {
+        require(&user, Permission::ManageSettings)?;
+        let (path, manifest) = state.create_backup().await?;
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        Ok(json!({"name": name, "manifest": manifest}))
+    }
+    .await;
+    state.audit(&user, "backup.create", None, json!({}), &result).await;
+    Ok(Json(result?))
+}
+
+/// Re-seal every secret at rest with the current app key: SASL passwords and
+/// sealed settings in the Store, and each user's soju password file
+/// (plaintext ones from older versions included). Run after rotating
+/// APP_KEY, before dropping the old key from APP_RETIRED_KEYS.
+/// Route: POST /api/admin/reencrypt
+async fn handle_admin_reencrypt(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<Value, AppError> = async {
+        require(&user, Permission::ManageSettings)?;
+        let keys = Arc::clone(&state.keys);
+        let (store_changed, store_failed) =
+            state.store.reseal_secrets(|context, sealed| keys.reseal(context, sealed)).await?;
+        let (soju_changed, soju_failed) = state.soju.reencrypt().await?;
+        Ok(json!({
+            "keyId": state.keys.current_id(),
+            "store": {"changed": store_changed, "failed": store_failed},
+            "sojuPasswords": {"changed": soju_changed, "failed": soju_failed},
+        }))
+    }
+    .await;
+    state.audit(&user, "secrets.reencrypt", None, json!({}), &result).await;
+    Ok(Json(result?))
+}
+
+/// Download a backup. Archives contain soju passwords, hence ManageSettings.
+/// Route: GET /api/admin/backups/:name
+async fn handle_admin_download_backup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<tokio::fs::File, AppError> = async {
+        require(&user, Permission::ManageSettings)?;
+        if !backup::is_backup_name(&name) {
+            return Err(AppError::NotFound(name.clone()));
+        }
+        tokio::fs::File::open(state.backup_dir().join(&name))
+            .await
+            .map_err(|_| AppError::NotFound(name.clone()))
+    }
+    .await;
+    state.audit(&user, "backup.download", None, json!({"name": name}), &result).await;
+
+    let body = axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(result?));
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "application/gzip".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", name)),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[derive(Deserialize, Serialize)]
 struct SettingsBody {
     #[serde(rename = "maxUsers")]
     max_users: Option<u32>,
@@ -351,7 +2493,7 @@ async fn handle_admin_get_settings(
     headers: HeaderMap,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    if !user.is_admin { return Err(AppError::Forbidden); }
+    require(&user, Permission::ViewSettings)?;
 
     let max_users: u32 = state
         .store
@@ -374,27 +2516,323 @@ async fn handle_admin_post_settings(
     Json(body): Json<SettingsBody>,
 ) -> Result<Json<Value>, AppError> {
     let user = state.authenticate(&headers).await?;
-    if !user.is_admin { return Err(AppError::Forbidden); }
+    let result: Result<(), AppError> = async {
+        require(&user, Permission::ManageSettings)?;
+
+        if let Some(max) = body.max_users {
+            if !(1..=1000).contains(&max) {
+                return Err(AppError::BadRequest("maxUsers must be 1–1000".into()));
+            }
+            state.store.set_setting("max_users", &max.to_string()).await.map_err(AppError::from)?;
+        }
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "settings.update", None, json!(body), &result).await;
+    result?;
+
+    Ok(Json(json!({"success": true})))
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    actor: Option<String>,
+    action: Option<String>,
+    target: Option<String>,
+    /// Unix milliseconds, inclusive
+    since: Option<i64>,
+    /// Unix milliseconds, exclusive
+    until: Option<i64>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Route: GET /api/admin/audit?actor=&action=&target=&since=&until=&limit=&offset=
+async fn handle_admin_audit(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<AuditQuery>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    require(&user, Permission::ViewAudit)?;
+
+    let filter = AuditFilter {
+        actor: q.actor.filter(|s| !s.is_empty()),
+        action: q.action.filter(|s| !s.is_empty()),
+        target: q.target.filter(|s| !s.is_empty()),
+        since: q.since,
+        until: q.until,
+        limit: q.limit.unwrap_or(100).clamp(1, 1000),
+        offset: q.offset.unwrap_or(0).max(0),
+    };
+    let (entries, total) = state.store.list_audit(&filter).await.map_err(AppError::from)?;
+
+    let rows: Vec<Value> = entries
+        .iter()
+        .map(|e| {
+            json!({
+                "id":      e.id,
+                "ts":      e.ts,
+                "actor":   e.actor,
+                "action":  e.action,
+                "target":  e.target,
+                "params":  serde_json::from_str::<Value>(&e.params).unwrap_or(Value::Null),
+                "outcome": e.outcome,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "entries": rows,
+        "total":   total,
+        "limit":   filter.limit,
+        "offset":  filter.offset,
+    })))
+}
+
+// ── Role management ───────────────────────────────────────────────────────────
+
+/// Route: GET /api/admin/roles — role definitions and all stored grants.
+async fn handle_admin_roles(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    require(&user, Permission::ViewUsers)?;
+
+    let roles: Vec<Value> = ALL_ROLES
+        .iter()
+        .map(|r| json!({"name": r, "permissions": r.permissions()}))
+        .collect();
+    let grants = state.store.list_role_grants().await.map_err(AppError::from)?;
+    let groups: Vec<Value> = state
+        .runtime()
+        .role_groups
+        .iter()
+        .map(|(g, r)| json!({"group": g, "role": r}))
+        .collect();
+
+    Ok(Json(json!({"roles": roles, "grants": grants, "groupMappings": groups})))
+}
+
+#[derive(Deserialize, Serialize)]
+struct GrantBody {
+    role: String,
+}
 
-    if let Some(max) = body.max_users {
-        if max < 1 || max > 1000 {
-            return Err(AppError::Internal(anyhow::anyhow!("maxUsers must be 1–1000")));
+/// Route: POST /api/admin/users/:username/roles  {"role": "operator"}
+async fn handle_admin_grant_role(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    Json(body): Json<GrantBody>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        require(&user, Permission::ManageRoles)?;
+        let role: Role = body.role.parse().map_err(|e: anyhow::Error| AppError::BadRequest(e.to_string()))?;
+        state
+            .store
+            .grant_role(&username, role.as_str(), &user.username)
+            .await
+            .map_err(AppError::from)?;
+        if role == Role::Admin {
+            state.sync_soju_admin(&username, true).await;
         }
-        state.store.set_setting("max_users", &max.to_string()).await.map_err(AppError::from)?;
+        Ok(())
     }
+    .await;
+    state.audit(&user, "role.grant", Some(&username), json!(body), &result).await;
+    result?;
+    Ok(Json(json!({"success": true})))
+}
 
+/// Route: DELETE /api/admin/users/:username/roles/:role
+async fn handle_admin_revoke_role(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((username, role)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let user = state.authenticate(&headers).await?;
+    let result: Result<(), AppError> = async {
+        require(&user, Permission::ManageRoles)?;
+        let role: Role = role.parse().map_err(|e: anyhow::Error| AppError::BadRequest(e.to_string()))?;
+        if role == Role::Admin && username == user.username {
+            return Err(AppError::BadRequest("cannot revoke your own admin role".into()));
+        }
+        state
+            .store
+            .revoke_role(&username, role.as_str())
+            .await
+            .map_err(AppError::from)?;
+        if role == Role::Admin {
+            state.sync_soju_admin(&username, false).await;
+        }
+        Ok(())
+    }
+    .await;
+    state.audit(&user, "role.revoke", Some(&username), json!({"role": role}), &result).await;
+    result?;
     Ok(Json(json!({"success": true})))
 }
 
 // ── Main ──────────────────────────────────────────────────────────────────────
 
+/// Everyone in ADMIN_USERS holds the admin role. Grants made at runtime
+/// live in the DB; revoking a bootstrap admin only sticks once they are
+/// removed from ADMIN_USERS too.
+async fn bootstrap_admins(store: &Store, runtime: &Runtime) -> Result<()> {
+    for username in &runtime.admin_users {
+        if store.grant_role(username, Role::Admin.as_str(), "ADMIN_USERS").await? {
+            info!("granted admin role to {} (ADMIN_USERS)", username);
+        }
+    }
+    Ok(())
+}
+
+/// HMAC key for share tokens, kept sealed in the settings table. One an
+/// older version stored in plaintext is sealed on the way.
+async fn load_share_secret(store: &Store, keys: &crypto::Keyring) -> Result<String> {
+    let context = store::setting_context("share_secret");
+    let stored = store.get_setting("share_secret", "").await;
+    if crypto::is_sealed(&stored) {
+        return keys.open(&context, &stored).context("cannot decrypt share_secret (wrong APP_KEY?)");
+    }
+    let secret = if stored.is_empty() { share::new_secret() } else { stored };
+    store.set_setting("share_secret", &keys.seal(&context, &secret)).await?;
+    Ok(secret)
+}
+
+const USAGE: &str = "usage: irssi-v5 [--config <file>] [command]
+
+  config check                         validate the configuration
+  keygen                               print a new random APP_KEY
+  admin <command>                      talk to the running server (see `admin help`)
+  backup [--out <dir>]                 write a backup archive (default <data dir>/backups)
+  restore <archive> [--with-soju]      restore an archive; the server must be stopped
+  ssh-keys <socket> <type> <key>       sshd's AuthorizedKeysCommand (SSH_LISTEN)
+  ssh-attach <socket> <user> <key id>  forced command of SSH logins";
+
+/// Command line: `[--config <file>] [command...]`. The config file can also
+/// be given as CONFIG_FILE.
+fn parse_args() -> Result<(Option<PathBuf>, Vec<String>)> {
+    let mut file = std::env::var_os("CONFIG_FILE").map(PathBuf::from);
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => {
+                file = Some(args.next().ok_or_else(|| anyhow::anyhow!("--config needs a path\n{}", USAGE))?.into())
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => command.push(arg),
+        }
+    }
+    Ok((file, command))
+}
+
+/// `config check`: load and validate the config, print a summary, and exit
+/// non-zero on the first error.
+fn config_check(file: Option<&std::path::Path>) -> Result<()> {
+    let (cfg, rt) = Config::load(file)?;
+    match &cfg.file {
+        Some(p) => println!("config file:  {}", p.display()),
+        None => println!("config file:  (none, env only)"),
+    }
+    println!("port:         {}", cfg.port);
+    println!("dev mode:     {}", cfg.dev_mode);
+    println!("dtach:        {}", cfg.dtach_session);
+    println!("admin users:  {}", rt.admin_users.iter().cloned().collect::<Vec<_>>().join(", "));
+    for n in &rt.networks {
+        println!("network:      {} {}", n.name, n.addr);
+    }
+    if !cfg.dev_mode && (cfg.cf_aud.is_empty() || cfg.cf_team_domain.is_empty()) {
+        anyhow::bail!("CF_AUD and CF_TEAM_DOMAIN must be set (or set DEV_MODE=true)");
+    }
+    println!("config OK");
+    Ok(())
+}
+
+/// `backup`: works whether or not the server is running — VACUUM INTO
+/// gives a consistent snapshot either way.
+async fn backup_cli(file: Option<&std::path::Path>, out: Option<&str>) -> Result<()> {
+    let (cfg, _) = Config::load(file)?;
+    let store = Store::new(cfg.data_dir.join("app.db").to_str().unwrap()).await?;
+    let out = out.map(PathBuf::from).unwrap_or_else(|| cfg.data_dir.join("backups"));
+    let (path, manifest) = backup::create(&store, &cfg.sessions_dir, cfg.soju_db.as_deref(), &out).await?;
+    println!("wrote {} ({} users{})", path.display(), manifest.users.len(),
+        if manifest.soju_db { ", with soju.db" } else { "" });
+    Ok(())
+}
+
+/// `restore`: refuses to run while the server is up, since it swaps the
+/// database and session directories underneath it.
+fn restore_cli(file: Option<&std::path::Path>, archive: &str, with_soju: bool) -> Result<()> {
+    let (cfg, _) = Config::load(file)?;
+    if std::os::unix::net::UnixStream::connect(&cfg.control_socket).is_ok() {
+        anyhow::bail!("irssi-v5 is running — stop it before restoring");
+    }
+    let soju_db = match (with_soju, &cfg.soju_db) {
+        (true, None) => anyhow::bail!("--with-soju needs SOJU_DB to be set"),
+        (true, Some(p)) => Some(p.as_path()),
+        (false, _) => None,
+    };
+    let restored = backup::restore(std::path::Path::new(archive), &cfg.data_dir, &cfg.sessions_dir, soju_db)?;
+    let m = &restored.manifest;
+    let taken = std::time::UNIX_EPOCH + std::time::Duration::from_millis(m.created_at as u64);
+    println!(
+        "restored backup from {} (irssi-v5 {}, {} users)",
+        humantime::format_rfc3339_seconds(taken),
+        m.app_version,
+        m.users.len()
+    );
+    for p in &restored.moved_aside {
+        println!("previous state kept at {}", p.display());
+    }
+    if let Some(p) = &restored.soju_db_left {
+        println!("soju.db was not restored (no --with-soju); it is at {}", p.display());
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let _ = dotenvy::dotenv();
+    let (config_file, command) = parse_args()?;
+    match command.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => {}
+        ["config", "check"] => return config_check(config_file.as_deref()),
+        ["keygen"] => {
+            println!("{}", crypto::generate_key());
+            return Ok(());
+        }
+        ["admin", ..] => {
+            let (cfg, _) = Config::load(config_file.as_deref())?;
+            return control::run_admin(&cfg.control_socket, &command[1..]).await;
+        }
+        ["backup"] => return backup_cli(config_file.as_deref(), None).await,
+        ["backup", "--out", dir] => return backup_cli(config_file.as_deref(), Some(dir)).await,
+        ["restore", archive] => return restore_cli(config_file.as_deref(), archive, false),
+        ["restore", archive, "--with-soju"] => return restore_cli(config_file.as_deref(), archive, true),
+        ["ssh-keys", socket, algorithm, key] => {
+            return ssh::authorized_keys(std::path::Path::new(socket), algorithm, key).await
+        }
+        ["ssh-attach", socket, username, key_id] => {
+            let key_id = key_id.parse().context("key id must be a number")?;
+            return ssh::attach(std::path::Path::new(socket), username, key_id).await;
+        }
+        _ => anyhow::bail!("unknown command '{}'\n{}", command.join(" "), USAGE),
+    }
+
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env().add_directive("irssi_v5=info".parse()?))
         .init();
 
-    let cfg = Arc::new(Config::from_env()?);
+    let (cfg, runtime) = Config::load(config_file.as_deref())?;
+    let cfg = Arc::new(cfg);
 
     if cfg.dev_mode {
         warn!("DEV MODE — CF JWT validation disabled");
@@ -409,46 +2847,237 @@ async fn main() -> Result<()> {
             &cfg.cf_team_domain,
             &cfg.cf_aud,
             cfg.cf_jwks_cache_ttl,
-            cfg.admin_users.clone(),
         ))
     };
 
     let db_path = cfg.data_dir.join("app.db");
     let store = Store::new(db_path.to_str().unwrap()).await?;
-    
+
+    bootstrap_admins(&store, &runtime).await?;
+
+    let keys = Arc::new(crypto::Keyring::load(cfg.app_key.as_deref(), &cfg.app_key_file, &cfg.app_retired_keys)?);
+    let share_secret = load_share_secret(&store, &keys).await?;
+
     let sessions = SessionManager::new(cfg.ttyd_base_port, cfg.dtach_session);
     let soju = SojuManager::new(
-        cfg.soju_socket.clone(),
         cfg.sessions_dir.clone(),
         cfg.soju_addr.clone(),
         cfg.irc_addr.clone(),
         cfg.irc_network_name.clone(),
+        Arc::clone(&keys),
+    );
+
+    let tls = match (&cfg.irc_proxy_tls_cert, &cfg.irc_proxy_tls_key) {
+        (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+        (None, None) => None,
+        _ => anyhow::bail!("set both IRC_PROXY_TLS_CERT and IRC_PROXY_TLS_KEY, or neither"),
+    };
+    if cfg.ssh_listen.is_some() && !cfg.dtach_session {
+        anyhow::bail!("SSH_LISTEN needs DTACH_SESSION=true, so SSH and the web terminal share one irssi");
+    }
+    let irc_proxy = ircproxy::Proxy::new(store.clone(), Arc::clone(&soju), cfg.soju_addr.clone(), tls)?;
+
+    let maintenance = Maintenance::new(
+        store.get_setting("maintenance_enabled", "false").await == "true",
+        store.get_setting("maintenance_message", maintenance::DEFAULT_MESSAGE).await,
     );
+    if maintenance.refusal().is_some() {
+        warn!("maintenance mode is on — new sessions will be refused");
+    }
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
     let state = AppState {
         cfg: Arc::clone(&cfg),
+        runtime: Arc::new(RwLock::new(Arc::new(runtime))),
         validator,
         store,
         sessions,
         soju,
+        conns: proxy::Registry::new(),
+        irc_proxy,
+        share_secret: share_secret.into(),
+        keys,
+        catalog: Arc::new(catalog::Catalog::new(cfg.catalog_dir.clone())),
+        events: tokio::sync::broadcast::channel(64).0,
+        maintenance,
+        control_token: control::new_token().into(),
+        shutdown: shutdown_rx,
     };
 
     let app = Router::new()
         // User API
         .route("/terminal/ws", get(handle_terminal_ws))
         .route("/api/me", get(handle_me))
+        .route("/api/networks", get(handle_networks))
+        .route(
+            "/api/networks/:name/sasl",
+            get(handle_get_sasl).put(handle_put_sasl).delete(handle_delete_sasl),
+        )
+        .route("/api/networks/:name/certfp", get(handle_get_certfp).post(handle_generate_certfp))
         .route("/api/terminal", get(handle_provision))
         .route("/api/session/clear", post(handle_clear_session))
+        .route("/api/session/share", post(handle_create_share))
+        .route("/api/session/shares", get(handle_list_shares))
+        .route("/api/session/shares/:id", delete(handle_revoke_share))
+        .route("/share/ws", get(handle_share_ws))
+        .route("/api/events", get(handle_events))
+        .route("/api/announcements", get(handle_announcements))
+        .route("/api/me/devices", get(handle_my_devices))
+        .route("/api/me/devices/:id", delete(handle_my_device_disconnect))
+        .route("/api/me/export", get(handle_export))
+        .route("/api/me/preferences", get(handle_get_preferences).put(handle_put_preferences))
+        .route("/api/app-passwords", get(handle_list_app_passwords).post(handle_create_app_password))
+        .route("/api/app-passwords/:id", delete(handle_delete_app_password))
+        .route("/api/ssh-keys", get(handle_list_ssh_keys).post(handle_add_ssh_key))
+        .route("/api/ssh-keys/:id", delete(handle_delete_ssh_key))
+        .route("/api/irssi/settings", get(handle_irssi_settings).patch(handle_update_irssi_settings))
+        .route("/api/irssi/command", post(handle_irssi_command))
+        .route("/api/catalog", get(handle_catalog))
+        .route("/api/catalog/:kind/:name", put(handle_enable_catalog).delete(handle_disable_catalog))
+        .route("/open", get(handle_open))
+        .route("/api/me/delete", post(handle_request_deletion))
+        .route("/api/me", delete(handle_delete_account))
         // Admin API
         .route("/api/admin/users", get(handle_admin_users))
         .route("/api/admin/users/:username", delete(handle_admin_delete_user))
         .route("/api/admin/users/:username/kick", post(handle_admin_kick))
         .route("/api/admin/users/:username/clear", post(handle_admin_clear))
+        .route("/api/admin/users/:username/rotate-soju-password", post(handle_admin_rotate_soju_password))
+        .route("/api/admin/rotate-soju-passwords", post(handle_admin_rotate_soju_passwords))
+        .route("/api/admin/users/:username/devices", get(handle_admin_user_devices))
+        .route("/api/admin/users/:username/spectate/ws", get(handle_admin_spectate_ws))
+        .route("/api/admin/devices", get(handle_admin_devices))
+        .route("/api/admin/devices/:id", delete(handle_admin_device_disconnect))
+        .route("/api/admin/users/:username/suspend", post(handle_admin_suspend))
+        .route("/api/admin/users/:username/unsuspend", post(handle_admin_unsuspend))
         .route("/api/admin/settings", get(handle_admin_get_settings).post(handle_admin_post_settings))
+        .route("/api/admin/audit", get(handle_admin_audit))
+        .route("/api/admin/announce", post(handle_admin_announce))
+        .route("/api/admin/backups", get(handle_admin_backups).post(handle_admin_create_backup))
+        .route("/api/admin/backups/:name", get(handle_admin_download_backup))
+        .route("/api/admin/reencrypt", post(handle_admin_reencrypt))
+        .route("/api/admin/maintenance", get(handle_admin_get_maintenance).post(handle_admin_post_maintenance))
+        .route("/api/admin/roles", get(handle_admin_roles))
+        .route("/api/admin/users/:username/roles", post(handle_admin_grant_role))
+        .route("/api/admin/users/:username/roles/:role", delete(handle_admin_revoke_role))
         // Static files (frontend)
         .fallback_service(ServeDir::new(&cfg.public_dir))
         .layer(TraceLayer::new_for_http())
-        .with_state(state);
+        .with_state(state.clone());
+
+    // Scheduled backups: checked every minute against the newest archive,
+    // so a restart does not reset the schedule
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                tick.tick().await;
+                let rt = state.runtime();
+                let Some(interval) = rt.backup_interval else { continue };
+                if backup::newest_age(&state.backup_dir()).is_some_and(|age| age < interval) {
+                    continue;
+                }
+                match state.create_backup().await {
+                    Ok((path, _)) => info!("scheduled backup written to {}", path.display()),
+                    Err(e) => error!("scheduled backup failed: {:#}", e),
+                }
+                match backup::prune(&state.backup_dir(), rt.backup_keep) {
+                    Ok(0) => {}
+                    Ok(n) => info!("pruned {} old backups", n),
+                    Err(e) => warn!("pruning backups: {:#}", e),
+                }
+            }
+        });
+    }
+
+    // Scheduled soju password rotation, per user once their password is
+    // SOJU_PASSWORD_ROTATION old
+    if !cfg.dev_mode {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                tick.tick().await;
+                let Some(max_age) = state.runtime().soju_password_rotation else { continue };
+                match state.rotate_soju_passwords(Some(max_age)).await {
+                    Ok(out) if !out.rotated.is_empty() => {
+                        info!("rotated soju passwords of {}", out.rotated.join(", "))
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("scheduled soju password rotation failed: {:#}", e),
+                }
+            }
+        });
+    }
+
+    // IRC proxy for native clients
+    if let Some(listen) = cfg.irc_proxy_listen.clone() {
+        let proxy = Arc::clone(&state.irc_proxy);
+        tokio::spawn(async move {
+            if let Err(e) = proxy.serve(&listen).await {
+                error!("IRC proxy: {:#}", e);
+            }
+        });
+    }
+
+    // SSH gateway: sshd, and the socket its commands reach the server on
+    if let Some(listen) = cfg.ssh_listen.clone() {
+        // sshd runs its commands from the login's home directory
+        let cwd = std::env::current_dir()?;
+        let dir = cwd.join(cfg.data_dir.join("ssh"));
+        std::fs::create_dir_all(&dir)?;
+        let socket = dir.join("gateway.sock");
+        let gateway = Router::new()
+            .route("/api/ssh/keys/:fingerprint", get(handle_ssh_key_owner))
+            .route("/api/ssh/sessions", post(handle_ssh_session))
+            .with_state(state.clone());
+        let sshd_pid = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let (path, pid) = (socket.clone(), Arc::clone(&sshd_pid));
+        tokio::spawn(async move {
+            let allow = move |peer| ssh::run_by_sshd(peer, pid.load(std::sync::atomic::Ordering::Relaxed));
+            if let Err(e) = control::serve_local(path, gateway, allow).await {
+                error!("SSH gateway socket: {:#}", e);
+            }
+        });
+        let sshd = ssh::Sshd {
+            listen,
+            sshd: cfg.sshd.clone(),
+            host_key: cwd.join(&cfg.ssh_host_key),
+            dir,
+            socket,
+            pid: sshd_pid,
+        };
+        tokio::spawn(async move {
+            if let Err(e) = sshd.run().await {
+                error!("SSH gateway: {:#}", e);
+            }
+        });
+    }
+
+    // Same routes on the local control socket, for `irssi-v5 admin`
+    {
+        let (path, app, token) = (cfg.control_socket.clone(), app.clone(), Arc::clone(&state.control_token));
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(path, app, token).await {
+                error!("control socket: {:#}", e);
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        let state = state.clone();
+        let mut hup = signal::unix::signal(signal::unix::SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hup.recv().await.is_some() {
+                info!("SIGHUP — reloading config");
+                if let Err(e) = state.reload_config().await {
+                    error!("config reload failed, keeping the running config: {:#}", e);
+                }
+            }
+        });
+    }
 
     let addr = format!("0.0.0.0:{}", cfg.port);
     info!("irssi-v5 listening on {}", addr);
@@ -456,23 +3085,13 @@ async fn main() -> Result<()> {
     let listener = tokio::net::TcpListener::bind(&addr).await?;
 
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(shutdown_signal(state, shutdown_tx))
         .await?;
 
     Ok(())
 }
 
-// Handler — CF Access lands here after OAuth, just validate + redirect home
-async fn handle_token(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-) -> Result<Response, AppError> {
-    // Validates the JWT (ensures the CF cookie is good), then redirects to /
-    let _user = state.authenticate(&headers).await?;
-    Ok(axum::response::Redirect::to("/").into_response())
-}
-
-async fn shutdown_signal() {
+async fn shutdown_signal(state: AppState, shutdown: tokio::sync::watch::Sender<bool>) {
     let ctrl_c = async {
         signal::ctrl_c().await.expect("failed to install Ctrl+C handler");
     };
@@ -493,5 +3112,23 @@ async fn shutdown_signal() {
         _ = terminate => {},
     }
 
+    // Behave like a maintenance drain (in memory only, not persisted):
+    // refuse new sessions, warn connected users, then detach so dtach'd
+    // irssi processes survive the restart.
+    let grace = state.runtime().shutdown_grace;
+    info!("Shutting down — draining sessions in {:?}", grace);
+    state.maintenance.set(
+        maintenance::Status {
+            enabled: true,
+            message: "Server restarting — back in a moment".into(),
+            drain_at: Some(store::now_ms() + grace.as_millis() as i64),
+        },
+        None,
+    );
+    state.broadcast_maintenance();
+    tokio::time::sleep(grace).await;
+    state.drain_sessions(proxy::CLOSE_MAINTENANCE);
+    let _ = shutdown.send(true);
+
     info!("Shutting down...");
 }
\ No newline at end of file
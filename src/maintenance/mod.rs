@@ -0,0 +1,58 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_MESSAGE: &str = "Down for maintenance — back soon";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub enabled: bool,
+    pub message: String,
+    /// When a drain is scheduled: unix ms at which live sessions get detached
+    pub drain_at: Option<i64>,
+}
+
+/// Maintenance mode switch. While enabled, new sessions are refused; an
+/// optional drain task detaches existing ones after a countdown.
+pub struct Maintenance {
+    status: RwLock<Status>,
+    drain_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool, message: String) -> Arc<Self> {
+        Arc::new(Self {
+            status: RwLock::new(Status { enabled, message, drain_at: None }),
+            drain_task: Mutex::new(None),
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.read().unwrap().clone()
+    }
+
+    /// The refusal message while enabled, None otherwise.
+    pub fn refusal(&self) -> Option<String> {
+        let s = self.status.read().unwrap();
+        s.enabled.then(|| s.message.clone())
+    }
+
+    /// Replace the status. Any pending drain is cancelled; pass the new
+    /// drain task (if any) to have it tracked instead.
+    pub fn set(&self, status: Status, drain_task: Option<JoinHandle<()>>) {
+        *self.status.write().unwrap() = status;
+        let mut task = self.drain_task.lock().unwrap();
+        if let Some(old) = task.take() {
+            old.abort();
+        }
+        *task = drain_task;
+    }
+
+    /// Called by the drain task once it has run.
+    pub fn drain_done(&self) {
+        self.status.write().unwrap().drain_at = None;
+        self.drain_task.lock().unwrap().take();
+    }
+}
@@ -0,0 +1,321 @@
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc, Notify};
+
+use crate::store::now_ms;
+
+pub mod osc;
+
+/// Close code sent to the browser when its connection is dropped on purpose
+/// (device disconnect). The frontend does not auto-reconnect on it.
+pub const CLOSE_DISCONNECTED: u16 = 4000;
+/// Close code for sessions detached by maintenance mode. The frontend
+/// retries later instead of reconnecting straight away.
+pub const CLOSE_MAINTENANCE: u16 = 4001;
+
+/// Output frames buffered per connection for mirrors; a mirror that falls
+/// further behind than this skips ahead.
+const MIRROR_BUFFER: usize = 256;
+
+/// Most terminal output kept per connection to replay to a mirror when it
+/// attaches, so a watcher does not start from a blank screen.
+const MIRROR_BACKLOG: usize = 64 * 1024;
+
+/// Erase display; output before the last one is not needed to redraw.
+const CLEAR_SCREEN: &[u8] = b"\x1b[2J";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ConnKind {
+    /// The user's own browser terminal
+    Terminal,
+    /// An admin watching `target`'s terminal read-only
+    Spectate { target: String },
+    /// A guest attached to `owner`'s terminal through a share link
+    Share { owner: String, share_id: String },
+}
+
+/// A live browser ↔ ttyd WebSocket proxy.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnInfo {
+    pub id: u64,
+    pub username: String,
+    pub kind: ConnKind,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub connected_at: i64,
+}
+
+/// Disconnect request: wakes the proxy task and carries the close code.
+struct Signal {
+    notify: Notify,
+    code: AtomicU16,
+}
+
+struct Entry {
+    info: ConnInfo,
+    disconnect: Arc<Signal>,
+    output: Arc<Output>,
+    side: mpsc::UnboundedSender<String>,
+    input: mpsc::UnboundedSender<String>,
+}
+
+/// ttyd output frames copied for mirrors, with the recent output a new
+/// mirror starts from.
+struct Output {
+    frames: broadcast::Sender<Vec<u8>>,
+    backlog: Mutex<Vec<u8>>,
+}
+
+/// A watcher's view of a terminal: the output to draw first, as one ttyd
+/// output frame, then the live frames.
+pub struct Mirror {
+    pub backlog: Vec<u8>,
+    pub frames: broadcast::Receiver<Vec<u8>>,
+}
+
+/// Registry of live WebSocket proxies, so users and admins can list the
+/// devices attached to a terminal and drop a specific one.
+pub struct Registry {
+    conns: DashMap<u64, Entry>,
+    next_id: AtomicU64,
+}
+
+/// Registration handle owned by the proxy task. Dropping it unregisters
+/// the connection.
+pub struct Handle {
+    pub id: u64,
+    disconnect: Arc<Signal>,
+    registry: Arc<Registry>,
+    /// ttyd output frames are copied here for mirrors (spectators)
+    output: Arc<Output>,
+    side: Option<mpsc::UnboundedReceiver<String>>,
+    input: Option<mpsc::UnboundedReceiver<String>>,
+}
+
+impl Handle {
+    /// Resolves with the close code when someone asks for this connection
+    /// to be dropped.
+    pub async fn disconnected(&self) -> u16 {
+        self.disconnect.notify.notified().await;
+        self.disconnect.code.load(Ordering::Relaxed)
+    }
+
+    /// Structured JSON messages for the browser, sent as WS text frames
+    /// alongside the ttyd stream. Can only be taken once.
+    pub fn take_side(&mut self) -> mpsc::UnboundedReceiver<String> {
+        self.side.take().expect("side channel already taken")
+    }
+
+    /// ttyd input frames injected by guests with write access, to be sent
+    /// upstream as if the owner typed them. Can only be taken once.
+    pub fn take_input(&mut self) -> mpsc::UnboundedReceiver<String> {
+        self.input.take().expect("input channel already taken")
+    }
+
+    /// Copy a ttyd output frame to any mirrors.
+    pub fn mirror(&self, frame: &[u8]) {
+        // Held while sending so a mirror subscribing concurrently gets each
+        // frame exactly once, either in its backlog or live
+        let mut backlog = self.output.backlog.lock().unwrap();
+        if let Some(data) = frame.strip_prefix(b"0") {
+            append_backlog(&mut backlog, data);
+        }
+        if self.output.frames.receiver_count() > 0 {
+            let _ = self.output.frames.send(frame.to_vec());
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.registry.conns.remove(&self.id);
+    }
+}
+
+impl Registry {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            conns: DashMap::new(),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    pub fn register(
+        self: &Arc<Self>,
+        username: &str,
+        kind: ConnKind,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Handle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let disconnect = Arc::new(Signal {
+            notify: Notify::new(),
+            code: AtomicU16::new(CLOSE_DISCONNECTED),
+        });
+        let output = Arc::new(Output {
+            frames: broadcast::channel(MIRROR_BUFFER).0,
+            backlog: Mutex::new(Vec::new()),
+        });
+        let (side_tx, side_rx) = mpsc::unbounded_channel();
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+        let info = ConnInfo {
+            id,
+            username: username.to_string(),
+            kind,
+            ip,
+            user_agent,
+            connected_at: now_ms(),
+        };
+        self.conns.insert(id, Entry {
+            info,
+            disconnect: Arc::clone(&disconnect),
+            output: Arc::clone(&output),
+            side: side_tx,
+            input: input_tx,
+        });
+        Handle {
+            id,
+            disconnect,
+            registry: Arc::clone(self),
+            output,
+            side: Some(side_rx),
+            input: Some(input_rx),
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<ConnInfo> {
+        self.conns.get(&id).map(|e| e.info.clone())
+    }
+
+    /// Live connections, oldest first; all users when `username` is None.
+    pub fn list(&self, username: Option<&str>) -> Vec<ConnInfo> {
+        let mut conns: Vec<ConnInfo> = self
+            .conns
+            .iter()
+            .filter(|e| username.is_none_or(|u| e.info.username == u))
+            .map(|e| e.info.clone())
+            .collect();
+        conns.sort_by_key(|c| c.id);
+        conns
+    }
+
+    /// Ask one connection to close. Returns its info if it was live.
+    pub fn disconnect(&self, id: u64) -> Option<ConnInfo> {
+        self.disconnect_with(id, CLOSE_DISCONNECTED)
+    }
+
+    pub fn disconnect_with(&self, id: u64, code: u16) -> Option<ConnInfo> {
+        let entry = self.conns.get(&id)?;
+        entry.disconnect.code.store(code, Ordering::Relaxed);
+        // notify_one stores a permit, so this works even if the proxy task
+        // is not currently parked in `disconnected()`.
+        entry.disconnect.notify.notify_one();
+        Some(entry.info.clone())
+    }
+
+    /// Drop every live connection with the given close code.
+    pub fn disconnect_all(&self, code: u16) -> usize {
+        let ids: Vec<u64> = self.conns.iter().map(|e| *e.key()).collect();
+        ids.iter().filter(|id| self.disconnect_with(**id, code).is_some()).count()
+    }
+
+    /// Terminal connections of one user, oldest first.
+    pub fn terminals(&self, username: &str) -> Vec<ConnInfo> {
+        self.list(Some(username))
+            .into_iter()
+            .filter(|c| c.kind == ConnKind::Terminal)
+            .collect()
+    }
+
+    /// Mirror the user's most recently opened terminal.
+    pub fn subscribe(&self, username: &str) -> Option<Mirror> {
+        let latest = self.terminals(username).pop()?;
+        let output = Arc::clone(&self.conns.get(&latest.id)?.output);
+        let backlog = output.backlog.lock().unwrap();
+        Some(Mirror {
+            backlog: [b"0".as_slice(), &backlog].concat(),
+            frames: output.frames.subscribe(),
+        })
+    }
+
+    /// Input injector for the user's most recently opened terminal.
+    pub fn input(&self, username: &str) -> Option<mpsc::UnboundedSender<String>> {
+        let latest = self.terminals(username).pop()?;
+        self.conns.get(&latest.id).map(|e| e.input.clone())
+    }
+
+    /// Guests attached through one share link.
+    pub fn share_guests(&self, share_id: &str) -> Vec<ConnInfo> {
+        self.list(None)
+            .into_iter()
+            .filter(|c| matches!(&c.kind, ConnKind::Share { share_id: s, .. } if s == share_id))
+            .collect()
+    }
+
+    /// Connections currently spectating `target`.
+    pub fn spectators(&self, target: &str) -> Vec<ConnInfo> {
+        self.list(None)
+            .into_iter()
+            .filter(|c| matches!(&c.kind, ConnKind::Spectate { target: t } if t == target))
+            .collect()
+    }
+
+    /// Push a structured message to every terminal the user has open.
+    pub fn notify(&self, username: &str, message: &serde_json::Value) {
+        let text = message.to_string();
+        for c in self.terminals(username) {
+            if let Some(e) = self.conns.get(&c.id) {
+                let _ = e.side.send(text.clone());
+            }
+        }
+    }
+
+    pub fn disconnect_user(&self, username: &str) -> usize {
+        let ids: Vec<u64> = self.list(Some(username)).iter().map(|c| c.id).collect();
+        ids.iter().filter(|id| self.disconnect(**id).is_some()).count()
+    }
+}
+
+/// Add output to a backlog, dropping what a screen clear makes redundant
+/// and the oldest bytes past `MIRROR_BACKLOG`.
+fn append_backlog(backlog: &mut Vec<u8>, data: &[u8]) {
+    match data.windows(CLEAR_SCREEN.len()).rposition(|w| w == CLEAR_SCREEN) {
+        Some(i) => {
+            backlog.clear();
+            backlog.extend_from_slice(&data[i..]);
+        }
+        None => backlog.extend_from_slice(data),
+    }
+    if backlog.len() > MIRROR_BACKLOG {
+        backlog.drain(..backlog.len() - MIRROR_BACKLOG);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_backlog() {
+        let registry = Registry::new();
+        let conn = registry.register("alice", ConnKind::Terminal, None, None);
+        conn.mirror(b"0old screen");
+        conn.mirror(b"0\x1b[H\x1b[2Jnew");
+        conn.mirror(b"1{\"title\":\"x\"}");
+        conn.mirror(b"0 screen");
+
+        let mut mirror = registry.subscribe("alice").unwrap();
+        assert_eq!(mirror.backlog, b"0\x1b[2Jnew screen");
+        conn.mirror(b"0!");
+        assert_eq!(mirror.frames.try_recv().unwrap(), b"0!");
+        assert!(registry.subscribe("bob").is_none());
+
+        let mut backlog = Vec::new();
+        append_backlog(&mut backlog, &vec![b'x'; MIRROR_BACKLOG + 10]);
+        assert_eq!(backlog.len(), MIRROR_BACKLOG);
+    }
+}
@@ -0,0 +1,175 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Serialize;
+
+/// Longest OSC string kept; longer ones (e.g. a huge OSC 52 copy) are
+/// skipped up to their terminator.
+const MAX_OSC: usize = 128 * 1024;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Something the terminal program asked of the terminal that the browser
+/// should act on beyond drawing it. Sent as a side-channel message.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    /// Desktop notification: OSC 9 (`ESC ] 9 ; body`) or OSC 777
+    /// (`ESC ] 777 ; notify ; title ; body`)
+    Notify { title: Option<String>, body: String },
+    Bell,
+    /// Window title: OSC 0 or 2
+    Title { title: String },
+    /// OSC 52 clipboard write, decoded
+    Clipboard { text: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum State {
+    #[default]
+    Ground,
+    Esc,
+    Osc,
+    /// ESC inside an OSC string, possibly the start of ST (`ESC \`)
+    OscEsc,
+}
+
+/// Picks OSC sequences and bells out of terminal output. Frames are only
+/// read, never changed, and sequences may be split across frames.
+#[derive(Default)]
+pub struct Scanner {
+    state: State,
+    osc: Vec<u8>,
+    overflow: bool,
+}
+
+impl Scanner {
+    /// Scan one chunk of output. Several bells in a chunk count as one.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut bell = false;
+        for &b in data {
+            self.state = match (self.state, b) {
+                (State::Ground, ESC) => State::Esc,
+                (State::Ground, BEL) => {
+                    bell = true;
+                    State::Ground
+                }
+                (State::Ground, _) => State::Ground,
+                (State::Esc, b']') => {
+                    self.osc.clear();
+                    self.overflow = false;
+                    State::Osc
+                }
+                (State::Esc, ESC) => State::Esc,
+                (State::Esc, _) => State::Ground,
+                (State::Osc, BEL) => {
+                    events.extend(self.finish());
+                    State::Ground
+                }
+                (State::Osc, ESC) => State::OscEsc,
+                (State::Osc, _) => {
+                    self.push(b);
+                    State::Osc
+                }
+                (State::OscEsc, b'\\') => {
+                    events.extend(self.finish());
+                    State::Ground
+                }
+                // Any other escape cancels the OSC and starts a new sequence
+                (State::OscEsc, b']') => {
+                    self.osc.clear();
+                    self.overflow = false;
+                    State::Osc
+                }
+                (State::OscEsc, ESC) => State::Esc,
+                (State::OscEsc, _) => State::Ground,
+            };
+        }
+        if bell {
+            events.push(Event::Bell);
+        }
+        events
+    }
+
+    fn push(&mut self, b: u8) {
+        if self.osc.len() < MAX_OSC {
+            self.osc.push(b);
+        } else {
+            self.overflow = true;
+        }
+    }
+
+    fn finish(&mut self) -> Option<Event> {
+        let osc = std::mem::take(&mut self.osc);
+        if self.overflow {
+            return None;
+        }
+        parse(&String::from_utf8_lossy(&osc))
+    }
+}
+
+/// One complete OSC string, without `ESC ]` and terminator.
+fn parse(osc: &str) -> Option<Event> {
+    let (code, rest) = osc.split_once(';')?;
+    match code {
+        "0" | "2" => Some(Event::Title { title: rest.to_string() }),
+        // ConEmu uses `9 ; <n> ; …` for progress and the like
+        "9" if !rest.split_once(';').is_some_and(|(n, _)| n.chars().all(|c| c.is_ascii_digit())) => {
+            Some(Event::Notify { title: None, body: rest.to_string() })
+        }
+        "777" => {
+            let mut parts = rest.splitn(3, ';');
+            if parts.next() != Some("notify") {
+                return None;
+            }
+            let title = parts.next().filter(|t| !t.is_empty()).map(String::from);
+            let body = parts.next().unwrap_or_default().to_string();
+            Some(Event::Notify { title, body })
+        }
+        "52" => {
+            // `52 ; <selection> ; <base64>`; `?` asks to read the clipboard,
+            // which a browser terminal does not answer
+            let (_, data) = rest.split_once(';')?;
+            let text = BASE64.decode(data).ok()?;
+            Some(Event::Clipboard { text: String::from_utf8_lossy(&text).into_owned() })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanner() {
+        let mut s = Scanner::default();
+        assert_eq!(s.feed(b"plain \x1b[1mtext\x1b[0m"), []);
+        assert_eq!(s.feed(b"\x07a\x07b\x07"), [Event::Bell]);
+        assert_eq!(s.feed(b"\x1b]2;#irssi\x07"), [Event::Title { title: "#irssi".into() }]);
+        assert_eq!(
+            s.feed(b"\x1b]777;notify;alice;hi there\x1b\\"),
+            [Event::Notify { title: Some("alice".into()), body: "hi there".into() }]
+        );
+        assert_eq!(s.feed(b"\x1b]9;ping\x07"), [Event::Notify { title: None, body: "ping".into() }]);
+        assert_eq!(s.feed(b"\x1b]9;4;1;50\x07"), []);
+
+        // Split across frames, with the BEL that ends it not counted as a bell
+        assert_eq!(s.feed(b"x\x1b]52;c;aGVs"), []);
+        assert_eq!(s.feed(b"bG8="), []);
+        assert_eq!(s.feed(b"\x07"), [Event::Clipboard { text: "hello".into() }]);
+        assert_eq!(s.feed(b"\x1b"), []);
+        assert_eq!(s.feed("]0;é\x1b".as_bytes()), []);
+        assert_eq!(s.feed(b"\\\x07"), [Event::Title { title: "é".into() }, Event::Bell]);
+
+        assert_eq!(s.feed(b"\x1b]52;c;?\x07\x1b]1337;x\x07\x1b]8;;http://x\x1b\\"), []);
+        let mut big = b"\x1b]52;c;".to_vec();
+        big.extend(std::iter::repeat_n(b'A', MAX_OSC + 4));
+        big.extend(b"\x07\x1b]2;t\x07");
+        assert_eq!(s.feed(&big), [Event::Title { title: "t".into() }]);
+
+        let json = serde_json::to_value(Event::Notify { title: None, body: "b".into() }).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "notify", "title": null, "body": "b"}));
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+
+/// A role grants a fixed set of permissions. Roles are stored per user in the
+/// `user_roles` table and can additionally be derived from IdP group claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Full control, including destructive actions and role management.
+    Admin,
+    /// Day-to-day support: can see users and kick sessions, but not delete.
+    Operator,
+    /// Read-only access to users, settings and the audit log.
+    Auditor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ViewUsers,
+    KickSessions,
+    ClearUsers,
+    DeleteUsers,
+    SuspendUsers,
+    SpectateSessions,
+    Announce,
+    ViewSettings,
+    ManageSettings,
+    ViewAudit,
+    ManageRoles,
+}
+
+pub const ALL_ROLES: [Role; 3] = [Role::Admin, Role::Operator, Role::Auditor];
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+            Role::Auditor => "auditor",
+        }
+    }
+
+    pub fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Role::Admin => &[
+                ViewUsers, KickSessions, ClearUsers, DeleteUsers, SuspendUsers, SpectateSessions,
+                Announce, ViewSettings, ManageSettings, ViewAudit, ManageRoles,
+            ],
+            Role::Operator => &[ViewUsers, KickSessions, Announce, ViewSettings],
+            Role::Auditor => &[ViewUsers, ViewSettings, ViewAudit],
+        }
+    }
+
+    pub fn grants(self, perm: Permission) -> bool {
+        self.permissions().contains(&perm)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "admin" => Ok(Role::Admin),
+            "operator" => Ok(Role::Operator),
+            "auditor" => Ok(Role::Auditor),
+            other => Err(anyhow!("unknown role '{}' (expected admin, operator or auditor)", other)),
+        }
+    }
+}
+
+/// Parse a group→role mapping such as "irc-admins=admin,helpdesk=operator".
+pub fn parse_group_map(s: &str) -> anyhow::Result<Vec<(String, Role)>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (group, role) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected group=role, got '{}'", pair))?;
+            Ok((group.trim().to_string(), role.parse()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_group_map() {
+        let map = parse_group_map("irc-admins=admin, helpdesk = Operator,").unwrap();
+        assert_eq!(
+            map,
+            vec![
+                ("irc-admins".to_string(), Role::Admin),
+                ("helpdesk".to_string(), Role::Operator),
+            ]
+        );
+        assert!(parse_group_map("helpdesk").is_err());
+        assert!(parse_group_map("x=root").is_err());
+        assert!(Role::Operator.grants(Permission::KickSessions));
+        assert!(!Role::Operator.grants(Permission::DeleteUsers));
+        assert!(!Role::Auditor.grants(Permission::KickSessions));
+    }
+}
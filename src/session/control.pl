@@ -0,0 +1,169 @@
+# Installed by irssi-v5 into every user's irssi home; rewritten on each
+# session start, so local edits do not survive.
+#
+# Listens on <irssi home>/control.sock for commands from the irssi-v5
+# server, one per line:
+#
+#     <chatnet or server tag, may be empty> TAB </command args>
+#
+# and answers each with "ok" or "error <reason>". A line
+#
+#     :soju-chatnet TAB <chatnet>
+#
+# marks another chatnet as logging in to soju (see below), and
+#
+#     :soju-password TAB <password>
+#
+# replaces the soju password and reconnects the soju servers with it.
+#
+# The soju password is not kept in the irssi config. The server passes it
+# in IRSSI_V5_SOJU_PASSWORD, with the chatnets that use it in
+# IRSSI_V5_SOJU_CHATNETS; this script sets it on those chatnets in memory,
+# again after every config reload, and strips it from the file whenever
+# irssi saves the config.
+
+use strict;
+use warnings;
+
+use Irssi;
+use IO::Socket::UNIX;
+use Socket qw(SOCK_STREAM);
+
+our $VERSION = '1.0';
+our %IRSSI = (
+    authors     => 'irssi-v5',
+    name        => 'irssi_v5_control',
+    description => 'Accepts commands from the irssi-v5 server on a Unix socket',
+    license     => 'MIT',
+);
+
+my $MAX_LINE = 4096;
+
+my $soju_password = delete $ENV{IRSSI_V5_SOJU_PASSWORD};
+my %soju_chatnets = map { $_ => 1 } grep { length } split /,/, (delete $ENV{IRSSI_V5_SOJU_CHATNETS} // '');
+
+sub set_soju_password {
+    return unless defined $soju_password;
+    for my $name (@_) {
+        next unless Irssi::chatnet_find($name);
+        Irssi::command("/network modify -sasl_password $soju_password $name");
+    }
+}
+
+sub scrub_config {
+    my ($file) = @_;
+    return unless defined $soju_password;
+    open(my $in, '<', $file) or return;
+    my $text = do { local $/; <$in> };
+    close($in);
+    return unless $text =~ s/[ \t]*sasl_password = "\Q$soju_password\E";\n?//g;
+    my $tmp = "$file.tmp";
+    my $umask = umask(0077);
+    my $ok = open(my $out, '>', $tmp);
+    umask($umask);
+    return unless $ok;
+    print $out $text;
+    close($out) and rename($tmp, $file);
+}
+
+set_soju_password(keys %soju_chatnets);
+# irssi has just re-read the chatnets from the file, which has no password
+Irssi::signal_add_last('setup reread', sub { set_soju_password(keys %soju_chatnets) });
+Irssi::signal_add_last('setup saved', sub { scrub_config($_[0]) });
+
+my $path = Irssi::get_irssi_dir() . '/control.sock';
+unlink $path;
+my $umask = umask(0077);
+my $listener = IO::Socket::UNIX->new(Type => SOCK_STREAM, Local => $path, Listen => 5)
+    or die "irssi-v5 control socket $path: $!";
+umask($umask);
+$listener->blocking(0);
+
+my %clients;
+my $accept_tag = Irssi::input_add(fileno($listener), Irssi::INPUT_READ, \&accept_client, undef);
+
+sub accept_client {
+    my $sock = $listener->accept or return;
+    $sock->blocking(0);
+    my $fd = fileno($sock);
+    $clients{$fd} = { sock => $sock, buf => '' };
+    $clients{$fd}{tag} = Irssi::input_add($fd, Irssi::INPUT_READ, \&read_client, $fd);
+}
+
+sub read_client {
+    my ($fd) = @_;
+    my $c = $clients{$fd} or return;
+    my $n = sysread($c->{sock}, my $data, $MAX_LINE);
+    if (!$n) {
+        close_client($fd);
+        return;
+    }
+    $c->{buf} .= $data;
+    while ($c->{buf} =~ s/^([^\n]*)\n//) {
+        syswrite($c->{sock}, run_line($1) . "\n");
+    }
+    close_client($fd) if length($c->{buf}) > $MAX_LINE;
+}
+
+sub close_client {
+    my ($fd) = @_;
+    my $c = delete $clients{$fd} or return;
+    Irssi::input_remove($c->{tag});
+    close($c->{sock});
+}
+
+# /reconnect would reuse the login the server connected with; connect
+# afresh from the chatnet instead, which has the new password.
+sub reconnect_soju {
+    my %chatnets;
+    for my $server (Irssi::servers()) {
+        my $chatnet = $server->{chatnet} // '';
+        next unless $soju_chatnets{$chatnet};
+        $server->command('/disconnect');
+        $chatnets{$chatnet} = 1;
+    }
+    for my $rec (Irssi::reconnects()) {
+        my $chatnet = $rec->{conn}{chatnet} // '';
+        next unless $soju_chatnets{$chatnet};
+        Irssi::command("/disconnect RECON-$rec->{tag}");
+        $chatnets{$chatnet} = 1;
+    }
+    Irssi::command("/connect $_") for keys %chatnets;
+}
+
+sub run_line {
+    my ($line) = @_;
+    if ($line =~ /^:soju-chatnet\t(\S+)$/) {
+        $soju_chatnets{$1} = 1;
+        set_soju_password($1);
+        return 'ok';
+    }
+    if ($line =~ /^:soju-password\t(\S+)$/) {
+        $soju_password = $1;
+        set_soju_password(keys %soju_chatnets);
+        reconnect_soju();
+        return 'ok';
+    }
+    my ($network, $cmd) = split /\t/, $line, 2;
+    return 'error expected <network> TAB <command>' unless defined $cmd && $cmd =~ m{^/};
+
+    my $server;
+    if (length $network) {
+        ($server) = grep { lc($_->{chatnet} // '') eq lc($network) } Irssi::servers();
+        $server //= Irssi::server_find_tag($network);
+        # Only a server that finished registering can take commands
+        return "error not connected to $network" unless $server && $server->{connected};
+    }
+    my $ok = eval {
+        $server ? $server->command($cmd) : Irssi::command($cmd);
+        1;
+    };
+    return $ok ? 'ok' : 'error ' . ($@ =~ s/\s+$//r);
+}
+
+sub UNLOAD {
+    close_client($_) for keys %clients;
+    Irssi::input_remove($accept_tag);
+    close($listener);
+    unlink $path;
+}
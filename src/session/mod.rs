@@ -3,14 +3,31 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use dashmap::DashMap;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixStream};
 use tokio::process::{Child, Command};
 use tokio::sync::Mutex;
 use tokio::time::{sleep, timeout};
 use tracing::{info, warn};
 
+use crate::client::Client;
+
+/// irssi script that gives the server a command channel into a running
+/// irssi (see `send_command`).
+const CONTROL_SCRIPT: &str = include_str!("control.pl");
+const CONTROL_SCRIPT_NAME: &str = "irssi-v5-control.pl";
+const CONTROL_SOCKET: &str = "control.sock";
+
+/// What irssi needs to log in to soju. Passed in its environment, for the
+/// control script to pick up, rather than stored in the config.
+pub struct SojuLogin {
+    pub password: String,
+    /// Chatnets that authenticate to soju with `password`
+    pub chatnets: Vec<String>,
+}
+
 pub struct Session {
     pub port: u16,
     // Keep child alive — dropping it would kill the ttyd process.
@@ -59,11 +76,14 @@ impl Manager {
         })
     }
 
-    /// Return an existing session or spawn a new ttyd for this user.
+    /// Return an existing session or spawn a new ttyd for this user running
+    /// `client`. `client` and `soju` only matter when a new client starts.
     pub async fn get_or_create(
         self: &Arc<Self>,
         username: &str,
         user_dir: &Path,
+        client: Client,
+        soju: Option<&SojuLogin>,
     ) -> Result<u16> {
         // Return existing port if session is still alive
         if let Some(entry) = self.sessions.get(username) {
@@ -71,36 +91,30 @@ impl Manager {
             return Ok(sess.port);
         }
 
+        if client == Client::Irssi {
+            install_control_script(user_dir)?;
+        }
         let port = self.port_pool.lock().await.alloc()?;
 
-        let abs_user_dir = std::fs::canonicalize(user_dir)
-            .unwrap_or_else(|_| user_dir.to_path_buf());
-        let home_str = abs_user_dir.to_str().unwrap_or("/tmp").to_owned();
-        let config_path = format!("{}/config", home_str);
+        let home_str = home_dir(user_dir);
+        let command = client.command(&home_str);
+        let env = soju_env(soju);
 
         let child = if self.dtach_session {
-            // dtach socket path is per-user so multiple users don't collide.
-            // /tmp/irc-<username>.sock is fine — it lives inside the container.
-            let sock = format!("/tmp/irc-{}.sock", username);
+            let sock = dtach_socket(username);
 
             // Remove any stale socket from a previous failed irssi run.
             // If irssi exited uncleanly the socket file remains, and dtach -A
             // would attach to a dead socket instead of starting a fresh irssi.
             // We only remove it here — in get_or_create — so a live reattach
             // (browser reconnect while irssi is healthy) still works via kill().
-            match std::fs::metadata(&sock) {
-                Ok(_) => {
-                    // Socket exists but we have no live session tracked — stale.
-                    if let Err(e) = std::fs::remove_file(&sock) {
-                        warn!("failed to remove stale dtach socket {}: {}", sock, e);
-                    } else {
-                        info!("removed stale dtach socket {} for {}", sock, username);
-                    }
-                }
-                Err(_) => {} // doesn't exist, nothing to do
-            }
+            //
+            // A socket that still accepts connections belongs to an irssi we
+            // detached on purpose (maintenance drain) or one started for an
+            // SSH login — reattach to it.
+            remove_stale_socket(&sock, username);
 
-            info!("spawning ttyd+dtach for {} on port {} sock {}", username, port, sock);
+            info!("spawning ttyd+dtach ({}) for {} on port {} sock {}", client.as_str(), username, port, sock);
 
             // dtach -A <socket> <cmd>
             //   -A  attach to existing socket if it exists,
@@ -112,21 +126,23 @@ impl Manager {
                     "--interface", "127.0.0.1",
                     "--writable",
                     "dtach", "-A", &sock,
-                    "irssi", "--config", &config_path,
                 ])
+                .args(&command)
+                .envs(env.iter().cloned())
                 .kill_on_drop(true)
                 .spawn()
                 .with_context(|| format!("failed to spawn ttyd+dtach for {}", username))?
         } else {
-            info!("spawning ttyd for {} on port {} --home {}", username, port, home_str);
+            info!("spawning ttyd ({}) for {} on port {} --home {}", client.as_str(), username, port, home_str);
 
             Command::new("ttyd")
                 .args([
                     "--port", &port.to_string(),
                     "--interface", "127.0.0.1",
                     "--writable",
-                    "irssi", "--config", &config_path,
                 ])
+                .args(&command)
+                .envs(env.iter().cloned())
                 .kill_on_drop(true)
                 .spawn()
                 .with_context(|| format!("failed to spawn ttyd for {}", username))?
@@ -181,7 +197,7 @@ impl Manager {
         if self.dtach_session {
             // Removing the socket file causes dtach to exit, which kills irssi.
             // Best-effort — if the file doesn't exist that's fine.
-            let sock = format!("/tmp/irc-{}.sock", username);
+            let sock = dtach_socket(username);
             match std::fs::remove_file(&sock) {
                 Ok(_) => info!("removed dtach socket {} for {}", sock, username),
                 Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
@@ -190,6 +206,147 @@ impl Manager {
         }
     }
 
+    /// Start the user's client inside dtach with no terminal attached and
+    /// return its socket, for the SSH gateway to attach to. A client already
+    /// running there (started by ttyd or an earlier SSH login) is reused, so
+    /// the web terminal and SSH share one irssi. Needs dtach mode.
+    pub async fn start_detached(
+        &self,
+        username: &str,
+        user_dir: &Path,
+        client: Client,
+        soju: Option<&SojuLogin>,
+    ) -> Result<String> {
+        if !self.dtach_session {
+            bail!("SSH sessions need DTACH_SESSION=true");
+        }
+        let sock = dtach_socket(username);
+        if std::os::unix::net::UnixStream::connect(&sock).is_ok() {
+            return Ok(sock);
+        }
+        remove_stale_socket(&sock, username);
+        if client == Client::Irssi {
+            install_control_script(user_dir)?;
+        }
+
+        info!("spawning detached dtach ({}) for {} sock {}", client.as_str(), username, sock);
+        // dtach -n exits once the socket is up, leaving the client running
+        let status = Command::new("dtach")
+            .args(["-n", &sock])
+            .args(client.command(&home_dir(user_dir)))
+            .envs(soju_env(soju))
+            .status()
+            .await
+            .with_context(|| format!("failed to spawn dtach for {}", username))?;
+        if !status.success() {
+            bail!("dtach for {} exited with {}", username, status);
+        }
+        Ok(sock)
+    }
+
+    /// Stop the ttyd process but leave the dtach socket alone, so irssi keeps
+    /// running and the next get_or_create reattaches to it. Without dtach
+    /// this is the same as kill.
+    pub fn detach(&self, username: &str) {
+        if self.sessions.remove(username).is_some() {
+            info!("detached ttyd session for {}", username);
+        }
+    }
+
+    /// Ask the irssi running with `config_path` to re-read it (irssi reloads
+    /// on SIGHUP). Returns whether such a process was found. Settings it
+    /// changed in memory and never saved are lost, as with `/reload`.
+    pub fn reload_config(&self, config_path: &Path) -> bool {
+        let config_path = std::fs::canonicalize(config_path).unwrap_or_else(|_| config_path.to_path_buf());
+        let Ok(procs) = std::fs::read_dir("/proc") else { return false };
+        let mut found = false;
+        for entry in procs.flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<i32>().ok()) else { continue };
+            let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) else { continue };
+            let args: Vec<&[u8]> = cmdline.split(|&b| b == 0).collect();
+            // ttyd and dtach carry the same arguments — only irssi itself counts
+            let is_irssi = args.first().is_some_and(|a| a.rsplit(|&b| b == b'/').next() == Some(b"irssi"));
+            let ours = args.windows(2).any(|w| w[0] == b"--config" && Path::new(std::str::from_utf8(w[1]).unwrap_or("")) == config_path);
+            if is_irssi && ours {
+                // SAFETY: kill(2) has no memory-safety preconditions
+                if unsafe { libc::kill(pid, libc::SIGHUP) } == 0 {
+                    found = true;
+                } else {
+                    warn!("SIGHUP to irssi {} failed: {}", pid, std::io::Error::last_os_error());
+                }
+            }
+        }
+        found
+    }
+
+    /// The client running with `user_dir` as its home, if any, whether under
+    /// ttyd or detached in dtach. Found by its command line, which
+    /// `Client::command` makes unique per home.
+    pub fn running_client(&self, user_dir: &Path) -> Option<Client> {
+        let home = home_dir(user_dir);
+        let procs = std::fs::read_dir("/proc").ok()?;
+        for entry in procs.flatten() {
+            if !entry.file_name().to_str().is_some_and(|s| s.bytes().all(|b| b.is_ascii_digit())) {
+                continue;
+            }
+            let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) else { continue };
+            let cmdline = String::from_utf8_lossy(&cmdline);
+            let args: Vec<&str> = cmdline.trim_end_matches('\0').split('\0').collect();
+            // ttyd and dtach carry the same arguments after their own
+            let Some((exe, rest)) = args.split_first() else { continue };
+            let name = exe.rsplit('/').next().unwrap_or(exe);
+            let found = crate::client::ALL_CLIENTS
+                .into_iter()
+                .find(|c| c.as_str() == name && c.command(&home)[1..] == *rest);
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// Run an irssi command (e.g. `/join #rust`) in the irssi whose home is
+    /// `user_dir`, on the server for `network` if given. Fails if irssi is
+    /// not running, predates the control script, or rejects the command.
+    pub async fn send_command(&self, user_dir: &Path, network: Option<&str>, command: &str) -> Result<()> {
+        if command.contains(['\n', '\r', '\t']) || network.is_some_and(|n| n.contains(['\n', '\t'])) {
+            return Err(anyhow!("command and network must be a single line"));
+        }
+        self.control(user_dir, &format!("{}\t{}", network.unwrap_or(""), command)).await
+    }
+
+    /// Tell a running irssi that `chatnet` logs in to soju, so it gets the
+    /// soju password too (e.g. after adding a network and `/reload`).
+    pub async fn add_soju_chatnet(&self, user_dir: &Path, chatnet: &str) -> Result<()> {
+        if chatnet.is_empty() || chatnet.contains(char::is_whitespace) {
+            return Err(anyhow!("invalid chatnet name"));
+        }
+        self.control(user_dir, &format!(":soju-chatnet\t{}", chatnet)).await
+    }
+
+    /// Hand a running irssi a new soju password; it reconnects its soju
+    /// servers with it. Returns false when no irssi is listening (the next
+    /// one to start gets the password in its environment).
+    pub async fn set_soju_password(&self, user_dir: &Path, password: &str) -> Result<bool> {
+        if password.is_empty() || password.contains(char::is_whitespace) {
+            return Err(anyhow!("invalid password"));
+        }
+        let Ok(stream) = UnixStream::connect(user_dir.join(CONTROL_SOCKET)).await else {
+            return Ok(false);
+        };
+        exchange(stream, &format!(":soju-password\t{}", password)).await?;
+        Ok(true)
+    }
+
+    /// One request/reply exchange on the control script's socket.
+    async fn control(&self, user_dir: &Path, line: &str) -> Result<()> {
+        let path = user_dir.join(CONTROL_SOCKET);
+        let stream = UnixStream::connect(&path)
+            .await
+            .with_context(|| format!("irssi is not listening on {}", path.display()))?;
+        exchange(stream, line).await
+    }
+
     pub fn is_active(&self, username: &str) -> bool {
         self.sessions.contains_key(username)
     }
@@ -203,6 +360,66 @@ impl Manager {
     }
 }
 
+/// Send one line to the control script and read its answer.
+async fn exchange(mut stream: UnixStream, line: &str) -> Result<()> {
+    let reply = timeout(Duration::from_secs(5), async {
+        stream.write_all(format!("{}\n", line).as_bytes()).await?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply).await?;
+        Ok::<_, anyhow::Error>(reply)
+    })
+    .await
+    .map_err(|_| anyhow!("irssi did not answer"))??;
+    match reply.trim_end() {
+        "ok" => Ok(()),
+        "" => Err(anyhow!("irssi closed the control connection")),
+        other => Err(anyhow!("irssi: {}", other.strip_prefix("error ").unwrap_or(other))),
+    }
+}
+
+/// dtach socket of the user's irssi. Per-user so users don't collide;
+/// /tmp is fine — it lives inside the container.
+pub fn dtach_socket(username: &str) -> String {
+    format!("/tmp/irc-{}.sock", username)
+}
+
+/// Remove `sock` if it exists but nothing is listening on it.
+fn remove_stale_socket(sock: &str, username: &str) {
+    if std::fs::metadata(sock).is_ok() && std::os::unix::net::UnixStream::connect(sock).is_err() {
+        if let Err(e) = std::fs::remove_file(sock) {
+            warn!("failed to remove stale dtach socket {}: {}", sock, e);
+        } else {
+            info!("removed stale dtach socket {} for {}", sock, username);
+        }
+    }
+}
+
+/// A session directory as an absolute path, the client's home.
+fn home_dir(user_dir: &Path) -> String {
+    let abs_user_dir = std::fs::canonicalize(user_dir).unwrap_or_else(|_| user_dir.to_path_buf());
+    abs_user_dir.to_str().unwrap_or("/tmp").to_owned()
+}
+
+/// Environment for a new client: irssi's control script reads both,
+/// WeeChat and senpai configs refer to the password.
+fn soju_env(soju: Option<&SojuLogin>) -> Vec<(&'static str, String)> {
+    match soju {
+        Some(login) => vec![
+            (crate::client::PASSWORD_ENV, login.password.clone()),
+            ("IRSSI_V5_SOJU_CHATNETS", login.chatnets.join(",")),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// (Re)write the control script into irssi's autorun directory so every
+/// session runs the version this binary speaks to.
+fn install_control_script(home: &Path) -> Result<()> {
+    let dir = home.join("scripts").join("autorun");
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    std::fs::write(dir.join(CONTROL_SCRIPT_NAME), CONTROL_SCRIPT).context("write irssi control script")
+}
+
 async fn wait_for_port(port: u16, max_wait: Duration) -> Result<()> {
     let addr = format!("127.0.0.1:{}", port);
     let deadline = timeout(max_wait, async {
@@ -215,4 +432,34 @@ async fn wait_for_port(port: u16, max_wait: Duration) -> Result<()> {
     });
 
     deadline.await.map_err(|_| anyhow!("port {} not ready after {:?}", port, max_wait))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn test_set_soju_password() {
+        let dir = std::env::temp_dir().join(format!("irssi-v5-session-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let manager = Manager::new(7100, false);
+
+        // Nothing listening: the next irssi gets it from the environment
+        assert!(!manager.set_soju_password(&dir, "newpw").await.unwrap());
+
+        let listener = UnixListener::bind(dir.join(CONTROL_SOCKET)).unwrap();
+        let irssi = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut line = String::new();
+            BufReader::new(read).read_line(&mut line).await.unwrap();
+            write.write_all(b"ok\n").await.unwrap();
+            line
+        });
+        assert!(manager.set_soju_password(&dir, "newpw").await.unwrap());
+        assert_eq!(irssi.await.unwrap(), ":soju-password\tnewpw\n");
+        assert!(manager.set_soju_password(&dir, "two words").await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
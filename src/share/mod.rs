@@ -0,0 +1,128 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::Serialize;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a guest may do with a shared terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Watch only — every guest frame is dropped
+    Read,
+    /// Watch and type — guest keystrokes are forwarded to the owner's terminal
+    Write,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" | "read-only" => Ok(Scope::Read),
+            "write" | "read-write" => Ok(Scope::Write),
+            other => Err(anyhow!("unknown share scope '{}' (expected read or write)", other)),
+        }
+    }
+}
+
+pub fn new_share_id() -> String {
+    let bytes: [u8; 12] = rand::thread_rng().gen();
+    hex::encode(bytes)
+}
+
+pub fn new_secret() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    hex::encode(bytes)
+}
+
+/// Token format: `<share id>.<expires_at ms>.<hex HMAC-SHA256 of the first two>`.
+/// The signature stops guessing and tampering with the expiry; revocation
+/// is still checked against the shares table.
+pub fn sign(secret: &str, share_id: &str, expires_at: i64) -> String {
+    sign_in("", secret, share_id, expires_at)
+}
+
+/// Check a token's signature and return (share id, expires_at).
+/// Expiry is left to the caller.
+pub fn verify(secret: &str, token: &str) -> Option<(String, i64)> {
+    verify_in("", secret, token)
+}
+
+/// MAC domain of account-deletion confirmations. Share ids never contain
+/// `:`, so no share token verifies as a deletion token or the reverse.
+const DELETION: &str = "delete:";
+
+/// An account-deletion confirmation, in the share token format but signed
+/// in its own domain.
+pub fn sign_deletion(secret: &str, id: &str, expires_at: i64) -> String {
+    sign_in(DELETION, secret, id, expires_at)
+}
+
+pub fn verify_deletion(secret: &str, token: &str) -> Option<(String, i64)> {
+    verify_in(DELETION, secret, token)
+}
+
+fn sign_in(domain: &str, secret: &str, id: &str, expires_at: i64) -> String {
+    let payload = format!("{}.{}", id, expires_at);
+    let sig = mac(secret, domain, &payload).finalize().into_bytes();
+    format!("{}.{}", payload, hex::encode(sig))
+}
+
+fn verify_in(domain: &str, secret: &str, token: &str) -> Option<(String, i64)> {
+    let (payload, sig) = token.rsplit_once('.')?;
+    let sig = hex::decode(sig).ok()?;
+    mac(secret, domain, payload).verify_slice(&sig).ok()?;
+    let (id, exp) = payload.split_once('.')?;
+    Some((id.to_string(), exp.parse().ok()?))
+}
+
+fn mac(secret: &str, domain: &str, payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(domain.as_bytes());
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let token = sign("secret", "abc123", 1_700_000_000_000);
+        assert_eq!(verify("secret", &token), Some(("abc123".to_string(), 1_700_000_000_000)));
+        assert_eq!(verify("other", &token), None);
+
+        // Extending the expiry invalidates the signature
+        let forged = token.replacen("1700000000000", "1800000000000", 1);
+        assert_eq!(verify("secret", &forged), None);
+        assert_eq!(verify("secret", "garbage"), None);
+
+        // Deletion tokens are signed in their own domain
+        let deletion = sign_deletion("secret", "delete-616c696365", 1_700_000_000_000);
+        assert_eq!(verify_deletion("secret", &deletion), Some(("delete-616c696365".to_string(), 1_700_000_000_000)));
+        assert_eq!(verify("secret", &deletion), None);
+        assert_eq!(verify_deletion("secret", &token), None);
+    }
+}
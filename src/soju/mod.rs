@@ -1,86 +1,137 @@
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use dashmap::DashMap;
 use rand::Rng;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::client::Client;
+use crate::crypto::{self, Keyring};
+use crate::irssi;
+
+/// Per-user file holding the soju password, sealed with the app key.
+const PASSWORD_FILE: &str = "soju_password";
+
+/// One upstream network as reported by soju's `network status`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Network {
+    pub name: String,
+    pub addr: String,
+    /// e.g. "connected", "disabled", "disconnected"
+    pub status: String,
+}
+
+/// soju's view of a network's upstream SASL, from `sasl status`.
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaslStatus {
+    /// "PLAIN", "EXTERNAL", or None when SASL is off
+    pub mechanism: Option<String>,
+    pub username: Option<String>,
+    /// "authenticated", "unauthenticated" or "disconnected"
+    pub upstream: String,
+    /// Account the upstream network logged us in as
+    pub account: Option<String>,
+}
+
+/// Fingerprints of a network's client certificate (hex), as printed by
+/// soju's `certfp` commands.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Fingerprints {
+    pub sha1: String,
+    pub sha256: String,
+}
+
+/// Key types soju's `certfp generate -key-type` accepts.
+pub const CERT_KEY_TYPES: [&str; 3] = ["rsa", "ecdsa", "ed25519"];
 
 pub struct Manager {
-    socket_path: PathBuf,
     sessions_dir: PathBuf,
     soju_addr: String,
     irc_addr: String,
     irc_network_name: String,
+    keys: Arc<Keyring>,
+    /// Held while a soju password is read and pushed to soju, or changed,
+    /// so a rotation and a login cannot leave soju and the file apart
+    passwords: tokio::sync::Mutex<()>,
     /// Tracks users provisioned in this process run (avoids redundant sojuctl calls)
     provisioned: Arc<DashMap<String, ()>>,
 }
 
 impl Manager {
     pub fn new(
-        socket_path: PathBuf,
         sessions_dir: PathBuf,
         soju_addr: String,
         irc_addr: String,
         irc_network_name: String,
+        keys: Arc<Keyring>,
     ) -> Arc<Self> {
         Arc::new(Self {
-            socket_path,
             sessions_dir,
             soju_addr,
             irc_addr,
             irc_network_name,
+            keys,
+            passwords: tokio::sync::Mutex::new(()),
             provisioned: Arc::new(DashMap::new()),
         })
     }
 
-    /// Ensure a soju account and irssi config exist for this user.
+    /// Ensure a soju account exists for this user and the config of their
+    /// `client` carries the current soju address and credentials.
     /// Idempotent — safe to call on every login.
     ///
-    /// The password is stored in <user_dir>/soju_password so that if soju's
-    /// database is wiped (e.g. the soju stack is redeployed) but the app
-    /// volume is intact, we can re-create the soju user with the same password
-    /// the irssi config already has. Without this the passwords diverge and
-    /// SASL auth breaks.
-    pub async fn ensure_user(&self, username: &str) -> Result<()> {
+    /// The password is stored (sealed) in <user_dir>/soju_password so that
+    /// if soju's database is wiped (e.g. the soju stack is redeployed) but
+    /// the app volume is intact, we can re-create the soju user with the
+    /// same password a running irssi already has. Without this the
+    /// passwords diverge and SASL auth breaks.
+    ///
+    /// `admin` mirrors the app's admin role onto soju's own admin flag.
+    ///
+    /// Returns true when soju had no such user and it was created, i.e.
+    /// settings kept only in soju (upstream SASL) need pushing again.
+    pub async fn ensure_user(&self, username: &str, admin: bool, client: Client) -> Result<bool> {
         if self.provisioned.contains_key(username) {
-            return Ok(());
+            return Ok(false);
         }
 
         let user_dir = self.sessions_dir.join(username);
-        let config_path = user_dir.join("config");
-        let password_path = user_dir.join("soju_password");
+        let _guard = self.passwords.lock().await;
 
-        let password = if password_path.exists() {
-            // Password file exists — read it and (re-)provision soju in case
-            // its DB was wiped. Sojuctl calls are idempotent so this is safe.
-            tokio::fs::read_to_string(&password_path)
-                .await
-                .context("failed to read soju_password")?
-                .trim()
-                .to_string()
-        } else {
-            // First time — generate a fresh password and write it out.
-            tokio::fs::create_dir_all(&user_dir)
-                .await
-                .context("failed to create user dir")?;
-            let pw = random_password();
-            tokio::fs::write(&password_path, &pw)
-                .await
-                .context("failed to write soju_password")?;
-            pw
+        // An existing password (re-)provisions soju in case its DB was
+        // wiped. Sojuctl calls are idempotent so this is safe.
+        let password = match self.read_password(username).await? {
+            Some(pw) => pw,
+            None => {
+                // First time — generate a fresh password and write it out.
+                tokio::fs::create_dir_all(&user_dir)
+                    .await
+                    .context("failed to create user dir")?;
+                let pw = random_password();
+                self.write_password(username, &pw).await?;
+                pw
+            }
         };
 
+        let admin_flag = format!("-admin={}", admin);
+
         // (Re-)create soju user with the stored password
         let result = self
             .sojuctl(&[
                 "user", "create",
                 "-username", username,
                 "-password", &password,
+                &admin_flag,
             ])
             .await;
 
+        let created = result.is_ok();
         if let Err(e) = result {
             let msg = e.to_string();
             if msg.contains("already exists") {
@@ -90,6 +141,7 @@ impl Manager {
                     "user", "update",
                     username,
                     "-password", &password,
+                    &admin_flag,
                 ])
                 .await
                 .context("soju user update failed")?;
@@ -98,72 +150,217 @@ impl Manager {
             }
         }
 
-        // Add upstream IRC network — idempotent, ignore "already exists"
+        drop(_guard);
+
+        self.create_network(username, &self.irc_network_name, &self.irc_addr).await?;
+
+        // Bring the managed parts of the client config up to date, keeping
+        // the user's own settings. A config they broke is left for them.
+        match self.sync_client_config(username, client).await {
+            Ok(true) => info!("Updated {} config for {}", client.as_str(), username),
+            Ok(false) => {}
+            Err(e) => warn!("{} config for {} not updated: {:#}", client.as_str(), username, e),
+        }
+
+        info!("Provisioned soju user: {}", username);
+        self.provisioned.insert(username.to_string(), ());
+        Ok(created)
+    }
+
+    /// Point `client`'s config at soju (see `Client::sync_config`). Returns
+    /// whether it changed.
+    pub async fn sync_client_config(&self, username: &str, client: Client) -> Result<bool> {
+        let networks = match client {
+            Client::Weechat => self.list_networks(username).await?.into_iter().map(|n| n.name).collect(),
+            Client::Irssi | Client::Senpai => Vec::new(),
+        };
+        client.sync_config(&self.user_dir(username), &self.managed(username), &networks).await
+    }
+
+    fn managed<'a>(&'a self, username: &'a str) -> irssi::Managed<'a> {
+        let (soju_host, soju_port) = split_addr(&self.soju_addr);
+        irssi::Managed {
+            username,
+            network: &self.irc_network_name,
+            soju_host,
+            soju_port,
+        }
+    }
+
+    /// Add an upstream network — idempotent, "already exists" is fine.
+    async fn create_network(&self, username: &str, name: &str, addr: &str) -> Result<()> {
         let result = self
             .sojuctl(&[
                 "user", "run",
                 username,
                 "network", "create",
-                "-name", &self.irc_network_name,
-                "-addr", &self.irc_addr,
+                "-name", name,
+                "-addr", addr,
                 "-nick", username,
             ])
             .await;
 
-        if let Err(e) = result {
-            if !e.to_string().contains("already exists") {
-                return Err(e).context("soju network create failed");
+        match result {
+            Err(e) if !e.to_string().contains("already exists") => {
+                Err(e).with_context(|| format!("soju network create {} failed", name))
             }
+            _ => Ok(()),
         }
+    }
 
-        // Write irssi config only if it doesn't already exist
-        if !config_path.exists() {
-            let (soju_host, soju_port) = split_addr(&self.soju_addr);
-            let irssi_conf = format!(
-r#"chatnets = {{
-  {network_name} = {{
-    type = "IRC";
-    sasl_mechanism = "PLAIN";
-    sasl_username = "{username}/{network_name}";
-    sasl_password = "{password}";
-  }};
-}};
-
-servers = ({{
-  address = "{soju_host}";
-  port = {soju_port};
-  use_ssl = no;
-  chatnet = "{network_name}";
-  autoconnect = yes;
-}});
-
-settings = {{
-  core = {{
-    real_name = "{username}";
-    user_name = "{username}";
-    nick = "{username}";
-  }};
-  "fe-text" = {{ term_charset = "UTF-8"; }};
-  "fe-common/core" = {{ term_charset = "UTF-8"; }};
-}};
-"#,
-                network_name = self.irc_network_name,
-            );
-
-            tokio::fs::write(&config_path, irssi_conf)
-                .await
-                .context("failed to write irssi config")?;
+    /// Give a provisioned user another upstream network, plus a chatnet
+    /// and server for it in their irssi config (or a server in WeeChat's;
+    /// senpai finds networks itself). Returns whether the config changed (a
+    /// running irssi needs `/reload` and `/connect`).
+    pub async fn add_network(&self, username: &str, name: &str, addr: &str, client: Client) -> Result<bool> {
+        self.create_network(username, name, addr).await?;
+        if client != Client::Irssi {
+            return self.sync_client_config(username, client).await;
         }
 
-        info!("Provisioned soju user: {}", username);
-        self.provisioned.insert(username.to_string(), ());
-        Ok(())
+        let managed = self.managed(username);
+        let path = self.user_dir(username).join("config");
+        let mut conf = irssi::read_config(&path).await?;
+        if !irssi::add_network(&mut conf, &managed, name) {
+            return Ok(false);
+        }
+        irssi::write_config(&path, &conf).await?;
+        Ok(true)
     }
 
     pub fn user_dir(&self, username: &str) -> PathBuf {
         self.sessions_dir.join(username)
     }
 
+    /// The user's soju password, decrypted, or None before the first
+    /// `ensure_user`. A plaintext file left by an older version is sealed
+    /// on the way.
+    pub async fn read_password(&self, username: &str) -> Result<Option<String>> {
+        let path = self.user_dir(username).join(PASSWORD_FILE);
+        let text = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => text.trim().to_string(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("failed to read soju_password"),
+        };
+        if crypto::is_sealed(&text) {
+            let password = self
+                .keys
+                .open(&password_context(username), &text)
+                .context("failed to decrypt soju_password")?;
+            return Ok(Some(password));
+        }
+        self.write_password(username, &text).await?;
+        info!("Sealed plaintext soju_password of {}", username);
+        Ok(Some(text))
+    }
+
+    /// Replace a provisioned user's soju password with a fresh one: soju
+    /// first, then the sealed file — if writing the file fails soju gets
+    /// the old password back. Returns (old, new), so a caller that cannot
+    /// get the new one to irssi can `set_password` the old one again.
+    pub async fn rotate_password(&self, username: &str) -> Result<(String, String)> {
+        let _guard = self.passwords.lock().await;
+        let old = self
+            .read_password(username)
+            .await?
+            .with_context(|| format!("{} has no soju password yet", username))?;
+        let new = random_password();
+        self.update_password(username, &new).await?;
+        if let Err(e) = self.write_password(username, &new).await {
+            if let Err(undo) = self.update_password(username, &old).await {
+                warn!("restoring soju password of {} failed: {:#}", username, undo);
+            }
+            return Err(e);
+        }
+        // Drops a password irssi may have saved into the config
+        if let Err(e) = irssi::sync_config(&self.user_dir(username).join("config"), &self.managed(username)).await {
+            warn!("irssi config for {} not updated: {:#}", username, e);
+        }
+        Ok((old, new))
+    }
+
+    /// Set the user's soju password in soju and in the sealed file.
+    pub async fn set_password(&self, username: &str, password: &str) -> Result<()> {
+        let _guard = self.passwords.lock().await;
+        self.update_password(username, password).await?;
+        self.write_password(username, password).await
+    }
+
+    async fn update_password(&self, username: &str, password: &str) -> Result<()> {
+        self.sojuctl(&["user", "update", username, "-password", password])
+            .await
+            .context("soju user update -password failed")
+    }
+
+    /// Users with a soju password file, i.e. provisioned at some point.
+    pub async fn password_users(&self) -> Result<Vec<String>> {
+        let mut users = Vec::new();
+        let mut dirs = match tokio::fs::read_dir(&self.sessions_dir).await {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(users),
+            Err(e) => return Err(e).context("failed to list sessions dir"),
+        };
+        while let Some(entry) = dirs.next_entry().await? {
+            if entry.path().join(PASSWORD_FILE).is_file() {
+                users.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        users.sort();
+        Ok(users)
+    }
+
+    async fn write_password(&self, username: &str, password: &str) -> Result<()> {
+        let sealed = self.keys.seal(&password_context(username), password);
+        write_private(&self.user_dir(username).join(PASSWORD_FILE), &sealed)
+            .await
+            .context("failed to write soju_password")
+    }
+
+    /// Re-seal every soju password file with the current key, sealing
+    /// plaintext ones too. Returns how many were rewritten, and the users
+    /// whose file could not be read.
+    pub async fn reencrypt(&self) -> Result<(usize, Vec<String>)> {
+        let mut rewritten = 0;
+        let mut failed = Vec::new();
+        let _guard = self.passwords.lock().await;
+        for username in self.password_users().await? {
+            let path = self.user_dir(&username).join(PASSWORD_FILE);
+            let Ok(text) = tokio::fs::read_to_string(&path).await else { continue };
+            let text = text.trim();
+            let context = password_context(&username);
+            let sealed = if crypto::is_sealed(text) {
+                self.keys.reseal(&context, text)
+            } else {
+                Ok(Some(self.keys.seal(&context, text)))
+            };
+            match sealed {
+                Ok(None) => {}
+                Ok(Some(sealed)) => {
+                    write_private(&path, &sealed).await.with_context(|| format!("rewrite {}", path.display()))?;
+                    rewritten += 1;
+                }
+                Err(e) => {
+                    warn!("soju_password of {} not re-encrypted: {:#}", username, e);
+                    failed.push(username);
+                }
+            }
+        }
+        Ok((rewritten, failed))
+    }
+
+    /// Make the next `ensure_user` run in full again, e.g. to write the
+    /// config of a client the user just switched to.
+    pub fn forget(&self, username: &str) {
+        self.provisioned.remove(username);
+    }
+
+    /// Set soju's admin flag for an existing user.
+    pub async fn set_admin(&self, username: &str, admin: bool) -> Result<()> {
+        self.sojuctl(&["user", "update", username, &format!("-admin={}", admin)])
+            .await
+            .context("soju user update -admin failed")
+    }
+
     pub async fn delete_user(&self, username: &str) -> Result<()> {
         self.provisioned.remove(username);
 
@@ -180,7 +377,89 @@ settings = {{
         Ok(())
     }
 
+    /// Send a NOTICE from the bouncer to every connected soju user
+    /// (soju's admin `server notice` command).
+    pub async fn server_notice(&self, message: &str) -> Result<()> {
+        self.sojuctl(&["server", "notice", message])
+            .await
+            .context("soju server notice failed")
+    }
+
+    /// List the user's upstream networks.
+    pub async fn list_networks(&self, username: &str) -> Result<Vec<Network>> {
+        let out = self
+            .sojuctl_output(&["user", "run", username, "network", "status"])
+            .await
+            .context("soju network status failed")?;
+        Ok(out.lines().filter_map(parse_network_status).collect())
+    }
+
+    pub async fn set_network_enabled(&self, username: &str, network: &str, enabled: bool) -> Result<()> {
+        self.sojuctl(&[
+            "user", "run", username,
+            "network", "update", network,
+            &format!("-enabled={}", enabled),
+        ])
+        .await
+        .with_context(|| format!("soju network update {} failed", network))
+    }
+
+    /// Log in to `network` upstream with SASL PLAIN. soju reconnects the
+    /// network to apply it.
+    pub async fn set_sasl_plain(&self, username: &str, network: &str, sasl_username: &str, password: &str) -> Result<()> {
+        self.sojuctl(&[
+            "user", "run", username,
+            "sasl", "set-plain", "-network", network, sasl_username, password,
+        ])
+        .await
+        .with_context(|| format!("soju sasl set-plain for {} failed", network))
+    }
+
+    pub async fn reset_sasl(&self, username: &str, network: &str) -> Result<()> {
+        self.sojuctl(&["user", "run", username, "sasl", "reset", "-network", network])
+            .await
+            .with_context(|| format!("soju sasl reset for {} failed", network))
+    }
+
+    pub async fn sasl_status(&self, username: &str, network: &str) -> Result<SaslStatus> {
+        let out = self
+            .sojuctl_output(&["user", "run", username, "sasl", "status", "-network", network])
+            .await
+            .with_context(|| format!("soju sasl status for {} failed", network))?;
+        Ok(parse_sasl_status(&out))
+    }
+
+    /// Have soju generate a client certificate for `network` and switch
+    /// its upstream login to SASL EXTERNAL. soju reconnects the network.
+    pub async fn generate_certfp(&self, username: &str, network: &str, key_type: Option<&str>) -> Result<Fingerprints> {
+        let mut args = vec!["user", "run", username, "certfp", "generate", "-network", network];
+        if let Some(key_type) = key_type {
+            args.extend(["-key-type", key_type]);
+        }
+        let out = self
+            .sojuctl_output(&args)
+            .await
+            .with_context(|| format!("soju certfp generate for {} failed", network))?;
+        parse_fingerprints(&out).ok_or_else(|| anyhow::anyhow!("soju certfp generate: no fingerprint in output"))
+    }
+
+    /// Fingerprints of the network's current certificate, if it has one.
+    pub async fn certfp_fingerprint(&self, username: &str, network: &str) -> Result<Option<Fingerprints>> {
+        match self
+            .sojuctl_output(&["user", "run", username, "certfp", "fingerprint", "-network", network])
+            .await
+        {
+            Ok(out) => Ok(parse_fingerprints(&out)),
+            Err(e) if e.to_string().contains("not enabled") => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("soju certfp fingerprint for {} failed", network)),
+        }
+    }
+
     async fn sojuctl(&self, args: &[&str]) -> Result<()> {
+        self.sojuctl_output(args).await.map(|_| ())
+    }
+
+    async fn sojuctl_output(&self, args: &[&str]) -> Result<String> {
         let output = Command::new("sojuctl")
             .arg("-config")
             .arg("/etc/soju/config")
@@ -193,18 +472,213 @@ settings = {{
             let stderr = String::from_utf8_lossy(&output.stderr);
             return Err(anyhow::anyhow!("sojuctl error: {}", stderr.trim()));
         }
-        Ok(())
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     }
 }
 
+fn password_context(username: &str) -> String {
+    format!("soju:{}", username)
+}
+
+/// Replace `path` atomically with a mode 0600 file.
+async fn write_private(path: &Path, text: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600)).await?;
+    file.write_all(text.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
 fn random_password() -> String {
     let bytes: Vec<u8> = (0..16).map(|_| rand::thread_rng().gen()).collect();
     hex::encode(bytes)
 }
 
+/// Parse one line of `network status` output:
+///   "libera (ircs://irc.libera.chat) [connected as bob, current]: 3 channels"
+///   "irc.example.org [disabled]"
+fn parse_network_status(line: &str) -> Option<Network> {
+    let line = line.trim();
+    let (head, rest) = line.split_once(" [")?;
+    let (status, _) = rest.split_once(']')?;
+    let (name, addr) = match head.split_once(" (") {
+        Some((name, addr)) => (name, addr.strip_suffix(')')?),
+        None => (head, head),
+    };
+    Some(Network {
+        name: name.to_string(),
+        addr: addr.to_string(),
+        status: status.split(',').next().unwrap_or(status).trim().to_string(),
+    })
+}
+
+/// Parse `sasl status` output:
+///   SASL PLAIN enabled with username "alice"
+///   Authenticated on upstream network with account "alice"
+fn parse_sasl_status(out: &str) -> SaslStatus {
+    let quoted = |line: &str| line.split_once('"').map(|(_, r)| r.trim_end_matches('"').to_string());
+    let mut status = SaslStatus { upstream: "unknown".into(), ..Default::default() };
+    for line in out.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("SASL PLAIN enabled") {
+            status.mechanism = Some("PLAIN".into());
+            status.username = quoted(rest);
+        } else if line.starts_with("SASL EXTERNAL") {
+            status.mechanism = Some("EXTERNAL".into());
+        } else if line.starts_with("Authenticated on upstream") {
+            status.upstream = "authenticated".into();
+            status.account = quoted(line);
+        } else if line.starts_with("Unauthenticated on upstream") {
+            status.upstream = "unauthenticated".into();
+        } else if line.starts_with("Disconnected from upstream") {
+            status.upstream = "disconnected".into();
+        }
+    }
+    status
+}
+
+/// Parse `certfp` output:
+///   SHA-1 fingerprint: 3f78…
+///   SHA-256 fingerprint: 9b2e…
+fn parse_fingerprints(out: &str) -> Option<Fingerprints> {
+    let find = |label: &str| {
+        out.lines()
+            .find_map(|l| l.trim().strip_prefix(label))
+            .map(|f| f.trim().to_ascii_lowercase())
+            .filter(|f| !f.is_empty() && f.chars().all(|c| c.is_ascii_hexdigit()))
+    };
+    Some(Fingerprints { sha1: find("SHA-1 fingerprint:")?, sha256: find("SHA-256 fingerprint:")? })
+}
+
+/// An `irc://` / `ircs://` link, e.g. `ircs://irc.libera.chat:6697/#rust`.
+#[derive(Debug, PartialEq)]
+pub struct IrcUrl {
+    pub host: String,
+    /// With the `#` (or other prefix); `#` is added when the link omits it
+    pub channel: Option<String>,
+}
+
+pub fn parse_irc_url(url: &str) -> Result<IrcUrl> {
+    let rest = ["irc://", "ircs://"]
+        .iter()
+        .find_map(|scheme| url.strip_prefix(scheme))
+        .ok_or_else(|| anyhow::anyhow!("not an irc:// or ircs:// link"))?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    // Drop any user info and port
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    let host = host.split(':').next().unwrap_or(host).to_ascii_lowercase();
+    if host.is_empty() || !host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-') {
+        anyhow::bail!("invalid host in irc link");
+    }
+
+    let path = percent_decode(path.split('?').next().unwrap_or(""));
+    let mut parts = path.split(',');
+    let target = parts.next().unwrap_or("");
+    if parts.any(|flag| flag == "isnick" || flag == "isserver") {
+        anyhow::bail!("only channel links are supported");
+    }
+    let channel = match target {
+        "" => None,
+        t if t.starts_with(['#', '&', '+', '!']) => Some(t.to_string()),
+        t => Some(format!("#{}", t)),
+    };
+    if channel.as_ref().is_some_and(|c| c.len() > 50 || c.chars().any(|c| c == ' ' || c.is_control())) {
+        anyhow::bail!("invalid channel in irc link");
+    }
+    Ok(IrcUrl { host, channel })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 fn split_addr(addr: &str) -> (&str, &str) {
     match addr.rsplit_once(':') {
         Some((host, port)) => (host, port),
         None => (addr, "6667"),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_network_status() {
+        assert_eq!(
+            parse_network_status("libera (ircs://irc.libera.chat) [connected as bob, current]: 3 channels"),
+            Some(Network {
+                name: "libera".into(),
+                addr: "ircs://irc.libera.chat".into(),
+                status: "connected as bob".into(),
+            })
+        );
+        assert_eq!(
+            parse_network_status("irc.example.org [disabled]"),
+            Some(Network {
+                name: "irc.example.org".into(),
+                addr: "irc.example.org".into(),
+                status: "disabled".into(),
+            })
+        );
+        assert_eq!(parse_network_status("No network configured, add one with \"network create\"."), None);
+    }
+
+    #[test]
+    fn test_parse_sasl_status() {
+        let out = "SASL PLAIN enabled with username \"alice\"\nAuthenticated on upstream network with account \"alice\"\n";
+        assert_eq!(
+            parse_sasl_status(out),
+            SaslStatus {
+                mechanism: Some("PLAIN".into()),
+                username: Some("alice".into()),
+                upstream: "authenticated".into(),
+                account: Some("alice".into()),
+            }
+        );
+        let off = parse_sasl_status("SASL is disabled\nUnauthenticated on upstream network\n");
+        assert_eq!(off.mechanism, None);
+        assert_eq!(off.upstream, "unauthenticated");
+    }
+
+    #[test]
+    fn test_parse_fingerprints() {
+        let out = "certificate generated\nSHA-1 fingerprint: 0A1B\nSHA-256 fingerprint: 2c3d\n";
+        assert_eq!(
+            parse_fingerprints(out),
+            Some(Fingerprints { sha1: "0a1b".into(), sha256: "2c3d".into() })
+        );
+        assert_eq!(parse_fingerprints("SASL EXTERNAL is not enabled"), None);
+    }
+
+    #[test]
+    fn test_parse_irc_url() {
+        let url = |host: &str, channel: Option<&str>| IrcUrl { host: host.into(), channel: channel.map(Into::into) };
+        assert_eq!(parse_irc_url("ircs://irc.Libera.Chat:6697/#rust").unwrap(), url("irc.libera.chat", Some("#rust")));
+        assert_eq!(parse_irc_url("irc://irc.libera.chat/%23rust").unwrap(), url("irc.libera.chat", Some("#rust")));
+        assert_eq!(parse_irc_url("irc://irc.libera.chat/rust,needkey").unwrap(), url("irc.libera.chat", Some("#rust")));
+        assert_eq!(parse_irc_url("irc://irc.libera.chat").unwrap(), url("irc.libera.chat", None));
+        assert!(parse_irc_url("irc://irc.libera.chat/bob,isnick").is_err());
+        assert!(parse_irc_url("https://example.com/#x").is_err());
+        assert!(parse_irc_url("irc://ev il/#x").is_err());
+        assert!(parse_irc_url("irc://h/%23a%0Ab").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::{STANDARD as BASE64, STANDARD_NO_PAD as BASE64_NO_PAD};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Key types sshd may offer and users may upload.
+const ALGORITHMS: [&str; 7] = [
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "ssh-rsa",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+/// How long to wait before restarting an sshd that exited.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// A public key in OpenSSH `authorized_keys` form.
+#[derive(Debug, PartialEq)]
+pub struct PublicKey {
+    pub algorithm: String,
+    /// Base64 key blob
+    pub key: String,
+    pub comment: Option<String>,
+    /// `SHA256:…`, as printed by `ssh-keygen -l`
+    pub fingerprint: String,
+}
+
+/// Parse one `<type> <base64> [comment]` line, as in `id_ed25519.pub`.
+pub fn parse_public_key(line: &str) -> Result<PublicKey> {
+    let mut parts = line.trim().splitn(3, char::is_whitespace);
+    let algorithm = parts.next().unwrap_or_default();
+    if !ALGORITHMS.contains(&algorithm) {
+        bail!("not an SSH public key (expected e.g. ssh-ed25519 AAAA…, without options)");
+    }
+    let key = parts.next().unwrap_or_default().trim();
+    let blob = BASE64.decode(key).map_err(|_| anyhow!("key data is not base64"))?;
+    // The blob starts with the algorithm name as an SSH string
+    let named = blob
+        .get(..4)
+        .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+        .and_then(|len| blob.get(4..4 + len));
+    if named != Some(algorithm.as_bytes()) {
+        bail!("key data does not match its type {}", algorithm);
+    }
+    let comment = parts.next().map(str::trim).filter(|c| !c.is_empty()).map(String::from);
+    Ok(PublicKey {
+        algorithm: algorithm.to_string(),
+        key: key.to_string(),
+        comment,
+        fingerprint: format!("SHA256:{}", BASE64_NO_PAD.encode(Sha256::digest(&blob))),
+    })
+}
+
+/// Quote `s` for the shell sshd runs a forced command with.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// The `authorized_keys` line sshd gets for a known key: no forwarding, and
+/// whatever the client asks to run, it attaches to the owner's irssi.
+pub fn authorized_keys_line(exe: &Path, socket: &Path, username: &str, key_id: i64, key: &PublicKey) -> String {
+    let command = [
+        shell_quote(&exe.to_string_lossy()),
+        "ssh-attach".to_string(),
+        shell_quote(&socket.to_string_lossy()),
+        shell_quote(username),
+        key_id.to_string(),
+    ]
+    .join(" ");
+    format!(
+        "restrict,pty,command=\"{}\" {} {}",
+        command.replace('\\', r"\\").replace('"', r#"\""#),
+        key.algorithm,
+        key.key
+    )
+}
+
+/// Name of the account this process runs as — the only one sshd lets in.
+fn login_name() -> Result<String> {
+    // SAFETY: getpwuid returns null or a pointer to static storage, which is
+    // copied out before any other passwd lookup could overwrite it
+    unsafe {
+        let pw = libc::getpwuid(libc::getuid());
+        if pw.is_null() {
+            bail!("no passwd entry for uid {}", libc::getuid());
+        }
+        Ok(std::ffi::CStr::from_ptr((*pw).pw_name).to_string_lossy().into_owned())
+    }
+}
+
+/// Whether process `pid` is this program started, directly or not, by the
+/// sshd with pid `sshd`: `ssh-keys` or `ssh-attach`. The gateway socket
+/// serves only those. Terminal sessions run as the same user, so no file or
+/// argument they could read would do; they cannot make sshd their ancestor.
+pub fn run_by_sshd(pid: i32, sshd: u32) -> bool {
+    if sshd == 0 {
+        return false;
+    }
+    let (Ok(exe), Ok(own)) = (std::fs::read_link(format!("/proc/{}/exe", pid)), std::env::current_exe()) else {
+        return false;
+    };
+    if exe != own {
+        return false;
+    }
+    let mut pid = pid as u32;
+    // sshd listener → per-connection sshd (and its session child) → command
+    for _ in 0..16 {
+        match parent(pid) {
+            Some(p) if p == sshd => return true,
+            Some(p) if p > 1 => pid = p,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Parent pid, from `/proc/<pid>/stat` (`pid (comm) state ppid …`).
+fn parent(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// An OpenSSH sshd run and restarted by irssi-v5. It only accepts public
+/// keys, asks `irssi-v5 ssh-keys` (and so the Store) which user a key
+/// belongs to, and forces every login into `irssi-v5 ssh-attach`. Both
+/// commands talk to the server over the gateway socket, which checks that
+/// they descend from this sshd (`run_by_sshd`).
+///
+/// This is the system's sshd rather than an SSH server inside irssi-v5: no
+/// SSH server library is among the crates the build can use, and OpenSSH
+/// keeps the protocol and crypto out of this code.
+pub struct Sshd {
+    pub listen: String,
+    pub sshd: PathBuf,
+    pub host_key: PathBuf,
+    /// Where the generated sshd_config goes
+    pub dir: PathBuf,
+    /// Gateway socket (`control::serve_local`) serving the `/api/ssh` routes
+    pub socket: PathBuf,
+    /// Pid of the running sshd, 0 while there is none
+    pub pid: Arc<AtomicU32>,
+}
+
+impl Sshd {
+    /// Run sshd until it cannot be started; restarts it when it exits.
+    pub async fn run(self) -> Result<()> {
+        let config = self.prepare().await?;
+        loop {
+            let mut child = Command::new(&self.sshd)
+                .args(["-D", "-e", "-f"])
+                .arg(&config)
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("failed to spawn {}", self.sshd.display()))?;
+            self.pid.store(child.id().unwrap_or(0), Ordering::Relaxed);
+            info!("SSH gateway on {}", self.listen);
+            let status = child.wait().await;
+            self.pid.store(0, Ordering::Relaxed);
+            let status = status?;
+            warn!("sshd exited with {}, restarting in {:?}", status, RESTART_DELAY);
+            tokio::time::sleep(RESTART_DELAY).await;
+        }
+    }
+
+    /// Generate the host key if missing and write sshd_config.
+    async fn prepare(&self) -> Result<PathBuf> {
+        let exe = std::env::current_exe().context("cannot find own executable")?;
+        for path in [&exe, &self.socket, &self.host_key] {
+            if path.to_string_lossy().contains(char::is_whitespace) {
+                bail!("SSH gateway: {} must not contain whitespace", path.display());
+            }
+        }
+        if !self.host_key.exists() {
+            let status = Command::new("ssh-keygen")
+                .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+                .arg(&self.host_key)
+                .status()
+                .await
+                .context("failed to run ssh-keygen")?;
+            if !status.success() {
+                bail!("ssh-keygen exited with {}", status);
+            }
+            info!("generated SSH host key {}", self.host_key.display());
+        }
+
+        let login = login_name()?;
+        let config = format!(
+            "ListenAddress {listen}\n\
+             HostKey {host_key}\n\
+             PidFile none\n\
+             AllowUsers {login}\n\
+             AuthenticationMethods publickey\n\
+             PasswordAuthentication no\n\
+             KbdInteractiveAuthentication no\n\
+             AuthorizedKeysFile none\n\
+             AuthorizedKeysCommand {exe} ssh-keys {socket} %t %k\n\
+             AuthorizedKeysCommandUser {login}\n\
+             DisableForwarding yes\n\
+             PermitUserRC no\n\
+             PrintMotd no\n\
+             PrintLastLog no\n",
+            listen = self.listen,
+            host_key = self.host_key.display(),
+            exe = exe.display(),
+            socket = self.socket.display(),
+        );
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::set_permissions(&self.dir, std::fs::Permissions::from_mode(0o700)).await?;
+        let path = self.dir.join("sshd_config");
+        let mut file = tokio::fs::File::create(&path).await.with_context(|| format!("create {}", path.display()))?;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600)).await?;
+        file.write_all(config.as_bytes()).await.with_context(|| format!("write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+// ── Commands sshd runs ───────────────────────────────────────────────────────
+
+/// `irssi-v5 ssh-keys <socket> <type> <key>`, sshd's
+/// AuthorizedKeysCommand: print an `authorized_keys` line if the key belongs
+/// to a user, nothing otherwise.
+pub async fn authorized_keys(socket: &Path, algorithm: &str, key: &str) -> Result<()> {
+    let key = parse_public_key(&format!("{} {}", algorithm, key))?;
+    if let Some((username, id)) = crate::control::ssh_key_owner(socket, &key.fingerprint).await? {
+        let exe = std::env::current_exe()?;
+        println!("{}", authorized_keys_line(&exe, socket, &username, id, &key));
+    }
+    Ok(())
+}
+
+/// `irssi-v5 ssh-attach <socket> <user> <key id>`, the forced command of
+/// every SSH login: start the user's irssi if needed and attach to it.
+pub async fn attach(socket: &Path, username: &str, key_id: i64) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: isatty has no memory-safety preconditions
+    if unsafe { libc::isatty(0) } != 1 {
+        bail!("irssi needs a terminal — connect with `ssh -t`");
+    }
+    let sock = crate::control::ssh_session(socket, username, key_id).await?;
+    // -r winch: have irssi redraw for this terminal's size
+    let err = std::process::Command::new("dtach").args(["-a", &sock, "-r", "winch"]).exec();
+    Err(anyhow!("cannot run dtach: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_public_key() {
+        // ssh-keygen -l: 256 SHA256:XgNTRop9xUBl5P84Xaqq5waWaGHs8TEr3Bkwu0xwYKo
+        let line = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ4RXSTIVeT3eKo2bGY9Ukkh+7SBvx/8hZyVXmJN8IwR alice@laptop";
+        let key = parse_public_key(line).unwrap();
+        assert_eq!(key.algorithm, "ssh-ed25519");
+        assert_eq!(key.comment.as_deref(), Some("alice@laptop"));
+        assert_eq!(key.fingerprint, "SHA256:XgNTRop9xUBl5P84Xaqq5waWaGHs8TEr3Bkwu0xwYKo");
+        assert_eq!(parse_public_key(&format!("{} {}", key.algorithm, key.key)).unwrap().comment, None);
+
+        assert!(parse_public_key("command=\"x\" ssh-ed25519 AAAA").is_err());
+        assert!(parse_public_key("ssh-rsa AAAAC3NzaC1lZDI1NTE5AAAAIJ4RXSTIVeT3eKo2bGY9Ukkh+7SBvx/8hZyVXmJN8IwR").is_err());
+        assert!(parse_public_key("ssh-ed25519 !!!").is_err());
+        assert!(parse_public_key("").is_err());
+
+        let line = authorized_keys_line(Path::new("/app/irssi-v5"), Path::new("/data/ssh/gateway.sock"), "o'neil", 7, &key);
+        assert_eq!(
+            line,
+            "restrict,pty,command=\"'/app/irssi-v5' ssh-attach '/data/ssh/gateway.sock' 'o'\\\\''neil' 7\" \
+             ssh-ed25519 \
+             AAAAC3NzaC1lZDI1NTE5AAAAIJ4RXSTIVeT3eKo2bGY9Ukkh+7SBvx/8hZyVXmJN8IwR"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use serde::Serialize;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{QueryBuilder, Sqlite};
 use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -12,6 +13,143 @@ pub struct UserRecord {
     pub is_admin: i64, // SQLite stores bools as 0/1
 }
 
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct RoleGrant {
+    pub username: String,
+    pub role: String,
+    pub granted_by: String,
+    pub granted_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct LoginRecord {
+    pub id: i64,
+    pub ts: i64,
+    /// "auth" for an authenticated browser visit, "terminal" for a /terminal/ws connection
+    pub kind: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Repeated authentications from the same browser within this window are
+/// folded into one login record.
+const LOGIN_DEDUP_MS: i64 = 30 * 60 * 1000;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Suspension {
+    pub username: String,
+    pub reason: String,
+    pub suspended_by: String,
+    pub suspended_at: i64,
+    pub expires_at: Option<i64>,
+    /// JSON array of soju network names disabled by this suspension
+    pub disabled_networks: String,
+}
+
+impl Suspension {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= now_ms())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ShareRecord {
+    pub id: String,
+    pub owner: String,
+    pub scope: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+impl ShareRecord {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > now_ms()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Announcement {
+    pub id: i64,
+    pub ts: i64,
+    pub author: String,
+    pub message: String,
+    /// NULL when IRC delivery was not requested
+    pub irc_delivered: Option<bool>,
+    pub irc_error: Option<String>,
+}
+
+/// Upstream SASL PLAIN credentials a user saved for one soju network.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct NetworkSasl {
+    pub network: String,
+    pub sasl_username: String,
+    /// `crypto::Keyring::seal` output, context `sasl_context(user, network)`
+    #[serde(skip)]
+    pub sealed_password: String,
+    pub updated_at: i64,
+}
+
+/// Encryption context binding a sealed SASL password to its row.
+pub fn sasl_context(username: &str, network: &str) -> String {
+    format!("sasl:{}:{}", username, network)
+}
+
+/// Settings whose value is sealed, with context `setting_context(key)`.
+pub const SEALED_SETTINGS: [&str; 1] = ["share_secret"];
+
+pub fn setting_context(key: &str) -> String {
+    format!("setting:{}", key)
+}
+
+/// A named password a user made for one IRC client, for logging in to
+/// soju through the IRC proxy. Only a digest of the password is kept.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AppPassword {
+    pub id: i64,
+    pub name: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub last_used_ip: Option<String>,
+}
+
+/// A public key a user uploaded for the SSH gateway. A key belongs to at
+/// most one user, so the fingerprint alone says who is logging in.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SshKey {
+    pub id: i64,
+    pub name: String,
+    pub algorithm: String,
+    pub public_key: String,
+    pub fingerprint: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+/// One row of the append-only audit log.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub ts: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub params: String, // JSON object
+    pub outcome: String,
+}
+
+/// Filters for `Store::list_audit`. All fields are optional and AND-ed.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
 #[derive(Clone)]
 pub struct Store {
     pool: SqlitePool,
@@ -41,6 +179,97 @@ impl Store {
                 value TEXT NOT NULL
             );
             INSERT OR IGNORE INTO settings (key, value) VALUES ('max_users', '50');
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id      INTEGER PRIMARY KEY AUTOINCREMENT,
+                ts      INTEGER NOT NULL,
+                actor   TEXT NOT NULL,
+                action  TEXT NOT NULL,
+                target  TEXT,
+                params  TEXT NOT NULL DEFAULT '{}',
+                outcome TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS audit_log_ts ON audit_log (ts);
+            CREATE TABLE IF NOT EXISTS user_roles (
+                username   TEXT NOT NULL,
+                role       TEXT NOT NULL,
+                granted_by TEXT NOT NULL,
+                granted_at INTEGER NOT NULL,
+                PRIMARY KEY (username, role)
+            );
+            CREATE TABLE IF NOT EXISTS logins (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                username   TEXT NOT NULL,
+                ts         INTEGER NOT NULL,
+                kind       TEXT NOT NULL,
+                ip         TEXT,
+                user_agent TEXT
+            );
+            CREATE INDEX IF NOT EXISTS logins_user_ts ON logins (username, ts);
+            CREATE TABLE IF NOT EXISTS shares (
+                id         TEXT PRIMARY KEY,
+                owner      TEXT NOT NULL,
+                scope      TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL,
+                revoked_at INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS announcements (
+                id            INTEGER PRIMARY KEY AUTOINCREMENT,
+                ts            INTEGER NOT NULL,
+                author        TEXT NOT NULL,
+                message       TEXT NOT NULL,
+                irc_delivered INTEGER,
+                irc_error     TEXT
+            );
+            CREATE TABLE IF NOT EXISTS suspensions (
+                username          TEXT PRIMARY KEY,
+                reason            TEXT NOT NULL,
+                suspended_by      TEXT NOT NULL,
+                suspended_at      INTEGER NOT NULL,
+                expires_at        INTEGER,
+                disabled_networks TEXT NOT NULL DEFAULT '[]'
+            );
+            CREATE TABLE IF NOT EXISTS network_sasl (
+                username        TEXT NOT NULL,
+                network         TEXT NOT NULL,
+                sasl_username   TEXT NOT NULL,
+                sealed_password TEXT NOT NULL,
+                updated_at      INTEGER NOT NULL,
+                PRIMARY KEY (username, network)
+            );
+            CREATE TABLE IF NOT EXISTS app_passwords (
+                id            INTEGER PRIMARY KEY AUTOINCREMENT,
+                username      TEXT NOT NULL,
+                name          TEXT NOT NULL,
+                password_hash TEXT NOT NULL UNIQUE,
+                created_at    INTEGER NOT NULL,
+                last_used_at  INTEGER,
+                last_used_ip  TEXT,
+                UNIQUE (username, name)
+            );
+            CREATE TABLE IF NOT EXISTS user_preferences (
+                username   TEXT PRIMARY KEY,
+                client     TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS ssh_keys (
+                id           INTEGER PRIMARY KEY AUTOINCREMENT,
+                username     TEXT NOT NULL,
+                name         TEXT NOT NULL,
+                algorithm    TEXT NOT NULL,
+                public_key   TEXT NOT NULL,
+                fingerprint  TEXT NOT NULL UNIQUE,
+                created_at   INTEGER NOT NULL,
+                last_used_at INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS soju_password_rotations (
+                username   TEXT PRIMARY KEY,
+                rotated_at INTEGER NOT NULL
+            );
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END;
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END;
             "#,
         )
         .execute(&pool)
@@ -49,43 +278,142 @@ impl Store {
         Ok(Store { pool })
     }
 
-    pub async fn touch(&self, username: &str, is_admin: bool) -> Result<()> {
+    pub async fn touch(&self, username: &str) -> Result<()> {
         let now = now_ms();
-        let admin = is_admin as i64;
         sqlx::query(
             r#"
-            INSERT INTO users (username, first_seen, last_seen, is_admin)
-            VALUES (?1, ?2, ?2, ?3)
+            INSERT INTO users (username, first_seen, last_seen)
+            VALUES (?1, ?2, ?2)
             ON CONFLICT(username) DO UPDATE SET
-                last_seen = excluded.last_seen,
-                is_admin  = excluded.is_admin
+                last_seen = excluded.last_seen
             "#,
         )
         .bind(username)
         .bind(now)
-        .bind(admin)
         .execute(&self.pool)
         .await?;
         Ok(())
     }
 
     pub async fn list_users(&self) -> Result<Vec<UserRecord>> {
+        // is_admin is derived from user_roles; the legacy column is no longer written.
         let rows = sqlx::query_as::<_, UserRecord>(
-            "SELECT username, first_seen, last_seen, is_admin FROM users ORDER BY last_seen DESC",
+            r#"
+            SELECT u.username, u.first_seen, u.last_seen,
+                   EXISTS(SELECT 1 FROM user_roles r
+                          WHERE r.username = u.username AND r.role = 'admin') AS is_admin
+            FROM users u ORDER BY u.last_seen DESC
+            "#,
         )
         .fetch_all(&self.pool)
         .await?;
         Ok(rows)
     }
 
+    pub async fn get_user(&self, username: &str) -> Result<Option<UserRecord>> {
+        let row = sqlx::query_as::<_, UserRecord>(
+            r#"
+            SELECT u.username, u.first_seen, u.last_seen,
+                   EXISTS(SELECT 1 FROM user_roles r
+                          WHERE r.username = u.username AND r.role = 'admin') AS is_admin
+            FROM users u WHERE u.username = ?
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
     pub async fn delete_user(&self, username: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
         sqlx::query("DELETE FROM users WHERE username = ?")
             .bind(username)
-            .execute(&self.pool)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM user_roles WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM logins WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM shares WHERE owner = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM network_sasl WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM soju_password_rotations WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM app_passwords WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
             .await?;
+        sqlx::query("DELETE FROM ssh_keys WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM user_preferences WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM suspensions WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
         Ok(())
     }
 
+    // ── Roles ─────────────────────────────────────────────────────────────────
+
+    pub async fn roles_for(&self, username: &str) -> Result<Vec<String>> {
+        let roles = sqlx::query_scalar("SELECT role FROM user_roles WHERE username = ?")
+            .bind(username)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(roles)
+    }
+
+    pub async fn list_role_grants(&self) -> Result<Vec<RoleGrant>> {
+        let rows = sqlx::query_as::<_, RoleGrant>(
+            "SELECT username, role, granted_by, granted_at FROM user_roles ORDER BY username, role",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Grant a role. Returns false if the user already had it.
+    pub async fn grant_role(&self, username: &str, role: &str, granted_by: &str) -> Result<bool> {
+        let res = sqlx::query(
+            "INSERT OR IGNORE INTO user_roles (username, role, granted_by, granted_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(username)
+        .bind(role)
+        .bind(granted_by)
+        .bind(now_ms())
+        .execute(&self.pool)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Revoke a role. Returns false if the user did not have it.
+    pub async fn revoke_role(&self, username: &str, role: &str) -> Result<bool> {
+        let res = sqlx::query("DELETE FROM user_roles WHERE username = ? AND role = ?")
+            .bind(username)
+            .bind(role)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
     pub async fn user_count(&self) -> Result<i64> {
         let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
             .fetch_one(&self.pool)
@@ -93,6 +421,18 @@ impl Store {
         Ok(count)
     }
 
+    /// Write a consistent copy of the database to `dest` (which must not
+    /// exist). Safe while the server is running.
+    pub async fn snapshot(&self, dest: &std::path::Path) -> Result<()> {
+        let dest = dest.to_str().ok_or_else(|| anyhow::anyhow!("non-UTF-8 snapshot path"))?;
+        sqlx::query("VACUUM INTO ?").bind(dest).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn sqlite_version(&self) -> Result<String> {
+        Ok(sqlx::query_scalar("SELECT sqlite_version()").fetch_one(&self.pool).await?)
+    }
+
     pub async fn get_setting(&self, key: &str, default: &str) -> String {
         sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
             .bind(key)
@@ -111,11 +451,716 @@ impl Store {
             .await?;
         Ok(())
     }
+
+    // ── Login history ─────────────────────────────────────────────────────────
+
+    /// Record a login. "auth" records are deduplicated per browser
+    /// (ip + user agent) within LOGIN_DEDUP_MS since every API call
+    /// authenticates; "terminal" records are always written.
+    pub async fn record_login(
+        &self,
+        username: &str,
+        kind: &str,
+        ip: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> Result<()> {
+        let now = now_ms();
+        sqlx::query(
+            r#"
+            INSERT INTO logins (username, ts, kind, ip, user_agent)
+            SELECT ?1, ?2, ?3, ?4, ?5
+            WHERE ?3 != 'auth' OR NOT EXISTS (
+                SELECT 1 FROM logins
+                WHERE username = ?1 AND kind = 'auth' AND ip IS ?4 AND user_agent IS ?5 AND ts > ?6
+            )
+            "#,
+        )
+        .bind(username)
+        .bind(now)
+        .bind(kind)
+        .bind(ip)
+        .bind(user_agent)
+        .bind(now - LOGIN_DEDUP_MS)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_logins(&self, username: &str, limit: i64) -> Result<Vec<LoginRecord>> {
+        let rows = sqlx::query_as::<_, LoginRecord>(
+            "SELECT id, ts, kind, ip, user_agent FROM logins WHERE username = ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(username)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    // ── Terminal shares ───────────────────────────────────────────────────────
+
+    pub async fn create_share(&self, id: &str, owner: &str, scope: &str, expires_at: i64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO shares (id, owner, scope, created_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(owner)
+        .bind(scope)
+        .bind(now_ms())
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_share(&self, id: &str) -> Result<Option<ShareRecord>> {
+        let row = sqlx::query_as::<_, ShareRecord>(
+            "SELECT id, owner, scope, created_at, expires_at, revoked_at FROM shares WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    /// Unrevoked, unexpired shares of one owner, newest first.
+    pub async fn list_active_shares(&self, owner: &str) -> Result<Vec<ShareRecord>> {
+        let rows = sqlx::query_as::<_, ShareRecord>(
+            "SELECT id, owner, scope, created_at, expires_at, revoked_at FROM shares \
+             WHERE owner = ? AND revoked_at IS NULL AND expires_at > ? ORDER BY created_at DESC",
+        )
+        .bind(owner)
+        .bind(now_ms())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Revoke one of the owner's shares. Returns false if there was no such
+    /// active share.
+    pub async fn revoke_share(&self, id: &str, owner: &str) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE shares SET revoked_at = ? WHERE id = ? AND owner = ? AND revoked_at IS NULL",
+        )
+        .bind(now_ms())
+        .bind(id)
+        .bind(owner)
+        .execute(&self.pool)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    // ── Upstream SASL ─────────────────────────────────────────────────────────
+
+    pub async fn set_network_sasl(
+        &self,
+        username: &str,
+        network: &str,
+        sasl_username: &str,
+        sealed_password: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO network_sasl (username, network, sasl_username, sealed_password, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(username, network) DO UPDATE SET
+                sasl_username = excluded.sasl_username,
+                sealed_password = excluded.sealed_password,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(username)
+        .bind(network)
+        .bind(sasl_username)
+        .bind(sealed_password)
+        .bind(now_ms())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_network_sasl(&self, username: &str, network: &str) -> Result<Option<NetworkSasl>> {
+        let row = sqlx::query_as::<_, NetworkSasl>(
+            "SELECT network, sasl_username, sealed_password, updated_at FROM network_sasl \
+             WHERE username = ? AND network = ?",
+        )
+        .bind(username)
+        .bind(network)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn list_network_sasl(&self, username: &str) -> Result<Vec<NetworkSasl>> {
+        let rows = sqlx::query_as::<_, NetworkSasl>(
+            "SELECT network, sasl_username, sealed_password, updated_at FROM network_sasl \
+             WHERE username = ? ORDER BY network",
+        )
+        .bind(username)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn delete_network_sasl(&self, username: &str, network: &str) -> Result<bool> {
+        let res = sqlx::query("DELETE FROM network_sasl WHERE username = ? AND network = ?")
+            .bind(username)
+            .bind(network)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Run every sealed value (SASL passwords, `SEALED_SETTINGS`) through
+    /// `reseal(context, sealed)` and store what it returns, in one
+    /// transaction; `Ok(None)` leaves a value as it is. Returns how many
+    /// changed and the contexts that failed.
+    pub async fn reseal_secrets<F>(&self, reseal: F) -> Result<(usize, Vec<String>)>
+    where
+        F: Fn(&str, &str) -> Result<Option<String>>,
+    {
+        let mut tx = self.pool.begin().await?;
+        let mut changed = 0;
+        let mut failed = Vec::new();
+
+        let rows: Vec<(String, String, String)> =
+            sqlx::query_as("SELECT username, network, sealed_password FROM network_sasl")
+                .fetch_all(&mut *tx)
+                .await?;
+        for (username, network, sealed) in rows {
+            let context = sasl_context(&username, &network);
+            match reseal(&context, &sealed) {
+                Ok(None) => {}
+                Ok(Some(new)) => {
+                    sqlx::query("UPDATE network_sasl SET sealed_password = ? WHERE username = ? AND network = ?")
+                        .bind(new)
+                        .bind(&username)
+                        .bind(&network)
+                        .execute(&mut *tx)
+                        .await?;
+                    changed += 1;
+                }
+                Err(_) => failed.push(context),
+            }
+        }
+
+        for key in SEALED_SETTINGS {
+            let sealed: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+                .bind(key)
+                .fetch_optional(&mut *tx)
+                .await?;
+            let Some(sealed) = sealed else { continue };
+            let context = setting_context(key);
+            match reseal(&context, &sealed) {
+                Ok(None) => {}
+                Ok(Some(new)) => {
+                    sqlx::query("UPDATE settings SET value = ? WHERE key = ?")
+                        .bind(new)
+                        .bind(key)
+                        .execute(&mut *tx)
+                        .await?;
+                    changed += 1;
+                }
+                Err(_) => failed.push(context),
+            }
+        }
+
+        tx.commit().await?;
+        Ok((changed, failed))
+    }
+
+    // ── App passwords ─────────────────────────────────────────────────────────
+
+    /// Returns the new password's id, or None if the user already has one
+    /// with that name.
+    pub async fn create_app_password(&self, username: &str, name: &str, password_hash: &str) -> Result<Option<i64>> {
+        let res = sqlx::query(
+            "INSERT OR IGNORE INTO app_passwords (username, name, password_hash, created_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(username)
+        .bind(name)
+        .bind(password_hash)
+        .bind(now_ms())
+        .execute(&self.pool)
+        .await?;
+        Ok((res.rows_affected() > 0).then(|| res.last_insert_rowid()))
+    }
+
+    pub async fn list_app_passwords(&self, username: &str) -> Result<Vec<AppPassword>> {
+        let rows = sqlx::query_as::<_, AppPassword>(
+            "SELECT id, name, created_at, last_used_at, last_used_ip FROM app_passwords \
+             WHERE username = ? ORDER BY created_at",
+        )
+        .bind(username)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn delete_app_password(&self, username: &str, id: i64) -> Result<bool> {
+        let res = sqlx::query("DELETE FROM app_passwords WHERE username = ? AND id = ?")
+            .bind(username)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Id of the user's app password with this digest.
+    pub async fn find_app_password(&self, username: &str, password_hash: &str) -> Result<Option<i64>> {
+        let id = sqlx::query_scalar("SELECT id FROM app_passwords WHERE username = ? AND password_hash = ?")
+            .bind(username)
+            .bind(password_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn touch_app_password(&self, id: i64, ip: &str) -> Result<()> {
+        sqlx::query("UPDATE app_passwords SET last_used_at = ?, last_used_ip = ? WHERE id = ?")
+            .bind(now_ms())
+            .bind(ip)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // ── Preferences ───────────────────────────────────────────────────────────
+
+    /// The terminal client the user picked, None if they never did.
+    pub async fn client_preference(&self, username: &str) -> Result<Option<String>> {
+        let client = sqlx::query_scalar("SELECT client FROM user_preferences WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(client)
+    }
+
+    pub async fn set_client_preference(&self, username: &str, client: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO user_preferences (username, client, updated_at) VALUES (?, ?, ?) \
+             ON CONFLICT(username) DO UPDATE SET client = excluded.client, updated_at = excluded.updated_at",
+        )
+        .bind(username)
+        .bind(client)
+        .bind(now_ms())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // ── SSH keys ──────────────────────────────────────────────────────────────
+
+    /// Returns the new key's id, or None if the key is already registered
+    /// (to this user or another).
+    pub async fn add_ssh_key(
+        &self,
+        username: &str,
+        name: &str,
+        algorithm: &str,
+        public_key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<i64>> {
+        let res = sqlx::query(
+            "INSERT OR IGNORE INTO ssh_keys (username, name, algorithm, public_key, fingerprint, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(username)
+        .bind(name)
+        .bind(algorithm)
+        .bind(public_key)
+        .bind(fingerprint)
+        .bind(now_ms())
+        .execute(&self.pool)
+        .await?;
+        Ok((res.rows_affected() > 0).then(|| res.last_insert_rowid()))
+    }
+
+    pub async fn list_ssh_keys(&self, username: &str) -> Result<Vec<SshKey>> {
+        let rows = sqlx::query_as::<_, SshKey>(
+            "SELECT id, name, algorithm, public_key, fingerprint, created_at, last_used_at FROM ssh_keys \
+             WHERE username = ? ORDER BY created_at",
+        )
+        .bind(username)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn delete_ssh_key(&self, username: &str, id: i64) -> Result<bool> {
+        let res = sqlx::query("DELETE FROM ssh_keys WHERE username = ? AND id = ?")
+            .bind(username)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Owner and id of the key with this fingerprint.
+    pub async fn find_ssh_key(&self, fingerprint: &str) -> Result<Option<(String, i64)>> {
+        let row = sqlx::query_as("SELECT username, id FROM ssh_keys WHERE fingerprint = ?")
+            .bind(fingerprint)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    pub async fn touch_ssh_key(&self, id: i64) -> Result<()> {
+        sqlx::query("UPDATE ssh_keys SET last_used_at = ? WHERE id = ?")
+            .bind(now_ms())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // ── soju password rotation ────────────────────────────────────────────────
+
+    /// Note that the user's soju password was (re)set at `at` (ms).
+    pub async fn record_soju_rotation(&self, username: &str, at: i64) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO soju_password_rotations (username, rotated_at) VALUES (?, ?)")
+            .bind(username)
+            .bind(at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// When each user's soju password was last rotated (ms), by username.
+    pub async fn soju_rotations(&self) -> Result<std::collections::HashMap<String, i64>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as("SELECT username, rotated_at FROM soju_password_rotations")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    // ── Announcements ─────────────────────────────────────────────────────────
+
+    pub async fn create_announcement(&self, author: &str, message: &str) -> Result<Announcement> {
+        let row = sqlx::query_as::<_, Announcement>(
+            "INSERT INTO announcements (ts, author, message) VALUES (?, ?, ?) \
+             RETURNING id, ts, author, message, irc_delivered, irc_error",
+        )
+        .bind(now_ms())
+        .bind(author)
+        .bind(message)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn set_announcement_irc(&self, id: i64, delivered: bool, error: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE announcements SET irc_delivered = ?, irc_error = ? WHERE id = ?")
+            .bind(delivered)
+            .bind(error)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_announcements(&self, limit: i64) -> Result<Vec<Announcement>> {
+        let rows = sqlx::query_as::<_, Announcement>(
+            "SELECT id, ts, author, message, irc_delivered, irc_error FROM announcements \
+             ORDER BY id DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    // ── Suspensions ──────────────────────────────────────────────────────────
+
+    pub async fn suspension(&self, username: &str) -> Result<Option<Suspension>> {
+        let row = sqlx::query_as::<_, Suspension>(
+            "SELECT username, reason, suspended_by, suspended_at, expires_at, disabled_networks \
+             FROM suspensions WHERE username = ?",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn list_suspensions(&self) -> Result<Vec<Suspension>> {
+        let rows = sqlx::query_as::<_, Suspension>(
+            "SELECT username, reason, suspended_by, suspended_at, expires_at, disabled_networks \
+             FROM suspensions ORDER BY suspended_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Create or replace a user's suspension.
+    pub async fn suspend(
+        &self,
+        username: &str,
+        reason: &str,
+        suspended_by: &str,
+        expires_at: Option<i64>,
+        disabled_networks: &[String],
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO suspensions \
+             (username, reason, suspended_by, suspended_at, expires_at, disabled_networks) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(username)
+        .bind(reason)
+        .bind(suspended_by)
+        .bind(now_ms())
+        .bind(expires_at)
+        .bind(serde_json::to_string(disabled_networks)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Lift a suspension, returning the removed row if there was one.
+    pub async fn unsuspend(&self, username: &str) -> Result<Option<Suspension>> {
+        let existing = self.suspension(username).await?;
+        sqlx::query("DELETE FROM suspensions WHERE username = ?")
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(existing)
+    }
+
+    // ── Audit log ─────────────────────────────────────────────────────────────
+
+    pub async fn audit(
+        &self,
+        actor: &str,
+        action: &str,
+        target: Option<&str>,
+        params: &serde_json::Value,
+        outcome: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO audit_log (ts, actor, action, target, params, outcome) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(now_ms())
+        .bind(actor)
+        .bind(action)
+        .bind(target)
+        .bind(params.to_string())
+        .bind(outcome)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Return one page of audit entries (newest first) plus the total number
+    /// of entries matching the filter.
+    pub async fn list_audit(&self, filter: &AuditFilter) -> Result<(Vec<AuditRecord>, i64)> {
+        fn push_filters<'a>(qb: &mut QueryBuilder<'a, Sqlite>, f: &'a AuditFilter) {
+            qb.push(" WHERE 1 = 1");
+            if let Some(ref actor) = f.actor {
+                qb.push(" AND actor = ").push_bind(actor);
+            }
+            if let Some(ref action) = f.action {
+                // "user" matches "user.kick", "user.delete", …; compared as a
+                // prefix rather than with LIKE so `%` and `_` match literally
+                let prefix = format!("{}.", action);
+                qb.push(" AND (action = ").push_bind(action);
+                qb.push(" OR substr(action, 1, ").push_bind(prefix.chars().count() as i64);
+                qb.push(") = ").push_bind(prefix);
+                qb.push(")");
+            }
+            if let Some(ref target) = f.target {
+                qb.push(" AND target = ").push_bind(target);
+            }
+            if let Some(since) = f.since {
+                qb.push(" AND ts >= ").push_bind(since);
+            }
+            if let Some(until) = f.until {
+                qb.push(" AND ts < ").push_bind(until);
+            }
+        }
+
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM audit_log");
+        push_filters(&mut count, filter);
+        let total: i64 = count.build_query_scalar().fetch_one(&self.pool).await?;
+
+        let mut page = QueryBuilder::new(
+            "SELECT id, ts, actor, action, target, params, outcome FROM audit_log",
+        );
+        push_filters(&mut page, filter);
+        page.push(" ORDER BY id DESC LIMIT ")
+            .push_bind(filter.limit)
+            .push(" OFFSET ")
+            .push_bind(filter.offset);
+        let rows = page.build_query_as::<AuditRecord>().fetch_all(&self.pool).await?;
+
+        Ok((rows, total))
+    }
 }
 
-fn now_ms() -> i64 {
+pub fn now_ms() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         .as_millis() as i64
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("irssi-v5-{}-{}.db", name, std::process::id()))
+    }
+
+    async fn temp_store(name: &str) -> Store {
+        let path = temp_path(name);
+        let _ = std::fs::remove_file(&path);
+        Store::new(path.to_str().unwrap()).await.unwrap()
+    }
+
+    /// Close the store and remove its database files.
+    async fn remove_store(store: Store, name: &str) {
+        store.pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", temp_path(name).display(), suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_filter_and_append_only() {
+        let store = temp_store("audit").await;
+        let params = serde_json::json!({});
+        store.audit("alice", "user.kick", Some("bob"), &params, "ok").await.unwrap();
+        store.audit("alice", "user.delete", Some("carol"), &params, "denied").await.unwrap();
+        store.audit("bob", "session.clear", Some("bob"), &params, "ok").await.unwrap();
+
+        let filter = AuditFilter { action: Some("user".into()), limit: 10, ..Default::default() };
+        let (rows, total) = store.list_audit(&filter).await.unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(rows[0].action, "user.delete");
+        for wildcard in ["use_", "%"] {
+            let filter = AuditFilter { action: Some(wildcard.into()), limit: 10, ..Default::default() };
+            assert_eq!(store.list_audit(&filter).await.unwrap().1, 0);
+        }
+
+        let filter = AuditFilter { actor: Some("alice".into()), limit: 1, offset: 1, ..Default::default() };
+        let (rows, total) = store.list_audit(&filter).await.unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].target.as_deref(), Some("bob"));
+
+        assert!(sqlx::query("DELETE FROM audit_log").execute(&store.pool).await.is_err());
+        assert!(sqlx::query("UPDATE audit_log SET outcome = 'ok'").execute(&store.pool).await.is_err());
+        remove_store(store, "audit").await;
+    }
+
+    #[tokio::test]
+    async fn test_record_login_dedup() {
+        let store = temp_store("logins").await;
+        let (ip, ua) = (Some("203.0.113.7"), Some("Firefox"));
+        store.record_login("alice", "auth", ip, ua).await.unwrap();
+        store.record_login("alice", "auth", ip, ua).await.unwrap();
+        store.record_login("alice", "auth", Some("198.51.100.1"), ua).await.unwrap();
+        store.record_login("alice", "terminal", ip, ua).await.unwrap();
+        store.record_login("alice", "terminal", ip, ua).await.unwrap();
+
+        let logins = store.list_logins("alice", 10).await.unwrap();
+        assert_eq!(logins.iter().filter(|l| l.kind == "auth").count(), 2);
+        assert_eq!(logins.iter().filter(|l| l.kind == "terminal").count(), 2);
+        remove_store(store, "logins").await;
+    }
+
+    #[tokio::test]
+    async fn test_suspensions() {
+        let store = temp_store("suspensions").await;
+        store.touch("alice").await.unwrap();
+        store.suspend("alice", "spam", "admin", Some(now_ms() - 1), &["libera".into()]).await.unwrap();
+        store.suspend("bob", "abuse", "admin", None, &[]).await.unwrap();
+
+        let alice = store.suspension("alice").await.unwrap().unwrap();
+        assert!(alice.is_expired());
+        assert_eq!(alice.disabled_networks, r#"["libera"]"#);
+        assert!(!store.suspension("bob").await.unwrap().unwrap().is_expired());
+        assert_eq!(store.list_suspensions().await.unwrap().len(), 2);
+
+        // Suspending again replaces the old suspension
+        store.suspend("alice", "spam again", "admin", None, &[]).await.unwrap();
+        let alice = store.suspension("alice").await.unwrap().unwrap();
+        assert_eq!((alice.reason.as_str(), alice.expires_at), ("spam again", None));
+        assert_eq!(store.list_suspensions().await.unwrap().len(), 2);
+
+        assert_eq!(store.unsuspend("bob").await.unwrap().unwrap().reason, "abuse");
+        assert!(store.unsuspend("bob").await.unwrap().is_none());
+
+        // A new account with a deleted user's name starts unsuspended
+        store.delete_user("alice").await.unwrap();
+        assert!(store.suspension("alice").await.unwrap().is_none());
+        assert!(store.list_suspensions().await.unwrap().is_empty());
+        remove_store(store, "suspensions").await;
+    }
+
+    #[tokio::test]
+    async fn test_shares() {
+        let store = temp_store("shares").await;
+        let hour = 3_600_000;
+        store.create_share("s1", "alice", "read", now_ms() + hour).await.unwrap();
+        store.create_share("s2", "alice", "write", now_ms() + 2 * hour).await.unwrap();
+        store.create_share("old", "alice", "read", now_ms() - 1).await.unwrap();
+        store.create_share("b1", "bob", "read", now_ms() + hour).await.unwrap();
+
+        let active: Vec<String> = store.list_active_shares("alice").await.unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(active.len(), 2);
+        assert!(active.contains(&"s1".to_string()) && active.contains(&"s2".to_string()));
+        let old = store.get_share("old").await.unwrap().unwrap();
+        assert!(!old.is_active(), "expired shares stay readable but inactive");
+
+        // Only the owner can revoke, and only once
+        assert!(!store.revoke_share("s1", "bob").await.unwrap());
+        assert!(store.revoke_share("s1", "alice").await.unwrap());
+        assert!(!store.revoke_share("s1", "alice").await.unwrap());
+        assert!(!store.get_share("s1").await.unwrap().unwrap().is_active());
+        assert_eq!(store.list_active_shares("alice").await.unwrap().len(), 1);
+        assert!(store.get_share("missing").await.unwrap().is_none());
+
+        store.delete_user("alice").await.unwrap();
+        assert!(store.get_share("s2").await.unwrap().is_none());
+        assert!(store.get_share("b1").await.unwrap().unwrap().is_active());
+        remove_store(store, "shares").await;
+    }
+
+    #[tokio::test]
+    async fn test_announcements() {
+        let store = temp_store("announcements").await;
+        let first = store.create_announcement("alice", "Restarting at 20:00").await.unwrap();
+        assert_eq!((first.author.as_str(), first.irc_delivered), ("alice", None));
+        let second = store.create_announcement("bob", "Back up").await.unwrap();
+        store.set_announcement_irc(first.id, false, Some("sojuctl: timeout")).await.unwrap();
+        store.set_announcement_irc(second.id, true, None).await.unwrap();
+
+        let list = store.list_announcements(10).await.unwrap();
+        assert_eq!(list.iter().map(|a| a.id).collect::<Vec<_>>(), [second.id, first.id]);
+        assert_eq!(list[0].irc_delivered, Some(true));
+        assert_eq!(list[1].irc_delivered, Some(false));
+        assert_eq!(list[1].irc_error.as_deref(), Some("sojuctl: timeout"));
+        assert_eq!(store.list_announcements(1).await.unwrap()[0].message, "Back up");
+        remove_store(store, "announcements").await;
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_settings_survive_restart() {
+        let store = temp_store("maintenance").await;
+        assert_eq!(store.get_setting("maintenance_enabled", "false").await, "false");
+        store.set_setting("maintenance_enabled", "true").await.unwrap();
+        store.set_setting("maintenance_message", "Upgrading soju").await.unwrap();
+        store.pool.close().await;
+
+        let store = Store::new(temp_path("maintenance").to_str().unwrap()).await.unwrap();
+        assert_eq!(store.get_setting("maintenance_enabled", "false").await, "true");
+        assert_eq!(store.get_setting("maintenance_message", "").await, "Upgrading soju");
+        store.set_setting("maintenance_enabled", "false").await.unwrap();
+        assert_eq!(store.get_setting("maintenance_enabled", "true").await, "false");
+        remove_store(store, "maintenance").await;
+    }
+}